@@ -0,0 +1,458 @@
+/// The ALTO export is built from the hOCR Tesseract writes for each page. The words, lines and
+/// blocks are taken over with their bounding boxes, which are converted from pixels to tenths of
+/// a millimetre using the resolution the page was rendered with. Each page becomes an ALTO 4
+/// file of its own, a METS file lists them in the order of the output PDF.
+use crate::error::Error;
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Name of the METS file next to the pages.
+const METS_FILE: &str = "mets.xml";
+
+/// Classes of the hOCR elements which are exported as text lines.
+const LINE_CLASSES: [&str; 4] = ["ocr_line", "ocr_caption", "ocr_header", "ocr_textfloat"];
+
+/// A page of the output.
+pub struct Page {
+    /// The hOCR file of the page, missing for pages taken over untouched.
+    pub hocr: Option<PathBuf>,
+    /// Resolution in DPI the hOCR coordinates refer to.
+    pub resolution: u64,
+    /// Width and height in points of a page without hOCR.
+    pub size: Option<(f64, f64)>,
+}
+
+/// Bounding box in pixels: left, top, right and bottom.
+type BBox = [u64; 4];
+
+/// A recognized word.
+struct Word {
+    bbox: BBox,
+    /// Confidence of Tesseract in percent.
+    confidence: Option<u64>,
+    text: String,
+}
+
+/// A line of text, exported as `TextLine`.
+struct Line {
+    bbox: BBox,
+    words: Vec<Word>,
+}
+
+/// A column or paragraph area of the page, exported as `TextBlock`.
+struct Block {
+    bbox: BBox,
+    lines: Vec<Line>,
+}
+
+/// The content of a hOCR page.
+struct Layout {
+    bbox: BBox,
+    blocks: Vec<Block>,
+}
+
+/// Writes the ALTO files of all pages and the METS file to the output. The output is a zip
+/// archive if its extension is `zip`, otherwise a directory which is created if needed.
+pub fn export(pages: &[Page], output: &Path) -> Result<(), Error> {
+    let mut files = Vec::with_capacity(pages.len() + 1);
+    for (n, page) in pages.iter().enumerate() {
+        let layout = match &page.hocr {
+            Some(x) => Some(parse(x)?),
+            None => None,
+        };
+        files.push((
+            page_file(n + 1),
+            alto(n + 1, layout, page.resolution, page.size),
+        ));
+    }
+    files.push((String::from(METS_FILE), mets(pages.len())));
+
+    let result = match output.extension().is_some_and(|x| x == "zip") {
+        true => write_zip(&files, output),
+        false => write_directory(&files, output),
+    };
+    result.map_err(|e| Error::io(format!("Couldn't write ALTO to {}", output.display()), e))
+}
+
+/// Returns the file name of the ALTO file of a page.
+fn page_file(number: usize) -> String {
+    format!("page_{:04}.xml", number)
+}
+
+/// Writes the files into the output directory.
+fn write_directory(files: &[(String, String)], output: &Path) -> io::Result<()> {
+    fs::create_dir_all(output)?;
+    for (name, content) in files {
+        fs::write(output.join(name), content)?;
+    }
+    Ok(())
+}
+
+/// Writes the files into a zip archive at the output path.
+fn write_zip(files: &[(String, String)], output: &Path) -> io::Result<()> {
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in files {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Reads the blocks, lines and words of a hOCR page.
+fn parse(path: &Path) -> Result<Layout, Error> {
+    let content = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(Error::io(
+                format!("Couldn't read hOCR file {}", path.display()),
+                e,
+            ))
+        }
+    };
+    let element = Regex::new(r"<(?:div|p|span)\s+class='([a-z_]+)'([^>]*)>").unwrap();
+    let title = Regex::new(r#"title=(?:'([^']*)'|"([^"]*)")"#).unwrap();
+    let bbox = Regex::new(r"bbox (\d+) (\d+) (\d+) (\d+)").unwrap();
+    let confidence = Regex::new(r"x_wconf (\d+)").unwrap();
+    let tag = Regex::new(r"<[^>]*>").unwrap();
+
+    let mut layout = None;
+    let mut blocks: Vec<Block> = Vec::new();
+    for x in element.captures_iter(&content) {
+        let properties = title
+            .captures(&x[2])
+            .and_then(|t| t.get(1).or_else(|| t.get(2)))
+            .map_or("", |t| t.as_str());
+        let bounds = match bbox.captures(properties) {
+            Some(b) => [&b[1], &b[2], &b[3], &b[4]].map(|v| v.parse().unwrap_or(0)),
+            None => continue,
+        };
+        let class = &x[1];
+        if class == "ocr_page" {
+            layout = Some(bounds);
+        } else if class == "ocr_carea" {
+            blocks.push(Block {
+                bbox: bounds,
+                lines: vec![],
+            });
+        } else if LINE_CLASSES.contains(&class) {
+            let line = Line {
+                bbox: bounds,
+                words: vec![],
+            };
+            match blocks.last_mut() {
+                Some(block) => block.lines.push(line),
+                None => blocks.push(Block {
+                    bbox: bounds,
+                    lines: vec![line],
+                }),
+            }
+        } else if class == "ocrx_word" {
+            let start = x.get(0).unwrap().end();
+            let end = content[start..]
+                .find("</span>")
+                .map_or(start, |e| start + e);
+            let text = decode(tag.replace_all(&content[start..end], "").trim());
+            let line = blocks.last_mut().and_then(|b| b.lines.last_mut());
+            if let (Some(line), false) = (line, text.is_empty()) {
+                line.words.push(Word {
+                    bbox: bounds,
+                    confidence: confidence
+                        .captures(properties)
+                        .and_then(|c| c[1].parse().ok()),
+                    text,
+                });
+            }
+        }
+    }
+    match layout {
+        Some(bbox) => Ok(Layout { bbox, blocks }),
+        None => Err(Error::parse(format!(
+            "Couldn't find the page in {}",
+            path.display()
+        ))),
+    }
+}
+
+/// Decodes the entities of the XML text content.
+fn decode(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Escapes a text for the use in an attribute value.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Converts a length in pixels at the given resolution into tenths of a millimetre.
+fn mm10(px: f64, resolution: f64) -> u64 {
+    (px * 254.0 / resolution).round() as u64
+}
+
+/// Returns the ALTO document of a page, the coordinates are given in tenths of a millimetre. A
+/// page without layout only states its size in points, if known.
+fn alto(
+    number: usize,
+    layout: Option<Layout>,
+    resolution: u64,
+    size: Option<(f64, f64)>,
+) -> String {
+    let scaled = |px: u64| mm10(px as f64, resolution as f64);
+    let position = |b: &BBox| {
+        format!(
+            "HPOS=\"{}\" VPOS=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\"",
+            scaled(b[0]),
+            scaled(b[1]),
+            scaled(b[2].saturating_sub(b[0])),
+            scaled(b[3].saturating_sub(b[1]))
+        )
+    };
+
+    let mut page = String::new();
+    match layout {
+        Some(layout) => {
+            let b = &layout.bbox;
+            page.push_str(&format!(
+                "    <Page ID=\"page_{}\" PHYSICAL_IMG_NR=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\">\n",
+                number,
+                number,
+                scaled(b[2].saturating_sub(b[0])),
+                scaled(b[3].saturating_sub(b[1]))
+            ));
+            page.push_str(&format!("      <PrintSpace {}>\n", position(b)));
+            let (mut n_block, mut n_line, mut n_word) = (0, 0, 0);
+            for block in layout.blocks {
+                let lines: Vec<Line> = block
+                    .lines
+                    .into_iter()
+                    .filter(|x| !x.words.is_empty())
+                    .collect();
+                if lines.is_empty() {
+                    continue;
+                }
+                n_block += 1;
+                page.push_str(&format!(
+                    "        <TextBlock ID=\"block_{}_{}\" {}>\n",
+                    number,
+                    n_block,
+                    position(&block.bbox)
+                ));
+                for line in lines {
+                    n_line += 1;
+                    page.push_str(&format!(
+                        "          <TextLine ID=\"line_{}_{}\" {}>\n",
+                        number,
+                        n_line,
+                        position(&line.bbox)
+                    ));
+                    for (i, word) in line.words.iter().enumerate() {
+                        n_word += 1;
+                        if i > 0 {
+                            page.push_str("            <SP/>\n");
+                        }
+                        let confidence = match word.confidence {
+                            Some(x) => format!(" WC=\"{:.2}\"", x.min(100) as f64 / 100.0),
+                            None => String::new(),
+                        };
+                        page.push_str(&format!(
+                            "            <String ID=\"string_{}_{}\" CONTENT=\"{}\" {}{}/>\n",
+                            number,
+                            n_word,
+                            escape(&word.text),
+                            position(&word.bbox),
+                            confidence
+                        ));
+                    }
+                    page.push_str("          </TextLine>\n");
+                }
+                page.push_str("        </TextBlock>\n");
+            }
+            page.push_str("      </PrintSpace>\n    </Page>\n");
+        }
+        None => {
+            let dimensions = match size {
+                // A point is 1/72 inch.
+                Some((width, height)) => format!(
+                    " WIDTH=\"{}\" HEIGHT=\"{}\"",
+                    mm10(width, 72.0),
+                    mm10(height, 72.0)
+                ),
+                None => String::new(),
+            };
+            page.push_str(&format!(
+                "    <Page ID=\"page_{}\" PHYSICAL_IMG_NR=\"{}\"{}/>\n",
+                number, number, dimensions
+            ))
+        }
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<alto xmlns=\"http://www.loc.gov/standards/alto/ns-v4#\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"http://www.loc.gov/standards/alto/ns-v4# http://www.loc.gov/alto/v4/alto-4-2.xsd\">
+  <Description>
+    <MeasurementUnit>mm10</MeasurementUnit>
+    <Processing ID=\"OCR_0\">
+      <processingStepSettings>resolution {} DPI</processingStepSettings>
+      <processingSoftware>
+        <softwareName>{}</softwareName>
+        <softwareVersion>{}</softwareVersion>
+      </processingSoftware>
+    </Processing>
+  </Description>
+  <Layout>
+{}  </Layout>
+</alto>
+",
+        resolution,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        page
+    )
+}
+
+/// Returns the METS document which lists the ALTO files of the pages.
+fn mets(pages: usize) -> String {
+    let mut files = String::new();
+    let mut divs = String::new();
+    for n in 1..=pages {
+        files.push_str(&format!(
+            "      <mets:file ID=\"ALTO_{:04}\" MIMETYPE=\"text/xml\">
+        <mets:FLocat LOCTYPE=\"URL\" xlink:href=\"{}\"/>
+      </mets:file>
+",
+            n,
+            page_file(n)
+        ));
+        divs.push_str(&format!(
+            "      <mets:div ID=\"PHYS_{:04}\" ORDER=\"{}\" TYPE=\"page\">
+        <mets:fptr FILEID=\"ALTO_{:04}\"/>
+      </mets:div>
+",
+            n, n, n
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<mets:mets xmlns:mets=\"http://www.loc.gov/METS/\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"http://www.loc.gov/METS/ http://www.loc.gov/standards/mets/mets.xsd\">
+  <mets:fileSec>
+    <mets:fileGrp USE=\"FULLTEXT\">
+{}    </mets:fileGrp>
+  </mets:fileSec>
+  <mets:structMap TYPE=\"PHYSICAL\">
+    <mets:div ID=\"PHYS_0000\" TYPE=\"physSequence\">
+{}    </mets:div>
+  </mets:structMap>
+</mets:mets>
+",
+        files, divs
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOCR: &str = "<html>
+ <body>
+  <div class='ocr_page' id='page_1' title='image \"x.tiff\"; bbox 0 0 2480 3508; ppageno 0'>
+   <div class='ocr_carea' id='block_1_1' title=\"bbox 100 200 900 300\">
+    <p class='ocr_par' id='par_1_1' lang='eng' title=\"bbox 100 200 900 300\">
+     <span class='ocr_line' id='line_1_1' title=\"bbox 100 200 900 300; baseline 0 -5\">
+      <span class='ocrx_word' id='word_1_1' title='bbox 100 200 400 300; x_wconf 96'>Fish</span>
+      <span class='ocrx_word' id='word_1_2' title='bbox 500 200 900 300; x_wconf 87'><strong>&amp;</strong> Chips</span>
+      <span class='ocrx_word' id='word_1_3' title='bbox 950 200 960 300; x_wconf 10'> </span>
+     </span>
+    </p>
+   </div>
+   <div class='ocr_carea' id='block_1_2' title=\"bbox 100 400 900 500\">
+    <span class='ocr_header' id='line_1_2' title=\"bbox 100 400 900 500\">
+     <span class='ocrx_word' id='word_1_4' title='bbox 100 400 900 500'>Menu</span>
+    </span>
+   </div>
+  </div>
+ </body>
+</html>
+";
+
+    #[test]
+    fn parse_hocr() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("page.hocr");
+        fs::write(&path, HOCR).unwrap();
+
+        let layout = parse(&path).unwrap();
+        assert_eq!(layout.bbox, [0, 0, 2480, 3508]);
+        assert_eq!(layout.blocks.len(), 2);
+        let line = &layout.blocks[0].lines[0];
+        assert_eq!(line.bbox, [100, 200, 900, 300]);
+        let words: Vec<&str> = line.words.iter().map(|x| x.text.as_str()).collect();
+        assert_eq!(words, vec!["Fish", "& Chips"]);
+        assert_eq!(line.words[0].bbox, [100, 200, 400, 300]);
+        assert_eq!(line.words[1].confidence, Some(87));
+        let header = &layout.blocks[1].lines[0];
+        assert_eq!(header.words[0].text, "Menu");
+        assert_eq!(header.words[0].confidence, None);
+    }
+
+    #[test]
+    fn parse_hocr_without_page() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("page.hocr");
+        fs::write(&path, "<html><body></body></html>").unwrap();
+
+        assert!(parse(&path).is_err());
+    }
+
+    #[test]
+    fn convert_to_mm10() {
+        assert_eq!(mm10(300.0, 300.0), 254);
+        assert_eq!(mm10(2480.0, 300.0), 2100);
+        assert_eq!(mm10(3508.0, 300.0), 2970);
+        assert_eq!(mm10(595.276, 72.0), 2100);
+        assert_eq!(mm10(0.0, 600.0), 0);
+    }
+
+    #[test]
+    fn alto_coordinates_in_mm10() {
+        let layout = Layout {
+            bbox: [0, 0, 2480, 3508],
+            blocks: vec![Block {
+                bbox: [300, 600, 900, 900],
+                lines: vec![Line {
+                    bbox: [300, 600, 900, 900],
+                    words: vec![Word {
+                        bbox: [300, 600, 900, 900],
+                        confidence: Some(90),
+                        text: String::from("a<b"),
+                    }],
+                }],
+            }],
+        };
+
+        let page = alto(2, Some(layout), 300, None);
+        assert!(page
+            .contains("<Page ID=\"page_2\" PHYSICAL_IMG_NR=\"2\" WIDTH=\"2100\" HEIGHT=\"2970\">"));
+        assert!(page.contains(
+            "<String ID=\"string_2_1\" CONTENT=\"a&lt;b\" HPOS=\"254\" VPOS=\"508\" WIDTH=\"508\" HEIGHT=\"254\" WC=\"0.90\"/>"
+        ));
+        let empty = alto(3, None, 300, Some((595.276, 841.89)));
+        assert!(empty.contains(
+            "<Page ID=\"page_3\" PHYSICAL_IMG_NR=\"3\" WIDTH=\"2100\" HEIGHT=\"2970\"/>"
+        ));
+    }
+}
@@ -0,0 +1,276 @@
+/// Pdfiron depends on a number of external applications. The name of each binary can be altered by
+/// the user with a command line argument or an environment variable. This module keeps track of
+/// the used names and how they were obtained, this way error messages can tell the user where a
+/// wrong name came from.
+use crate::error::{ExecutableNameMethod, ExecutableNotFound};
+
+use std::env;
+use std::fmt;
+use std::process::Command;
+
+/// The external applications used by pdfiron.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tool {
+    /// ImageMagick's convert.
+    Convert,
+    /// The document enhancement application unpaper.
+    Unpaper,
+    /// The OCR engine Tesseract.
+    Tesseract,
+    /// pdfinfo of the Poppler project.
+    Pdfinfo,
+    /// pdfunite of the Poppler project.
+    Pdfunite,
+    /// pdfseparate of the Poppler project.
+    Pdfseparate,
+    /// pdftotext of the Poppler project.
+    Pdftotext,
+    /// pdfimages of the Poppler project.
+    Pdfimages,
+    /// jbig2enc, compresses bilevel images with JBIG2.
+    Jbig2,
+    /// pdftocairo of the Poppler project.
+    Pdftocairo,
+}
+
+impl Tool {
+    /// All tools.
+    pub const ALL: [Tool; 10] = [
+        Tool::Convert,
+        Tool::Unpaper,
+        Tool::Tesseract,
+        Tool::Pdfinfo,
+        Tool::Pdfunite,
+        Tool::Pdfseparate,
+        Tool::Pdftotext,
+        Tool::Pdfimages,
+        Tool::Jbig2,
+        Tool::Pdftocairo,
+    ];
+
+    /// Returns the default name of the binary.
+    pub fn default_name<'a>(self) -> &'a str {
+        match self {
+            Tool::Convert => "convert",
+            Tool::Unpaper => "unpaper",
+            Tool::Tesseract => "tesseract",
+            Tool::Pdfinfo => "pdfinfo",
+            Tool::Pdfunite => "pdfunite",
+            Tool::Pdfseparate => "pdfseparate",
+            Tool::Pdftotext => "pdftotext",
+            Tool::Pdfimages => "pdfimages",
+            Tool::Jbig2 => "jbig2",
+            Tool::Pdftocairo => "pdftocairo",
+        }
+    }
+
+    /// Returns the name of the command line argument to alter the binary name.
+    pub fn arg<'a>(self) -> &'a str {
+        match self {
+            Tool::Convert => "convert-binary",
+            Tool::Unpaper => "unpaper-binary",
+            Tool::Tesseract => "tesseract-binary",
+            Tool::Pdfinfo => "pdfinfo-binary",
+            Tool::Pdfunite => "pdfunite-binary",
+            Tool::Pdfseparate => "pdfseparate-binary",
+            Tool::Pdftotext => "pdftotext-binary",
+            Tool::Pdfimages => "pdfimages-binary",
+            Tool::Jbig2 => "jbig2-binary",
+            Tool::Pdftocairo => "pdftocairo-binary",
+        }
+    }
+
+    /// Returns the name of the environment variable to alter the binary name.
+    pub fn env<'a>(self) -> &'a str {
+        match self {
+            Tool::Convert => "PDFIRON_CONVERT",
+            Tool::Unpaper => "PDFIRON_UNPAPER",
+            Tool::Tesseract => "PDFIRON_TESSERACT",
+            Tool::Pdfinfo => "PDFIRON_PDFINFO",
+            Tool::Pdfunite => "PDFIRON_PDFUNITE",
+            Tool::Pdfseparate => "PDFIRON_PDFSEPARATE",
+            Tool::Pdftotext => "PDFIRON_PDFTOTEXT",
+            Tool::Pdfimages => "PDFIRON_PDFIMAGES",
+            Tool::Jbig2 => "PDFIRON_JBIG2",
+            Tool::Pdftocairo => "PDFIRON_PDFTOCAIRO",
+        }
+    }
+
+    /// Returns the arguments which make the binary print its version.
+    pub fn version_args<'a>(self) -> &'a [&'a str] {
+        match self {
+            Tool::Convert => &["-version"],
+            Tool::Unpaper | Tool::Tesseract | Tool::Jbig2 => &["--version"],
+            Tool::Pdfinfo
+            | Tool::Pdfunite
+            | Tool::Pdfseparate
+            | Tool::Pdftotext
+            | Tool::Pdfimages
+            | Tool::Pdftocairo => &["-v"],
+        }
+    }
+
+    /// Returns whether the tool is only needed by optional features.
+    pub fn is_optional(self) -> bool {
+        matches!(self, Tool::Jbig2)
+    }
+}
+
+impl fmt::Display for Tool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.default_name())
+    }
+}
+
+/// An executable with the name used to call it.
+#[derive(Debug, Clone)]
+pub struct Executable {
+    /// Default name of the executable.
+    name: String,
+    /// Name of the command line argument to alter the binary name, if there is one.
+    arg: Option<String>,
+    /// Name of the environment variable to alter the binary name, if there is one.
+    env: Option<String>,
+    /// Binary name used to call the executable.
+    used: String,
+    /// Method used to obtaining the name of the executable.
+    method: ExecutableNameMethod,
+}
+
+impl Executable {
+    /// Returns the executable of a tool with the given binary name.
+    pub fn new<S: Into<String>>(tool: Tool, used: S, method: ExecutableNameMethod) -> Self {
+        Self {
+            name: String::from(tool.default_name()),
+            arg: Some(String::from(tool.arg())),
+            env: Some(String::from(tool.env())),
+            used: used.into(),
+            method,
+        }
+    }
+
+    /// Returns the executable of a tool, the name is taken from the environment variable of the
+    /// tool if present, otherwise the default name is used.
+    pub fn from_env(tool: Tool) -> Self {
+        match env::var(tool.env()) {
+            Ok(x) if !x.is_empty() => Executable::new(tool, x, ExecutableNameMethod::Env),
+            _ => Executable::new(tool, tool.default_name(), ExecutableNameMethod::Def),
+        }
+    }
+
+    /// Returns an executable which isn't one of the known tools, used for custom stages.
+    pub fn custom<S: Into<String>>(name: S) -> Self {
+        let name = name.into();
+        Self {
+            name: name.clone(),
+            arg: None,
+            env: None,
+            used: name,
+            method: ExecutableNameMethod::Def,
+        }
+    }
+
+    /// Returns the binary name used to call the executable.
+    pub fn used(&self) -> &str {
+        &self.used
+    }
+
+    /// Returns how the binary name was obtained.
+    pub fn method(&self) -> ExecutableNameMethod {
+        self.method
+    }
+
+    /// Returns a new command calling the executable.
+    pub fn command(&self) -> Command {
+        Command::new(&self.used)
+    }
+
+    /// Returns the error for the case the executable couldn't be found.
+    pub fn not_found(&self) -> ExecutableNotFound {
+        ExecutableNotFound::new(
+            self.name.clone(),
+            self.arg.clone(),
+            self.env.clone(),
+            self.used.clone(),
+            self.method,
+        )
+    }
+}
+
+impl fmt::Display for Executable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.used)
+    }
+}
+
+/// The executables of all tools used by pdfiron.
+#[derive(Debug, Clone)]
+pub struct Binaries {
+    convert: Executable,
+    unpaper: Executable,
+    tesseract: Executable,
+    pdfinfo: Executable,
+    pdfunite: Executable,
+    pdfseparate: Executable,
+    pdftotext: Executable,
+    pdfimages: Executable,
+    jbig2: Executable,
+    pdftocairo: Executable,
+}
+
+impl Binaries {
+    /// Returns the executables as defined by the environment variables, falls back to the
+    /// default names.
+    pub fn from_env() -> Self {
+        Self {
+            convert: Executable::from_env(Tool::Convert),
+            unpaper: Executable::from_env(Tool::Unpaper),
+            tesseract: Executable::from_env(Tool::Tesseract),
+            pdfinfo: Executable::from_env(Tool::Pdfinfo),
+            pdfunite: Executable::from_env(Tool::Pdfunite),
+            pdfseparate: Executable::from_env(Tool::Pdfseparate),
+            pdftotext: Executable::from_env(Tool::Pdftotext),
+            pdfimages: Executable::from_env(Tool::Pdfimages),
+            jbig2: Executable::from_env(Tool::Jbig2),
+            pdftocairo: Executable::from_env(Tool::Pdftocairo),
+        }
+    }
+
+    /// Returns the executable of a tool.
+    pub fn get(&self, tool: Tool) -> &Executable {
+        match tool {
+            Tool::Convert => &self.convert,
+            Tool::Unpaper => &self.unpaper,
+            Tool::Tesseract => &self.tesseract,
+            Tool::Pdfinfo => &self.pdfinfo,
+            Tool::Pdfunite => &self.pdfunite,
+            Tool::Pdfseparate => &self.pdfseparate,
+            Tool::Pdftotext => &self.pdftotext,
+            Tool::Pdfimages => &self.pdfimages,
+            Tool::Jbig2 => &self.jbig2,
+            Tool::Pdftocairo => &self.pdftocairo,
+        }
+    }
+
+    /// Sets the executable of a tool.
+    pub fn set(&mut self, tool: Tool, executable: Executable) {
+        match tool {
+            Tool::Convert => self.convert = executable,
+            Tool::Unpaper => self.unpaper = executable,
+            Tool::Tesseract => self.tesseract = executable,
+            Tool::Pdfinfo => self.pdfinfo = executable,
+            Tool::Pdfunite => self.pdfunite = executable,
+            Tool::Pdfseparate => self.pdfseparate = executable,
+            Tool::Pdftotext => self.pdftotext = executable,
+            Tool::Pdfimages => self.pdfimages = executable,
+            Tool::Jbig2 => self.jbig2 = executable,
+            Tool::Pdftocairo => self.pdftocairo = executable,
+        }
+    }
+}
+
+impl Default for Binaries {
+    fn default() -> Self {
+        Binaries::from_env()
+    }
+}
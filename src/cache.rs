@@ -0,0 +1,471 @@
+/// The cache keeps the outputs of the stages between runs. An entry is addressed by the hash of
+/// the input file of a stage together with the effective parameters of the stage. This way an
+/// unchanged stage (like the rasterization when only the Tesseract options were altered) is
+/// reused, even the following stages hit the cache as long as they produce the same files. The
+/// size of the cache is limited, the least recently used entries are removed first.
+use crate::error::Error;
+use crate::run::Run;
+use crate::util;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+/// Default size limit of the cache in megabytes.
+pub const DEFAULT_CACHE_SIZE: u64 = 1024;
+
+/// Name of the file within an entry listing the outputs.
+const INDEX: &str = "outputs";
+
+/// Name of the file within an entry listing the sidecar files of the outputs.
+const SIDECARS: &str = "sidecars";
+
+/// Version of the layout of the entries. It's part of each key, entries of other versions are
+/// never read and removed over time by the size limit.
+const VERSION: u32 = 2;
+
+/// Counter used to give each temporary entry folder a unique name.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The on-disk cache of stage outputs.
+pub struct Cache {
+    /// Folder of the cache.
+    folder: PathBuf,
+    /// Maximal size of the cache in bytes.
+    limit: u64,
+    /// Hashes of the input documents, the pages of a document are addressed with it.
+    documents: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl Cache {
+    /// Returns the default location of the cache. This is `pdfiron` within `$XDG_CACHE_HOME`,
+    /// falls back to `~/.cache`.
+    pub fn default_folder() -> PathBuf {
+        let base = match env::var("XDG_CACHE_HOME") {
+            Ok(x) if !x.is_empty() => PathBuf::from(x),
+            _ => PathBuf::from(shellexpand::tilde("~/.cache").into_owned()),
+        };
+        base.join("pdfiron")
+    }
+
+    /// Opens the cache in the given folder, the limit is given in megabytes.
+    pub fn open(folder: &Path, limit: u64) -> Result<Self, Error> {
+        if let Err(e) = fs::create_dir_all(folder) {
+            return Err(Error::io(
+                format!("Couldn't create cache folder {}", folder.display()),
+                e,
+            ));
+        }
+        Ok(Self {
+            folder: folder.to_path_buf(),
+            limit: limit * 1024 * 1024,
+            documents: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the key of a job. The input is either a file or (for the first stage) a page of
+    /// an input document addressed as `document.pdf[n]`.
+    pub fn key(&self, stage: &str, parameters: &str, input: &Path) -> Result<String, Error> {
+        let prefix = format!("{}\0{}\0{}\0", VERSION, stage, parameters);
+        if input.is_file() {
+            return hash_file(input, prefix.as_bytes());
+        }
+        let (document, page) = match util::split_page(input) {
+            Some(x) => x,
+            None => {
+                return Err(Error::internal(format!(
+                    "Input {} is neither a file nor a page of a document",
+                    input.display()
+                )))
+            }
+        };
+        let document = self.document_hash(&document)?;
+        Ok(hash_bytes(
+            format!("{}{}\0{}", prefix, document, page).as_bytes(),
+        ))
+    }
+
+    /// Returns the hash of an input document, each document is only hashed once.
+    fn document_hash(&self, document: &Path) -> Result<String, Error> {
+        let mut documents = self.documents.lock().unwrap();
+        if let Some(x) = documents.get(document) {
+            return Ok(x.clone());
+        }
+        let hash = hash_file(document, &[])?;
+        documents.insert(document.to_path_buf(), hash.clone());
+        Ok(hash)
+    }
+
+    /// Copies the outputs of a cached job and their sidecar files into the folder of the run.
+    /// Returns the outputs, None if there is no such entry.
+    pub fn fetch(&self, run: &Run, key: &str, stem: &str) -> Result<Option<Vec<PathBuf>>, Error> {
+        let entry = self.folder.join(key);
+        let index = match fs::read_to_string(entry.join(INDEX)) {
+            Ok(x) => x,
+            Err(_) => return Ok(None),
+        };
+        let sidecars = fs::read_to_string(entry.join(SIDECARS)).unwrap_or_default();
+        let mut outputs = vec![];
+        for (prefix, list) in [("", &index), ("s", &sidecars)] {
+            for (n, suffix) in list.lines().enumerate() {
+                let output = run.prepend_with_temp_folder(format!("{}{}", stem, suffix));
+                if let Err(e) = fs::copy(entry.join(format!("{}{}", prefix, n)), &output) {
+                    return Err(Error::io(
+                        format!("Couldn't copy cached file to {}", output.display()),
+                        e,
+                    ));
+                }
+                if prefix.is_empty() {
+                    outputs.push(output);
+                }
+            }
+        }
+        // The modification time of the index marks the last usage of the entry.
+        if let Err(e) = fs::write(entry.join(INDEX), index) {
+            return Err(Error::io("Couldn't update cache entry", e));
+        }
+        Ok(Some(outputs))
+    }
+
+    /// Stores the outputs of a job together with their sidecar files. Files which aren't named
+    /// after the stem of the job (like a stage passing its input through) can't be cached, the
+    /// job is skipped then.
+    pub fn store(
+        &self,
+        key: &str,
+        stem: &str,
+        outputs: &[PathBuf],
+        sidecars: &[PathBuf],
+    ) -> Result<(), Error> {
+        let (output_suffixes, sidecar_suffixes) =
+            match (suffixes(stem, outputs), suffixes(stem, sidecars)) {
+                (Some(x), Some(y)) => (x, y),
+                _ => return Ok(()),
+            };
+        let entry = self.folder.join(key);
+        if entry.exists() {
+            return Ok(());
+        }
+
+        // The entry is assembled in a temporary folder and moved in place at the end, this way
+        // concurrent runs never see an incomplete entry.
+        let tmp = self.folder.join(format!(
+            "{}.tmp-{}-{}",
+            key,
+            process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let rsl = (|| {
+            fs::create_dir_all(&tmp)?;
+            for (n, output) in outputs.iter().enumerate() {
+                fs::copy(output, tmp.join(n.to_string()))?;
+            }
+            for (n, sidecar) in sidecars.iter().enumerate() {
+                fs::copy(sidecar, tmp.join(format!("s{}", n)))?;
+            }
+            fs::write(tmp.join(SIDECARS), lines(&sidecar_suffixes))?;
+            fs::write(tmp.join(INDEX), lines(&output_suffixes))?;
+            fs::rename(&tmp, &entry)
+        })();
+        if rsl.is_err() {
+            let _ = fs::remove_dir_all(&tmp);
+        }
+        match rsl {
+            Ok(_) => Ok(()),
+            Err(_) if entry.exists() => Ok(()),
+            Err(e) => Err(Error::io("Couldn't store cache entry", e)),
+        }
+    }
+
+    /// Removes the least recently used entries until the cache fits into its size limit.
+    pub fn trim(&self) -> Result<(), Error> {
+        let mut entries = entries(&self.folder)?;
+        let mut total: u64 = entries.iter().map(|x| x.size).sum();
+        if total <= self.limit {
+            return Ok(());
+        }
+        entries.sort_by_key(|x| x.used);
+        let mut removed = 0;
+        for entry in entries {
+            if total <= self.limit {
+                break;
+            }
+            if let Err(e) = fs::remove_dir_all(&entry.path) {
+                return Err(Error::io("Couldn't remove cache entry", e));
+            }
+            total -= entry.size;
+            removed += 1;
+        }
+        debug!(
+            "removed {} entries from the cache to fit into the size limit",
+            removed
+        );
+        Ok(())
+    }
+
+    /// Removes all entries of the cache in the given folder, returns the number of freed bytes.
+    pub fn clean(folder: &Path) -> Result<u64, Error> {
+        if !folder.exists() {
+            return Ok(0);
+        }
+        let mut freed = 0;
+        for entry in entries(folder)? {
+            if let Err(e) = fs::remove_dir_all(&entry.path) {
+                return Err(Error::io("Couldn't remove cache entry", e));
+            }
+            freed += entry.size;
+        }
+        Ok(freed)
+    }
+}
+
+/// An entry of the cache.
+struct Entry {
+    /// Folder of the entry.
+    path: PathBuf,
+    /// Size of all files of the entry in bytes.
+    size: u64,
+    /// Time of the last usage.
+    used: SystemTime,
+}
+
+/// Returns all entries of the cache in the given folder.
+fn entries(folder: &Path) -> Result<Vec<Entry>, Error> {
+    let dir = match fs::read_dir(folder) {
+        Ok(x) => x,
+        Err(e) => return Err(Error::io("Couldn't read content of cache folder", e)),
+    };
+    let mut rsl = vec![];
+    for item in dir.flatten() {
+        let path = item.path();
+        // Skip files and entries which are currently assembled.
+        if !path.is_dir() || path.to_string_lossy().contains(".tmp-") {
+            continue;
+        }
+        let files = match fs::read_dir(&path) {
+            Ok(x) => x,
+            Err(e) => return Err(Error::io("Couldn't read content of cache entry", e)),
+        };
+        let size = files
+            .flatten()
+            .filter_map(|x| x.metadata().ok())
+            .map(|x| x.len())
+            .sum();
+        let used = fs::metadata(path.join(INDEX))
+            .and_then(|x| x.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        rsl.push(Entry { path, size, used });
+    }
+    Ok(rsl)
+}
+
+/// Returns the parts of the file names following the stem, None if a file isn't named after it.
+fn suffixes<'a>(stem: &str, files: &'a [PathBuf]) -> Option<Vec<&'a str>> {
+    files
+        .iter()
+        .map(|x| {
+            x.file_name()
+                .and_then(|x| x.to_str())
+                .and_then(|x| x.strip_prefix(stem))
+        })
+        .collect()
+}
+
+/// Returns the items as lines, each one terminated by a line break.
+fn lines(items: &[&str]) -> String {
+    items.iter().map(|x| format!("{}\n", x)).collect()
+}
+
+/// Returns the hex encoded SHA-256 hash of the prefix followed by the content of the file.
+pub(crate) fn hash_file(path: &Path, prefix: &[u8]) -> Result<String, Error> {
+    let mut file = match File::open(path) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(Error::io(
+                format!("Couldn't open {} for hashing", path.display()),
+                e,
+            ))
+        }
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(prefix);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) => {
+                return Err(Error::io(
+                    format!("Couldn't read {} for hashing", path.display()),
+                    e,
+                ))
+            }
+        }
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Returns the hex encoded SHA-256 hash of the data.
+fn hash_bytes(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Encodes the bytes as lower case hex string.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pipeline::PipelineOptions;
+
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    /// Returns a run of a single image within a new folder.
+    fn run(folder: &TempDir) -> Run {
+        let image = folder.path().join("page.png");
+        fs::write(&image, b"not really an image").unwrap();
+        let options = PipelineOptions::new(&image).workdir(folder.path().join("run"));
+        Run::new(&options).unwrap()
+    }
+
+    /// Writes a file of the given size into the folder of the run.
+    fn file(run: &Run, name: &str, size: usize) -> PathBuf {
+        let path = run.prepend_with_temp_folder(name);
+        fs::write(&path, vec![b'x'; size]).unwrap();
+        path
+    }
+
+    /// Marks the last usage of an entry.
+    fn set_used(cache: &Cache, key: &str, secs: u64) {
+        let index = File::options()
+            .write(true)
+            .open(cache.folder.join(key).join(INDEX))
+            .unwrap();
+        index
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn keys() {
+        let folder = tempfile::tempdir().unwrap();
+        let cache = Cache::open(&folder.path().join("cache"), 1).unwrap();
+        let a = folder.path().join("a.png");
+        let b = folder.path().join("b.png");
+        fs::write(&a, b"content").unwrap();
+        fs::write(&b, b"content").unwrap();
+        let key = cache.key("convert", "300 false", &a).unwrap();
+        assert_eq!(key.len(), 64);
+        assert_eq!(cache.key("convert", "300 false", &b).unwrap(), key);
+        assert_ne!(cache.key("convert", "600 false", &a).unwrap(), key);
+        assert_ne!(cache.key("unpaper", "300 false", &a).unwrap(), key);
+
+        let doc = folder.path().join("doc.pdf");
+        fs::write(&doc, b"%PDF-1.4").unwrap();
+        let page = |n: usize| PathBuf::from(format!("{}[{}]", doc.display(), n));
+        let first = cache.key("convert", "", &page(0)).unwrap();
+        assert_eq!(cache.key("convert", "", &page(0)).unwrap(), first);
+        assert_ne!(cache.key("convert", "", &page(1)).unwrap(), first);
+        assert!(cache
+            .key("convert", "", &folder.path().join("x.png"))
+            .is_err());
+    }
+
+    #[test]
+    fn store_and_fetch() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        let cache = Cache::open(&folder.path().join("cache"), 1).unwrap();
+        assert_eq!(cache.fetch(&run, "key", "d-0000").unwrap(), None);
+
+        let outputs = vec![
+            file(&run, "d-0000-0.pdf", 10),
+            file(&run, "d-0000-1.pdf", 20),
+        ];
+        let sidecars = vec![
+            file(&run, "d-0000-0.hocr", 30),
+            file(&run, "d-0000-1.hocr", 40),
+        ];
+        cache.store("key", "d-0000", &outputs, &sidecars).unwrap();
+        for path in outputs.iter().chain(sidecars.iter()) {
+            fs::remove_file(path).unwrap();
+        }
+
+        // The outputs are restored under the stem of the fetching job.
+        let fetched = cache.fetch(&run, "key", "d-0001").unwrap().unwrap();
+        assert_eq!(
+            fetched,
+            vec![
+                run.prepend_with_temp_folder("d-0001-0.pdf"),
+                run.prepend_with_temp_folder("d-0001-1.pdf")
+            ]
+        );
+        assert_eq!(fs::read(&fetched[1]).unwrap().len(), 20);
+        let hocr = run.prepend_with_temp_folder("d-0001-1.hocr");
+        assert_eq!(fs::read(hocr).unwrap().len(), 40);
+    }
+
+    #[test]
+    fn store_skips_foreign_files() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        let cache = Cache::open(&folder.path().join("cache"), 1).unwrap();
+        let outputs = vec![file(&run, "c-0000.png", 10)];
+        cache.store("key", "d-0000", &outputs, &[]).unwrap();
+        assert_eq!(cache.fetch(&run, "key", "d-0000").unwrap(), None);
+    }
+
+    #[test]
+    fn trim_removes_least_recently_used() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        let cache = Cache::open(&folder.path().join("cache"), 1).unwrap();
+        for (n, key) in ["a", "b", "c"].iter().enumerate() {
+            let output = file(&run, &format!("{}-0000.pdf", key), 400 * 1024);
+            cache
+                .store(key, &format!("{}-0000", key), &[output], &[])
+                .unwrap();
+            set_used(&cache, key, [20, 10, 30][n]);
+        }
+        cache.trim().unwrap();
+        assert!(cache.folder.join("a").exists());
+        assert!(!cache.folder.join("b").exists());
+        assert!(cache.folder.join("c").exists());
+
+        // Fetching marks the entry as used.
+        cache.fetch(&run, "a", "x-0000").unwrap().unwrap();
+        let output = file(&run, "d-0000.pdf", 400 * 1024);
+        cache.store("d", "d-0000", &[output], &[]).unwrap();
+        cache.trim().unwrap();
+        assert!(cache.folder.join("a").exists());
+        assert!(!cache.folder.join("c").exists());
+        assert!(cache.folder.join("d").exists());
+    }
+
+    #[test]
+    fn clean() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        let path = folder.path().join("cache");
+        assert_eq!(Cache::clean(&path).unwrap(), 0);
+        let cache = Cache::open(&path, 1).unwrap();
+        let output = file(&run, "a-0000.pdf", 100);
+        cache.store("a", "a-0000", &[output], &[]).unwrap();
+        assert!(Cache::clean(&path).unwrap() >= 100);
+        assert_eq!(entries(&path).unwrap().len(), 0);
+        assert_eq!(cache.fetch(&run, "a", "a-0000").unwrap(), None);
+    }
+}
@@ -0,0 +1,524 @@
+/// Recurring combinations of options can be stored in a TOML configuration file. The values in
+/// the `[default]` table apply to every run, named profiles (`[profile.<name>]`) are applied on
+/// top of them when selected. The keys are named after the long command line arguments. Command
+/// line arguments always take precedence over the values of the configuration file.
+///
+/// ```toml
+/// [default]
+/// lang = "deu"
+///
+/// [profile.book-double]
+/// layout = "double"
+/// output-pages = 2
+/// resolution = 400
+/// ```
+use crate::binary::{Executable, Tool};
+use crate::error::{Error, ExecutableNameMethod};
+use crate::pipeline::{PipelineOptions, Resolution};
+use crate::run::Format;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Location of the configuration file relative to the configuration folder of the user.
+const CONFIG_FILE: &str = "pdfiron/config.toml";
+
+/// A set of options, all values are optional. Options which aren't set keep their previous
+/// value.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    /// Use grayscale.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gray: Option<bool>,
+    /// Use RGB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rgb: Option<bool>,
+    /// Resolution in DPI or auto.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<Resolution>,
+    /// Upper limit of the automatic resolution in DPI.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_resolution: Option<u64>,
+    /// Rasterizes pages consisting of a single image instead of extracting the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_extract_images: Option<bool>,
+    /// Clockwise rotation in degrees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotate: Option<f64>,
+    /// Additional options for convert.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub convert_options: Option<String>,
+    /// Selection of the pages to process.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pages: Option<String>,
+    /// Takes the pages which aren't selected untouched into the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub splice: Option<bool>,
+    /// Processes pages which already contain text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_ocr: Option<bool>,
+    /// Processes pages whose text is an invisible OCR layer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redo_ocr: Option<bool>,
+    /// Skips the enhancement with unpaper.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_unpaper: Option<bool>,
+    /// Layout of the scanned pages (single, double or none).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// Number of output pages per input page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_pages: Option<u8>,
+    /// Additional options for unpaper.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unpaper_options: Option<String>,
+    /// Disables the blackfilter of unpaper.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_blackfilter: Option<bool>,
+    /// Disables the grayfilter of unpaper.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_grayfilter: Option<bool>,
+    /// Skips the OCR.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_tesseract: Option<bool>,
+    /// Language(s) used by Tesseract.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    /// Additional options for Tesseract.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tesseract_options: Option<String>,
+    /// Number of parallel Tesseract processes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tesseract_threads: Option<usize>,
+    /// Converts the output into PDF/A-2b.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdfa: Option<bool>,
+    /// Compresses the image of bilevel pages with JBIG2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jbig2: Option<bool>,
+    /// Uses the lossy JBIG2 symbol coding.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jbig2_lossy: Option<bool>,
+    /// Number of cores used in parallel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<usize>,
+    /// Skips the check of the system before the run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_preflight: Option<bool>,
+    /// Reuses the outputs of the stages from previous runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<bool>,
+    /// Folder of the cache.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+    /// Maximal size of the cache in megabytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_size: Option<u64>,
+    /// Name of the convert binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub convert_binary: Option<String>,
+    /// Name of the unpaper binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unpaper_binary: Option<String>,
+    /// Name of the tesseract binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tesseract_binary: Option<String>,
+    /// Name of the pdfinfo binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdfinfo_binary: Option<String>,
+    /// Name of the pdfunite binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdfunite_binary: Option<String>,
+    /// Name of the pdfseparate binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdfseparate_binary: Option<String>,
+    /// Name of the pdftotext binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdftotext_binary: Option<String>,
+    /// Name of the pdfimages binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdfimages_binary: Option<String>,
+    /// Name of the jbig2 binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jbig2_binary: Option<String>,
+    /// Name of the pdftocairo binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdftocairo_binary: Option<String>,
+}
+
+impl Profile {
+    /// Returns the profile describing the given options.
+    pub fn from_options(options: &PipelineOptions) -> Self {
+        let (gray, rgb) = match options.format {
+            Format::Graymap => (true, false),
+            Format::Pixmap => (false, true),
+            Format::Bitmap | Format::Tiff => (false, false),
+        };
+        let binary = |tool| Some(String::from(options.binaries.get(tool).used()));
+        Self {
+            gray: Some(gray),
+            rgb: Some(rgb),
+            resolution: Some(options.resolution),
+            max_resolution: Some(options.max_resolution),
+            no_extract_images: Some(options.no_extract_images),
+            rotate: options.rotate,
+            convert_options: options.convert_options.clone(),
+            pages: options.pages.as_ref().map(|x| x.to_string()),
+            splice: Some(options.splice),
+            force_ocr: Some(options.force_ocr),
+            redo_ocr: Some(options.redo_ocr),
+            disable_unpaper: Some(options.disable_unpaper),
+            layout: Some(options.layout.to_string()),
+            output_pages: Some(options.output_pages),
+            unpaper_options: options.unpaper_options.clone(),
+            no_blackfilter: Some(options.no_blackfilter),
+            no_grayfilter: Some(options.no_grayfilter),
+            disable_tesseract: Some(options.disable_tesseract),
+            lang: Some(options.lang.clone()),
+            tesseract_options: options.tesseract_options.clone(),
+            tesseract_threads: options.tesseract_threads,
+            pdfa: Some(options.pdfa),
+            jbig2: Some(options.jbig2),
+            jbig2_lossy: Some(options.jbig2_lossy),
+            jobs: Some(options.jobs),
+            no_preflight: Some(!options.preflight),
+            cache: Some(options.cache),
+            cache_dir: Some(options.cache_dir.clone()),
+            cache_size: Some(options.cache_size),
+            convert_binary: binary(Tool::Convert),
+            unpaper_binary: binary(Tool::Unpaper),
+            tesseract_binary: binary(Tool::Tesseract),
+            pdfinfo_binary: binary(Tool::Pdfinfo),
+            pdfunite_binary: binary(Tool::Pdfunite),
+            pdfseparate_binary: binary(Tool::Pdfseparate),
+            pdftotext_binary: binary(Tool::Pdftotext),
+            pdfimages_binary: binary(Tool::Pdfimages),
+            jbig2_binary: binary(Tool::Jbig2),
+            pdftocairo_binary: binary(Tool::Pdftocairo),
+        }
+    }
+
+    /// Applies the values of the profile to the options. Binary names set by an environment
+    /// variable take precedence over the ones of the profile.
+    pub fn apply(&self, mut options: PipelineOptions) -> Result<PipelineOptions, Error> {
+        if self.gray.is_some() || self.rgb.is_some() {
+            options.format = Format::from(self.gray.unwrap_or(false), self.rgb.unwrap_or(false));
+        }
+        if let Some(x) = self.resolution {
+            options.resolution = x;
+        }
+        if let Some(x) = self.max_resolution {
+            options.max_resolution = x;
+        }
+        if let Some(x) = self.no_extract_images {
+            options.no_extract_images = x;
+        }
+        if let Some(x) = self.rotate {
+            options.rotate = Some(x);
+        }
+        if let Some(x) = &self.convert_options {
+            options.convert_options = Some(x.clone());
+        }
+        if let Some(x) = &self.pages {
+            options.pages = Some(x.parse()?);
+        }
+        if let Some(x) = self.splice {
+            options.splice = x;
+        }
+        if let Some(x) = self.force_ocr {
+            options.force_ocr = x;
+        }
+        if let Some(x) = self.redo_ocr {
+            options.redo_ocr = x;
+        }
+        if let Some(x) = self.disable_unpaper {
+            options.disable_unpaper = x;
+        }
+        if let Some(x) = &self.layout {
+            options.layout = x.parse()?;
+        }
+        if let Some(x) = self.output_pages {
+            options.output_pages = x;
+        }
+        if let Some(x) = &self.unpaper_options {
+            options.unpaper_options = Some(x.clone());
+        }
+        if let Some(x) = self.no_blackfilter {
+            options.no_blackfilter = x;
+        }
+        if let Some(x) = self.no_grayfilter {
+            options.no_grayfilter = x;
+        }
+        if let Some(x) = self.disable_tesseract {
+            options.disable_tesseract = x;
+        }
+        if let Some(x) = &self.lang {
+            options.lang = x.clone();
+        }
+        if let Some(x) = &self.tesseract_options {
+            options.tesseract_options = Some(x.clone());
+        }
+        if let Some(x) = self.tesseract_threads {
+            options.tesseract_threads = Some(x);
+        }
+        if let Some(x) = self.pdfa {
+            options.pdfa = x;
+        }
+        if let Some(x) = self.jbig2 {
+            options.jbig2 = x;
+        }
+        if let Some(x) = self.jbig2_lossy {
+            options.jbig2_lossy = x;
+        }
+        if let Some(x) = self.jobs {
+            options.jobs = x;
+        }
+        if let Some(x) = self.no_preflight {
+            options.preflight = !x;
+        }
+        if let Some(x) = self.cache {
+            options.cache = x;
+        }
+        if let Some(x) = &self.cache_dir {
+            options.cache_dir =
+                PathBuf::from(shellexpand::tilde(&x.to_string_lossy()).into_owned());
+        }
+        if let Some(x) = self.cache_size {
+            options.cache_size = x;
+        }
+        for tool in Tool::ALL.iter() {
+            let from_env = options.binaries.get(*tool).method() == ExecutableNameMethod::Env;
+            if let (Some(x), false) = (self.binary(*tool), from_env) {
+                options.binaries.set(
+                    *tool,
+                    Executable::new(*tool, x, ExecutableNameMethod::Config),
+                );
+            }
+        }
+        Ok(options)
+    }
+
+    /// Returns the binary name of a tool if set.
+    fn binary(&self, tool: Tool) -> Option<&str> {
+        match tool {
+            Tool::Convert => self.convert_binary.as_deref(),
+            Tool::Unpaper => self.unpaper_binary.as_deref(),
+            Tool::Tesseract => self.tesseract_binary.as_deref(),
+            Tool::Pdfinfo => self.pdfinfo_binary.as_deref(),
+            Tool::Pdfunite => self.pdfunite_binary.as_deref(),
+            Tool::Pdfseparate => self.pdfseparate_binary.as_deref(),
+            Tool::Pdftotext => self.pdftotext_binary.as_deref(),
+            Tool::Pdfimages => self.pdfimages_binary.as_deref(),
+            Tool::Jbig2 => self.jbig2_binary.as_deref(),
+            Tool::Pdftocairo => self.pdftocairo_binary.as_deref(),
+        }
+    }
+
+    /// Returns the profile as TOML.
+    pub fn to_toml(&self) -> Result<String, Error> {
+        match toml::to_string(self) {
+            Ok(x) => Ok(x),
+            Err(e) => Err(Error::internal(format!(
+                "Couldn't serialize the configuration, {}",
+                e
+            ))),
+        }
+    }
+}
+
+/// Content of a configuration file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Values applied to every run.
+    #[serde(default)]
+    pub default: Profile,
+    /// Named profiles.
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Returns the default location of the configuration file. This is `pdfiron/config.toml`
+    /// within `$XDG_CONFIG_HOME`, falls back to `~/.config`.
+    pub fn default_path() -> PathBuf {
+        let base = match env::var("XDG_CONFIG_HOME") {
+            Ok(x) if !x.is_empty() => PathBuf::from(x),
+            _ => PathBuf::from(shellexpand::tilde("~/.config").into_owned()),
+        };
+        base.join(CONFIG_FILE)
+    }
+
+    /// Reads the configuration from the given file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(Error::io(
+                    format!("Couldn't read configuration file {}", path.display()),
+                    e,
+                ))
+            }
+        };
+        match toml::from_str(&content) {
+            Ok(x) => Ok(x),
+            Err(e) => Err(Error::parse(format!(
+                "Couldn't parse configuration file {}, {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    /// Reads the configuration from the default location. An empty configuration is returned if
+    /// there is no such file.
+    pub fn load_default() -> Result<Self, Error> {
+        let path = Config::default_path();
+        match path.exists() {
+            true => {
+                debug!("using configuration file {}", path.display());
+                Config::load(path)
+            }
+            false => Ok(Config::default()),
+        }
+    }
+
+    /// Applies the default values and the values of the given profile to the options.
+    pub fn apply(
+        &self,
+        profile: Option<&str>,
+        options: PipelineOptions,
+    ) -> Result<PipelineOptions, Error> {
+        let options = self.default.apply(options)?;
+        match profile {
+            Some(name) => {
+                match self.profile.get(name) {
+                    Some(x) => x.apply(options),
+                    None => {
+                        Err(Error::invalid_input(format!(
+                    "There is no profile named {} in the configuration, available profiles: {}",
+                    name,
+                    self.profile.keys().cloned().collect::<Vec<String>>().join(", ")
+                )))
+                    }
+                }
+            }
+            None => Ok(options),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pipeline::Layout;
+
+    const CONFIG: &str = r#"
+[default]
+lang = "deu"
+resolution = 400
+convert-binary = "magick"
+tesseract-binary = "/opt/tesseract/bin/tesseract"
+
+[profile.book-double]
+layout = "double"
+output-pages = 2
+resolution = "auto"
+"#;
+
+    fn config(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    /// Returns options whose binary names aren't affected by the environment of the test.
+    fn options() -> PipelineOptions {
+        let mut options = PipelineOptions::new("in.pdf");
+        for tool in Tool::ALL.iter() {
+            options.binaries.set(
+                *tool,
+                Executable::new(*tool, tool.default_name(), ExecutableNameMethod::Def),
+            );
+        }
+        options
+    }
+
+    #[test]
+    fn default_values() {
+        let options = config(CONFIG).unwrap().apply(None, options()).unwrap();
+        assert_eq!(options.lang, "deu");
+        assert_eq!(options.resolution, Resolution::Fixed(400));
+        assert_eq!(options.layout, PipelineOptions::new("in.pdf").layout);
+        assert_eq!(options.binaries.get(Tool::Convert).used(), "magick");
+        assert_eq!(
+            options.binaries.get(Tool::Convert).method(),
+            ExecutableNameMethod::Config
+        );
+    }
+
+    #[test]
+    fn profile_on_top_of_default_values() {
+        let options = config(CONFIG)
+            .unwrap()
+            .apply(Some("book-double"), options())
+            .unwrap();
+        assert_eq!(options.lang, "deu");
+        assert_eq!(options.layout, Layout::Double);
+        assert_eq!(options.output_pages, 2);
+        assert_eq!(options.resolution, Resolution::Auto);
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let rsl = config(CONFIG).unwrap().apply(Some("book"), options());
+        match rsl {
+            Err(Error::InvalidInput(x)) => assert!(x.contains("book-double"), "{}", x),
+            x => panic!("unexpected result {:?}", x.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        assert!(config("[default]\nlanguage = \"deu\"\n").is_err());
+        assert!(config("[defaults]\nlang = \"deu\"\n").is_err());
+        assert!(config("[profile.x]\nlang = \"deu\"\nlayuot = \"double\"\n").is_err());
+        assert!(config("").is_ok());
+    }
+
+    #[test]
+    fn invalid_values_are_refused() {
+        let config = config("[default]\nlayout = \"triple\"\n").unwrap();
+        assert!(config.apply(None, options()).is_err());
+    }
+
+    #[test]
+    fn environment_overrides_binary_names() {
+        let mut options = options();
+        options.binaries.set(
+            Tool::Tesseract,
+            Executable::new(Tool::Tesseract, "tesseract5", ExecutableNameMethod::Env),
+        );
+        let options = config(CONFIG).unwrap().apply(None, options).unwrap();
+        let tesseract = options.binaries.get(Tool::Tesseract);
+        assert_eq!(tesseract.used(), "tesseract5");
+        assert_eq!(tesseract.method(), ExecutableNameMethod::Env);
+        assert_eq!(options.binaries.get(Tool::Convert).used(), "magick");
+    }
+
+    #[test]
+    fn options_round_trip() {
+        let options = config(CONFIG)
+            .unwrap()
+            .apply(Some("book-double"), options())
+            .unwrap();
+        let profile = Profile::from_options(&options);
+        let parsed: Profile = toml::from_str(&profile.to_toml().unwrap()).unwrap();
+        assert_eq!(parsed, profile);
+    }
+}
@@ -1,199 +1,292 @@
-use crate::error::ErrorMessage;
-use crate::run::{Format, Run, START_PDF};
+use crate::binary::Tool;
+use crate::error::Error;
+use crate::run::{Format, Run};
+use crate::stage::{Artifact, Job, Stage};
 use crate::util;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::{Arc, Mutex};
-use std::thread;
 
-use num_cpus;
+/// Options of convert which keep bilevel images bilevel and compress them with CCITT Group 4.
+const BILEVEL_OPTIONS: &str = "-type Bilevel -compress Group4";
 
-/// Name of the convert binary.
-const CONVERT_BINARY: &str = "convert";
-
-/// The pages of the input document are extracted to images from the document. Takes an
-/// optional name of the convert binary (specify by the command line argument).
-pub fn execute<'a>(
-    run: &Run,
-    resolution: Option<String>,
-    rotate: Option<String>,
+/// The pages of the input document are extracted to images from the document. Input images are
+/// converted into the internal format, their pixels are kept as they are. The resolution of each
+/// page is given by its job.
+pub struct Rasterize {
+    /// Optional clockwise rotation in degrees.
+    rotate: Option<f64>,
+    /// Additional options for convert.
     options: Option<String>,
-) -> Result<(), ErrorMessage> {
-    run.log_step("Extracting images form input PDF");
-
-    let pages = util::count_pdf_pages(run.prepend_with_temp_folder(START_PDF))?;
-    let input_files: Vec<(PathBuf, PathBuf)> = (0..pages)
-        .map(|x| {
-            (
-                run.prepend_with_temp_folder(format!("{}[{}]", START_PDF, x)),
-                run.build_path(format!("a_{:05}", x), None),
-            )
-        })
-        .collect();
-
-    let files_arc = Arc::new(Mutex::new(input_files));
-    let format = Arc::new(run.format);
-    let resolution = Arc::new(resolution);
-    let rotate = Arc::new(rotate);
-    let options = Arc::new(options);
-    let mut handles = vec![];
-
-    for _ in 1..num_cpus::get() {
-        let files_arc = Arc::clone(&files_arc);
-        let format = Arc::clone(&format);
-        let resolution = Arc::clone(&resolution);
-        let rotate = Arc::clone(&rotate);
-        let options = Arc::clone(&options);
-
-        let handle =
-            thread::spawn(move || convert_thread(files_arc, format, resolution, rotate, options));
-        handles.push(handle);
-    }
-    for handle in handles {
-        handle.join().unwrap()?;
-    }
-
-    run.wait();
-    Ok(())
+}
+
+impl Rasterize {
+    /// Returns a new rasterize stage.
+    pub fn new(rotate: Option<f64>, options: Option<String>) -> Self {
+        Self { rotate, options }
+    }
+}
+
+impl Stage for Rasterize {
+    fn name(&self) -> &str {
+        "convert"
+    }
+
+    fn description(&self) -> String {
+        String::from("Extracting images form input PDF")
+    }
+
+    fn input(&self) -> Artifact {
+        Artifact::Document
+    }
+
+    fn output(&self) -> Artifact {
+        Artifact::Image
+    }
+
+    fn parameters(&self, run: &Run) -> Option<String> {
+        Some(format!(
+            "{:?} {:?} {}",
+            self.rotate,
+            self.options,
+            run.format.extension()
+        ))
+    }
+
+    fn process(&self, run: &Run, job: &Job) -> Result<Vec<PathBuf>, Error> {
+        let output = job.output(run, Artifact::Image);
+        convert(
+            run,
+            &job.input,
+            &output,
+            Some(run.format),
+            job.resolution,
+            self.rotate,
+            self.options.as_deref(),
+        )?;
+        Ok(vec![output])
+    }
 }
 
 /// Converts the intermediate portable anymaps (ether convert output or unpaper output depending
 /// whether unpaper was executed or not) and converts them into tiff's for Tesseract. Otherwise the
 /// resolution and or size could be affected.
-pub fn prepare_for_tesseract<'a>(
+pub struct Tiff;
+
+impl Stage for Tiff {
+    fn name(&self) -> &str {
+        "tiff"
+    }
+
+    fn description(&self) -> String {
+        String::from("Converting images for Tesseract input")
+    }
+
+    fn input(&self) -> Artifact {
+        Artifact::Image
+    }
+
+    fn output(&self) -> Artifact {
+        Artifact::Tiff
+    }
+
+    fn parameters(&self, _run: &Run) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn process(&self, run: &Run, job: &Job) -> Result<Vec<PathBuf>, Error> {
+        let output = job.output(run, Artifact::Tiff);
+        convert(
+            run,
+            &job.input,
+            &output,
+            None,
+            job.resolution,
+            None,
+            bilevel_options(job),
+        )?;
+        Ok(vec![output])
+    }
+}
+
+/// Wraps the images into single page PDF files. Used instead of the OCR if Tesseract is disabled.
+/// The resolution of the page determines the page size.
+pub struct ToPdf;
+
+impl Stage for ToPdf {
+    fn name(&self) -> &str {
+        "topdf"
+    }
+
+    fn description(&self) -> String {
+        String::from("Converting images to PDF")
+    }
+
+    fn input(&self) -> Artifact {
+        Artifact::Image
+    }
+
+    fn output(&self) -> Artifact {
+        Artifact::Pdf
+    }
+
+    fn parameters(&self, _run: &Run) -> Option<String> {
+        Some(String::new())
+    }
+
+    fn process(&self, run: &Run, job: &Job) -> Result<Vec<PathBuf>, Error> {
+        let output = job.output(run, Artifact::Pdf);
+        convert(
+            run,
+            &job.input,
+            &output,
+            None,
+            job.resolution,
+            None,
+            bilevel_options(job),
+        )?;
+        Ok(vec![output])
+    }
+}
+
+/// Wraps an image unaltered into a single page PDF file, the resolution determines the page size.
+pub fn wrap_image(
     run: &Run,
-    resolution: Option<String>,
-    disable_unpaper: bool,
-    disable_tesseract: bool,
-) -> Result<(), ErrorMessage> {
-    if disable_tesseract {
-        return Ok(());
-    }
-    run.log_step("Converting images for Tesseract input");
-
-    let input_files = run.query_files(match disable_unpaper {
-        true => "a_",
-        false => "b_",
-    })?;
-    let mut files: Vec<(PathBuf, PathBuf)> = vec![];
-    for input in input_files {
-        files.push((
-            input.clone(),
-            run.build_path(format!("c_{}_%05d", util::file_name(input)), Some("tiff")),
-        ));
-    }
-
-    let files_arc = Arc::new(Mutex::new(files));
-    let format = Arc::new(Format::Tiff);
-    let resolution = Arc::new(resolution);
-    let rotate = Arc::new(None);
-    let options = Arc::new(None);
-    let mut handles = vec![];
-
-    for _ in 1..num_cpus::get() {
-        let files_arc = Arc::clone(&files_arc);
-        let format = Arc::clone(&format);
-        let resolution = Arc::clone(&resolution);
-        let rotate = Arc::clone(&rotate);
-        let options = Arc::clone(&options);
-
-        let handle =
-            thread::spawn(move || convert_thread(files_arc, format, resolution, rotate, options));
-        handles.push(handle);
-    }
-    for handle in handles {
-        handle.join().unwrap()?;
-    }
-
-    run.wait();
+    input: &Path,
+    output: &Path,
+    resolution: u64,
+    bilevel: bool,
+) -> Result<(), Error> {
+    let options = match bilevel {
+        true => Some(BILEVEL_OPTIONS),
+        false => None,
+    };
+    convert(run, input, output, None, resolution, None, options)
+}
+
+/// Executes convert for one file. The color mode is only applied if a format is given.
+fn convert(
+    run: &Run,
+    input: &Path,
+    output: &Path,
+    format: Option<Format>,
+    resolution: u64,
+    rotate: Option<f64>,
+    options: Option<&str>,
+) -> Result<(), Error> {
+    let cmd = command(run, input, output, format, resolution, rotate, options);
+    debug!("Going to convert {}", input.display());
+    util::run_cmd(cmd, run.executable(Tool::Convert))?;
+    debug!("{} was converted to {}", input.display(), output.display());
     Ok(())
 }
 
-/// An convert execution thread to run multiple convert instances in parallel. Takes a tuple with
-/// the input and output path and executes the conversion. Will return if no more files are
-/// available to be processed.
-fn convert_thread<'a>(
-    input: Arc<Mutex<Vec<(PathBuf, PathBuf)>>>,
-    format: Arc<Format>,
-    resolution: Arc<Option<String>>,
-    rotate: Arc<Option<String>>,
-    options: Arc<Option<String>>,
-) -> Result<(), ErrorMessage> {
-    loop {
-        let mut files = input.lock().unwrap();
-        let file = match files.pop() {
-            Some(x) => x,
-            None => return Ok(()),
-        };
-        drop(files);
-        let mut cmd = Command::new(CONVERT_BINARY);
-        cmd.arg("-units").arg("PixelsPerInch");
-
-        // Color mode
-        match *format {
-            Format::Graymap => cmd
-                .arg("-colorspace")
-                .arg("gray")
-                .arg("-depth")
-                .arg("8")
-                .arg("-background")
-                .arg("white")
-                .arg("-alpha")
-                .arg("Off"),
-            Format::Bitmap => cmd
-                .arg("-depth")
-                .arg("8")
-                .arg("-background")
-                .arg("white")
-                .arg("-alpha")
-                .arg("Off"),
-            Format::Pixmap => cmd.arg("-type").arg("Bilevel"),
-            Format::Tiff => &cmd,
-        };
-
-        match *rotate {
-            Some(ref x) => cmd.arg("-rotate").arg(x),
-            None => &cmd,
-        };
-
-        set_density(&mut cmd, Arc::clone(&resolution))?;
-
-        // Optional arguments
-        cmd.args(match *format {
-            Format::Tiff => vec![],
-            _ => match *options {
-                Some(ref x) => x.split(" ").collect::<Vec<&str>>(),
-                None => vec![],
-            },
-        });
-
-        cmd.arg(&file.0);
-        cmd.arg(&file.1);
-
-        debug!("Going to convert {}", &file.0.display());
-        util::run_cmd(cmd, CONVERT_BINARY)?;
-        debug!(
-            "{} was converted to {}",
-            &file.0.display(),
-            &file.1.display()
-        );
+/// Returns the convert command for one file. The password is given if the input is a page of a
+/// document, only these may be encrypted.
+fn command(
+    run: &Run,
+    input: &Path,
+    output: &Path,
+    format: Option<Format>,
+    resolution: u64,
+    rotate: Option<f64>,
+    options: Option<&str>,
+) -> Command {
+    let mut cmd = run.command(Tool::Convert);
+    cmd.arg("-units").arg("PixelsPerInch");
+
+    // Color mode
+    match format {
+        Some(Format::Graymap) => cmd
+            .arg("-colorspace")
+            .arg("gray")
+            .arg("-depth")
+            .arg("8")
+            .arg("-background")
+            .arg("white")
+            .arg("-alpha")
+            .arg("Off"),
+        Some(Format::Bitmap) => cmd
+            .arg("-depth")
+            .arg("8")
+            .arg("-background")
+            .arg("white")
+            .arg("-alpha")
+            .arg("Off"),
+        Some(Format::Pixmap) => cmd.arg("-type").arg("Bilevel"),
+        Some(Format::Tiff) | None => &cmd,
+    };
+
+    match rotate {
+        Some(x) => cmd.arg("-rotate").arg(x.to_string()),
+        None => &cmd,
+    };
+
+    set_density(&mut cmd, resolution);
+
+    if let (Some(x), Some(_)) = (run.password(), util::split_page(input)) {
+        cmd.arg("-authenticate").arg(x);
+    }
+
+    // Optional arguments
+    cmd.args(match options {
+        Some(x) => x.split(' ').collect::<Vec<&str>>(),
+        None => vec![],
+    });
+
+    cmd.arg(input);
+    cmd.arg(output);
+    cmd
+}
+
+/// Returns the options needed to keep a bilevel page bilevel.
+fn bilevel_options(job: &Job) -> Option<&'static str> {
+    match job.bilevel {
+        true => Some(BILEVEL_OPTIONS),
+        false => None,
     }
 }
 
 /// Adds the density argument for a given command.
-fn set_density<'a>(cmd: &mut Command, resolution: Arc<Option<String>>) -> Result<(), ErrorMessage> {
-    let res = match *resolution {
-        Some(ref x) => match x.parse::<u64>() {
-            Ok(x) => x,
-            Err(_) => {
-                return Err(ErrorMessage::new(
-                    "Invalid resolution argument, has to be positive int",
-                ))
-            }
-        },
-        None => 300 as u64,
-    };
-    cmd.arg("-density").arg(format!("{}x{}", res, res));
-    Ok(())
+fn set_density(cmd: &mut Command, resolution: u64) {
+    cmd.arg("-density")
+        .arg(format!("{}x{}", resolution, resolution));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pipeline::PipelineOptions;
+
+    use std::ffi::OsStr;
+    use std::fs;
+
+    fn args(cmd: &Command) -> Vec<&OsStr> {
+        cmd.get_args().collect()
+    }
+
+    #[test]
+    fn password_is_given_for_pages_of_documents() {
+        let folder = tempfile::tempdir().unwrap();
+        let image = folder.path().join("page.png");
+        fs::write(&image, b"not really an image").unwrap();
+        let options = PipelineOptions::new(&image)
+            .workdir(folder.path().join("run"))
+            .password("secret");
+        let run = Run::new(&options).unwrap();
+        let page = Path::new("/tmp/doc.pdf[3]");
+        let output = Path::new("out.tif");
+
+        for format in [Some(run.format), None] {
+            let cmd = command(&run, page, output, format, 300, None, None);
+            let args = args(&cmd);
+            let n = args.iter().position(|x| *x == "-authenticate").unwrap();
+            assert_eq!(args[n + 1], "secret");
+            assert_eq!(args[args.len() - 2], page.as_os_str());
+        }
+
+        let cmd = command(&run, &image, output, Some(run.format), 300, None, None);
+        assert!(!args(&cmd).contains(&OsStr::new("-authenticate")));
+    }
 }
@@ -0,0 +1,367 @@
+/// Pdfiron can only do its work if the external applications are installed and usable. This
+/// module checks the system: whether all tools can be found (and which versions are installed),
+/// whether the language packs requested for Tesseract are available and whether the security
+/// policy of ImageMagick allows the reading of PDF files. The same checks, restricted to the tools
+/// needed by the given options, run as preflight before each conversion. This way a missing piece
+/// is reported before any work is done instead of failing somewhere in the middle of a run.
+use crate::binary::{Binaries, Executable, Tool};
+use crate::error::Error;
+use crate::input;
+use crate::pipeline::{PipelineOptions, Resolution};
+use crate::util;
+
+use std::fmt;
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Everything is fine.
+    Ok,
+    /// The check failed, a run will most likely fail too.
+    Failed,
+    /// An optional tool isn't usable, only the features depending on it are unavailable.
+    Missing,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Ok => f.pad("ok"),
+            Status::Failed => f.pad("FAIL"),
+            Status::Missing => f.pad("n/a"),
+        }
+    }
+}
+
+/// A single check of the system.
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// What was checked.
+    pub name: String,
+    /// Outcome of the check.
+    pub status: Status,
+    /// Version of the tool or a description of the problem.
+    pub detail: String,
+}
+
+impl Check {
+    /// Returns a new check based on the outcome of an operation.
+    fn from<S: Into<String>>(name: S, rsl: Result<String, Error>) -> Self {
+        let (status, detail) = match rsl {
+            Ok(x) => (Status::Ok, x),
+            Err(e) => (Status::Failed, e.to_string()),
+        };
+        Self {
+            name: name.into(),
+            status,
+            detail,
+        }
+    }
+}
+
+/// Outcome of all checks.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// The checks in execution order.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns the number of failed checks.
+    pub fn failed(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|x| x.status == Status::Failed)
+            .count()
+    }
+
+    /// Returns whether all checks passed.
+    pub fn is_ok(&self) -> bool {
+        self.failed() == 0
+    }
+}
+
+/// Checks all tools, the given Tesseract language(s) (multiple languages are joined with a plus
+/// sign like `deu+eng`) and the ImageMagick policy.
+pub fn diagnose(binaries: &Binaries, lang: &str) -> Report {
+    let mut checks: Vec<Check> = Tool::ALL
+        .iter()
+        .map(|x| {
+            let mut check = Check::from(x.to_string(), version(*x, binaries.get(*x)));
+            if x.is_optional() && check.status == Status::Failed {
+                check.status = Status::Missing;
+            }
+            check
+        })
+        .collect();
+    checks.push(Check::from(
+        "tesseract languages",
+        languages(binaries.get(Tool::Tesseract), lang).map(|_| lang.replace('+', ", ")),
+    ));
+    checks.push(Check::from(
+        "imagemagick policy",
+        pdf_policy(binaries.get(Tool::Convert)).map(|_| String::from("reading PDF is permitted")),
+    ));
+    Report { checks }
+}
+
+/// Runs the checks needed for the given options and returns the first problem found. Disabled
+/// tools aren't checked.
+pub fn preflight(options: &PipelineOptions) -> Result<(), Error> {
+    for tool in needed_tools(options) {
+        let rsl = version(tool, options.binaries.get(tool))?;
+        debug!("found {} ({})", tool, rsl);
+    }
+    if !options.disable_tesseract {
+        languages(options.binaries.get(Tool::Tesseract), &options.lang)?;
+    }
+    match needs_pdf_policy(options) {
+        true => pdf_policy(options.binaries.get(Tool::Convert)),
+        false => Ok(()),
+    }
+}
+
+/// Returns the tools needed for a run with the given options. The Poppler tools which read the
+/// input documents are only needed if there is a PDF input.
+fn needed_tools(options: &PipelineOptions) -> Vec<Tool> {
+    let pdf_input = options.inputs.iter().any(|x| input::may_be_pdf(x));
+    let mut tools = vec![Tool::Convert];
+    // The sizes of the pages taken over for ALTO are read with pdfinfo.
+    if pdf_input || options.alto.is_some() {
+        tools.push(Tool::Pdfinfo);
+    }
+    if !options.disable_unpaper {
+        tools.push(Tool::Unpaper);
+    }
+    if !options.disable_tesseract {
+        tools.push(Tool::Tesseract);
+    }
+    tools.push(Tool::Pdfunite);
+    if pdf_input {
+        // The images of the pages are listed with pdfimages for the automatic resolution and
+        // for the extraction of full page images.
+        if options.resolution == Resolution::Auto || !options.no_extract_images {
+            tools.push(Tool::Pdfimages);
+        }
+        // Pages with text are detected with pdftotext and taken over with pdfseparate.
+        if !options.force_ocr {
+            tools.push(Tool::Pdftotext);
+        }
+        if options.splice || !options.force_ocr {
+            tools.push(Tool::Pdfseparate);
+            // Pages of encrypted documents are taken over with pdftocairo.
+            if options.password.is_some() {
+                tools.push(Tool::Pdftocairo);
+            }
+        }
+    }
+    if options.jbig2 {
+        tools.push(Tool::Jbig2);
+    }
+    tools
+}
+
+/// Returns whether ImageMagick has to handle PDF files: a PDF input is rasterized or images which
+/// aren't selected are wrapped into PDF files.
+fn needs_pdf_policy(options: &PipelineOptions) -> bool {
+    options.inputs.iter().any(|x| input::may_be_pdf(x))
+        || (options.splice && options.pages.is_some())
+}
+
+/// Returns the version of a tool, this is the first line of its version output.
+fn version(tool: Tool, exe: &Executable) -> Result<String, Error> {
+    let mut cmd = exe.command();
+    cmd.args(tool.version_args());
+    let out = util::probe(cmd, exe)?;
+    match out.lines().map(|x| x.trim()).find(|x| !x.is_empty()) {
+        Some(x) => Ok(String::from(x)),
+        None => Err(Error::parse(format!(
+            "Couldn't determine the version of {}",
+            exe
+        ))),
+    }
+}
+
+/// Checks whether the language packs of all requested languages are installed.
+fn languages(tesseract: &Executable, lang: &str) -> Result<(), Error> {
+    let mut cmd = tesseract.command();
+    cmd.arg("--list-langs");
+    let out = util::probe(cmd, tesseract)?;
+    let installed = installed_languages(&out);
+    let missing: Vec<&str> = lang.split('+').filter(|x| !installed.contains(x)).collect();
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(Error::environment(format!(
+            "The Tesseract language pack(s) {} aren't installed (installed are: {}), on Debian based systems install the package(s) {}",
+            missing.join(", "),
+            installed.join(", "),
+            missing
+                .iter()
+                .map(|x| format!("tesseract-ocr-{}", x.to_lowercase().replace('_', "-")))
+                .collect::<Vec<String>>()
+                .join(" "),
+        ))),
+    }
+}
+
+/// Parses the output of `tesseract --list-langs` and returns the installed languages.
+fn installed_languages(listing: &str) -> Vec<&str> {
+    listing
+        .lines()
+        .skip_while(|x| !x.starts_with("List of available languages"))
+        .skip(1)
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// Checks whether the security policy of ImageMagick permits the reading of PDF files. Some
+/// distributions ship a policy which prohibits this.
+fn pdf_policy(convert: &Executable) -> Result<(), Error> {
+    let mut cmd = convert.command();
+    cmd.arg("-list").arg("policy");
+    let out = util::probe(cmd, convert)?;
+    match blocking_policy(&out) {
+        Some(x) => Err(Error::environment(format!(
+            "The security policy of ImageMagick prohibits the reading of PDF files. Please alter the PDF policy in {} to <policy domain=\"coder\" rights=\"read|write\" pattern=\"PDF\" />",
+            x
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Parses the policy listing of ImageMagick and returns the path of the policy file if the last
+/// matching coder or module policy doesn't grant read access to PDF files.
+fn blocking_policy(listing: &str) -> Option<String> {
+    let mut path = "";
+    let mut domain = "";
+    let mut rights = "";
+    let mut blocked = None;
+    for line in listing.lines().map(|x| x.trim()) {
+        if let Some(x) = line.strip_prefix("Path:") {
+            path = x.trim();
+        } else if let Some(x) = line.strip_prefix("Policy:") {
+            domain = x.trim();
+            rights = "";
+        } else if let Some(x) = line.strip_prefix("rights:") {
+            rights = x.trim();
+        } else if let Some(x) = line.strip_prefix("pattern:") {
+            let domain = domain.to_lowercase();
+            if (domain == "coder" || domain == "module") && matches_pdf(x.trim()) {
+                blocked = match rights.to_lowercase().contains("read") {
+                    true => None,
+                    false => Some(String::from(path)),
+                };
+            }
+        }
+    }
+    blocked
+}
+
+/// Checks whether a policy pattern like `{PS,PDF,XPS}` covers PDF files.
+fn matches_pdf(pattern: &str) -> bool {
+    pattern
+        .trim_matches(|x| x == '{' || x == '}')
+        .split(',')
+        .map(|x| x.trim().to_uppercase())
+        .any(|x| x == "PDF" || x == "*")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of `convert -list policy` with the policy shipped by Debian.
+    const DEBIAN_POLICY: &str = "
+Path: /etc/ImageMagick-6/policy.xml
+  Policy: Resource
+    name: disk
+    value: 1GiB
+  Policy: Coder
+    rights: None
+    pattern: PS
+  Policy: Coder
+    rights: None
+    pattern: PDF
+  Policy: Coder
+    rights: None
+    pattern: XPS
+
+Path: [built-in]
+  Policy: Undefined
+    rights: None
+";
+
+    #[test]
+    fn blocking_policy_of_debian() {
+        assert_eq!(
+            blocking_policy(DEBIAN_POLICY),
+            Some(String::from("/etc/ImageMagick-6/policy.xml"))
+        );
+    }
+
+    #[test]
+    fn permitting_policies() {
+        let altered = DEBIAN_POLICY.replace(
+            "rights: None\n    pattern: PDF",
+            "rights: Read Write\n    pattern: PDF",
+        );
+        assert_eq!(blocking_policy(&altered), None);
+        assert_eq!(
+            blocking_policy("Path: [built-in]\n  Policy: Undefined\n    rights: None\n"),
+            None
+        );
+        // A later policy overrides an earlier one.
+        let overridden = format!(
+            "{}Path: /home/user/.config/ImageMagick/policy.xml\n  Policy: Module\n    rights: Read\n    pattern: {{PS,PDF}}\n",
+            DEBIAN_POLICY
+        );
+        assert_eq!(blocking_policy(&overridden), None);
+    }
+
+    #[test]
+    fn pdf_patterns() {
+        assert!(matches_pdf("PDF"));
+        assert!(matches_pdf("pdf"));
+        assert!(matches_pdf("{PS,PDF,XPS}"));
+        assert!(matches_pdf("{ PS, PDF }"));
+        assert!(matches_pdf("*"));
+        assert!(!matches_pdf("PS"));
+        assert!(!matches_pdf("{PS,XPS,PDFA}"));
+    }
+
+    #[test]
+    fn parse_installed_languages() {
+        let listing = "List of available languages in \"/usr/share/tesseract-ocr/4.00/tessdata/\" (3):\ndeu\neng\nosd\n\n";
+        assert_eq!(installed_languages(listing), vec!["deu", "eng", "osd"]);
+        assert!(installed_languages("Error opening data file").is_empty());
+    }
+
+    #[test]
+    fn image_runs_need_no_pdf_tools() {
+        let options = PipelineOptions::new("scan.png").input("page.tif");
+        let tools = needed_tools(&options);
+        assert!(!tools.contains(&Tool::Pdfinfo));
+        assert!(!tools.contains(&Tool::Pdfimages));
+        assert!(!tools.contains(&Tool::Pdftotext));
+        assert!(tools.contains(&Tool::Convert));
+        assert!(tools.contains(&Tool::Pdfunite));
+        assert!(!needs_pdf_policy(&options));
+    }
+
+    #[test]
+    fn pdf_runs_need_poppler_and_the_policy() {
+        let options = PipelineOptions::new("scan.png").input("report.PDF");
+        let tools = needed_tools(&options);
+        for tool in [
+            Tool::Pdfinfo,
+            Tool::Pdfimages,
+            Tool::Pdftotext,
+            Tool::Pdfseparate,
+        ] {
+            assert!(tools.contains(&tool), "{} is missing", tool);
+        }
+        assert!(needs_pdf_policy(&options));
+        assert!(needs_pdf_policy(&PipelineOptions::new(input::STDIO)));
+    }
+}
@@ -1,73 +1,212 @@
 /// This module contains the errors which can occur during the execution of pdfiron. As this
 /// application is primary operated by the user itself the main focus is tho offer concise error
-/// messages for humans. Some related errors are unified into their own error class.
-use std::error::Error;
+/// messages for humans. Library users can match on the variants of [`Error`] to handle specific
+/// failures.
+use std::error;
 use std::fmt;
+use std::io;
+use std::path::PathBuf;
 
-/// Generic error class for all errors without their own error class. Only contains a error
-/// description for the end user.
-pub struct ErrorMessage(String);
+/// All errors which can occur while running pdfiron.
+#[derive(Debug)]
+pub enum Error {
+    /// A needed executable wasn't found on the system.
+    ExecutableNotFound(ExecutableNotFound),
+    /// An external tool was executed but didn't succeed.
+    ToolFailed {
+        /// Name of the executable.
+        tool: String,
+        /// Exit code of the process, None if the process was terminated by a signal.
+        code: Option<i32>,
+        /// Output of the process on stderr.
+        stderr: String,
+    },
+    /// The input file or the given options are invalid.
+    InvalidInput(String),
+    /// The input document is encrypted and no password was given.
+    Encrypted(PathBuf),
+    /// The given password doesn't open the encrypted input document.
+    WrongPassword(PathBuf),
+    /// An IO operation failed.
+    Io {
+        /// Description of the failed operation.
+        context: String,
+        /// The underlying error.
+        source: io::Error,
+    },
+    /// The output of a tool or a value couldn't be parsed.
+    Parse(String),
+    /// The processing of a page failed.
+    Page {
+        /// Index of the page in the input document, starting at zero.
+        page: usize,
+        /// The error which occurred while processing the page.
+        source: Box<Error>,
+    },
+    /// The system isn't ready for a run, like a missing Tesseract language or an ImageMagick
+    /// policy which prohibits the reading of PDF files.
+    Environment(String),
+    /// The output can't be made conform to PDF/A, contains the reasons.
+    PdfA(Vec<String>),
+    /// An unexpected failure within pdfiron itself (like a panicking worker thread).
+    Internal(String),
+}
+
+impl Error {
+    /// Returns a new invalid input error.
+    pub fn invalid_input<S: Into<String>>(msg: S) -> Self {
+        Error::InvalidInput(msg.into())
+    }
+
+    /// Returns a new IO error with the description of the failed operation.
+    pub fn io<S: Into<String>>(context: S, source: io::Error) -> Self {
+        Error::Io {
+            context: context.into(),
+            source,
+        }
+    }
 
-impl ErrorMessage {
-    /// Takes the error message and returns a new GenericError.
-    pub fn new<S: Into<String>>(msg: S) -> Self {
-        Self(msg.into())
+    /// Returns a new parse error.
+    pub fn parse<S: Into<String>>(msg: S) -> Self {
+        Error::Parse(msg.into())
+    }
+
+    /// Returns a new environment error.
+    pub fn environment<S: Into<String>>(msg: S) -> Self {
+        Error::Environment(msg.into())
+    }
+
+    /// Returns a new internal error.
+    pub fn internal<S: Into<String>>(msg: S) -> Self {
+        Error::Internal(msg.into())
+    }
+
+    /// Wraps the error into a page error. Errors which already belong to a page are kept as they
+    /// are.
+    pub fn for_page(self, page: usize) -> Self {
+        match self {
+            Error::Page { .. } => self,
+            _ => Error::Page {
+                page,
+                source: Box::new(self),
+            },
+        }
     }
 }
 
-impl fmt::Display for ErrorMessage {
+impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            Error::ExecutableNotFound(x) => write!(f, "{}", x),
+            Error::ToolFailed { tool, code, stderr } => {
+                match code {
+                    Some(x) => write!(f, "Execution of {} failed with exit code {}", tool, x)?,
+                    None => write!(f, "Execution of {} was terminated by a signal", tool)?,
+                };
+                match stderr.trim() {
+                    "" => Ok(()),
+                    x => write!(f, ", {}", x),
+                }
+            }
+            Error::InvalidInput(x) => write!(f, "{}", x),
+            Error::Encrypted(x) => write!(
+                f,
+                "The document {} is encrypted, please supply its password with --password or the PDFIRON_PASSWORD environment variable",
+                x.display()
+            ),
+            Error::WrongPassword(x) => write!(
+                f,
+                "The given password doesn't open the encrypted document {}",
+                x.display()
+            ),
+            Error::Io { context, source } => write!(f, "{}, {}", context, source),
+            Error::Parse(x) => write!(f, "{}", x),
+            Error::Page { page, source } => {
+                write!(f, "Processing of page {} failed: {}", page + 1, source)
+            }
+            Error::Environment(x) => write!(f, "{}", x),
+            Error::PdfA(x) => write!(
+                f,
+                "The output can't be converted to PDF/A-2b: {}",
+                x.join("; ")
+            ),
+            Error::Internal(x) => write!(f, "{}", x),
+        }
     }
 }
 
-impl fmt::Debug for ErrorMessage {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Page { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
     }
 }
 
-impl Error for ErrorMessage {}
+impl From<ExecutableNotFound> for Error {
+    fn from(item: ExecutableNotFound) -> Error {
+        Error::ExecutableNotFound(item)
+    }
+}
 
-/*
 /// Defines the used method of obtaining the name of a binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExecutableNameMethod {
+    /// The default name was used.
     Def,
+    /// The name was taken from an environment variable.
     Env,
+    /// The name was given as a command line argument.
     Arg,
+    /// The name was taken from the configuration file.
+    Config,
+}
+
+impl fmt::Display for ExecutableNameMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecutableNameMethod::Def => write!(f, "default"),
+            ExecutableNameMethod::Env => write!(f, "environment variable"),
+            ExecutableNameMethod::Arg => write!(f, "argument"),
+            ExecutableNameMethod::Config => write!(f, "configuration file"),
+        }
+    }
 }
 
 /// Error when an needed executable was not found on the system. Informs the user also the
 /// possibilities to set an alternative name via a command line argument and/or an environment
 /// variable.
+#[derive(Debug, Clone)]
 pub struct ExecutableNotFound {
     /// Default name of the executable.
-    name: String,
+    pub name: String,
     /// Name of the command line argument to set an alternative name of the executable.
-    arg: String,
+    pub arg: Option<String>,
     /// Name of the environment variable which can be used to alter the name of the binary.
-    env: String,
+    pub env: Option<String>,
     /// Binary name used while the error occurred.
-    used: String,
+    pub used: String,
     /// Method used to obtaining the name of the executable.
-    method: ExecutableNameMethod,
+    pub method: ExecutableNameMethod,
 }
 
 impl ExecutableNotFound {
     /// Returns a new ExecutableNotFound error instance.
     pub fn new(
         name: String,
-        arg: String,
-        env: String,
+        arg: Option<String>,
+        env: Option<String>,
         used: String,
         method: ExecutableNameMethod,
     ) -> Self {
         Self {
-            name: name,
-            arg: arg,
-            env: env,
-            used: used,
-            method: method,
+            name,
+            arg,
+            env,
+            used,
+            method,
         }
     }
 }
@@ -75,41 +214,40 @@ impl ExecutableNotFound {
 impl fmt::Display for ExecutableNotFound {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.method {
-            ExecutableNameMethod::Def => write!(
-                f,
-                "Couldn't find {} on your system under the name {} as specified by you with the --{} argument.",
-                self.name,
-                self.used,
-                self.arg
-            ),
+            ExecutableNameMethod::Def => {
+                write!(
+                    f,
+                    "Couldn't find {} on your system, please make sure {} is installed on your system.",
+                    self.name, self.name,
+                )?;
+                match (&self.arg, &self.env) {
+                    (Some(arg), Some(env)) => write!(
+                        f,
+                        " You can use the --{} argument or the environment variable {} to set an alternative binary name.",
+                        arg, env
+                    ),
+                    _ => Ok(()),
+                }
+            }
             ExecutableNameMethod::Env => write!(
                 f,
                 "Couldn't find {} on your system under the name {} as specified by you with the {} environment variable.",
                 self.name,
                 self.used,
-                self.env
+                self.env.as_deref().unwrap_or("<unknown>"),
             ),
             ExecutableNameMethod::Arg => write!(
                 f,
-                "Couldn't find {} on your system, please make sure {} is installed on your system. You can use the --{} argument or the environment variable {} to set an alternative binary name.",
-                self.name,
+                "Couldn't find {} on your system under the name {} as specified by you with the --{} argument.",
                 self.name,
-                self.arg,
-                self.env
+                self.used,
+                self.arg.as_deref().unwrap_or("<unknown>"),
+            ),
+            ExecutableNameMethod::Config => write!(
+                f,
+                "Couldn't find {} on your system under the name {} as specified in your configuration file.",
+                self.name, self.used,
             ),
         }
     }
 }
-
-impl fmt::Debug for ExecutableNotFound {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
-    }
-}
-
-impl From<ExecutableNotFound> for ErrorMessage {
-    fn from(item: ExecutableNotFound) -> ErrorMessage {
-        return ErrorMessage(format!("{}", item));
-    }
-}
-*/
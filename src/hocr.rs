@@ -0,0 +1,168 @@
+/// Tesseract writes the hOCR of each page into a file of its own. These are merged into a single
+/// hOCR document with the pages in the order of the output PDF. The ids within a page file all
+/// start with the page number 1, they are renumbered to match the position of the page.
+use crate::error::Error;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::{Captures, Regex};
+
+/// Header used if none of the pages has a hOCR file (all pages were taken over untouched).
+const EMPTY_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\"
+    \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd\">
+<html xmlns=\"http://www.w3.org/1999/xhtml\" xml:lang=\"en\" lang=\"en\">
+ <head>
+  <title></title>
+  <meta http-equiv=\"Content-Type\" content=\"text/html;charset=utf-8\"/>
+  <meta name='ocr-system' content='tesseract'/>
+  <meta name='ocr-number-of-pages' content='1'/>
+  <meta name='ocr-capabilities' content='ocr_page ocr_carea ocr_par ocr_line ocrx_word'/>
+ </head>
+ <body>
+";
+
+/// Merges the hOCR files of the pages into one document. Pages without a file (like pages taken
+/// over untouched) are added as empty pages.
+pub fn merge(pages: &[Option<PathBuf>], output: &Path) -> Result<(), Error> {
+    let id = Regex::new(r#"id='([a-z]+)_1([_'])"#).unwrap();
+    let page_number = Regex::new(r"ppageno \d+").unwrap();
+    let count = Regex::new(r"(name='ocr-number-of-pages' content=')\d+").unwrap();
+
+    let mut header = None;
+    let mut body = String::new();
+    for (n, page) in pages.iter().enumerate() {
+        let content = match page {
+            Some(x) => read(x)?,
+            None => {
+                body.push_str(&format!(
+                    "  <div class='ocr_page' id='page_{}' title='ppageno {}'>\n  </div>\n",
+                    n + 1,
+                    n
+                ));
+                continue;
+            }
+        };
+        let (head, rest) = match content.split_once("<body>") {
+            Some(x) => x,
+            None => {
+                return Err(Error::parse(format!(
+                    "Couldn't find the body of {}",
+                    page.as_ref().unwrap().display()
+                )))
+            }
+        };
+        if header.is_none() {
+            header = Some(format!("{}<body>\n", head));
+        }
+        let page_body = rest.split("</body>").next().unwrap_or_default();
+        let page_body = id.replace_all(page_body, |x: &Captures| {
+            format!("id='{}_{}{}", &x[1], n + 1, &x[2])
+        });
+        let page_body = page_body.trim_start_matches('\n').trim_end();
+        body.push_str(&page_number.replace_all(page_body, format!("ppageno {}", n).as_str()));
+        body.push('\n');
+    }
+
+    let header = header.unwrap_or_else(|| String::from(EMPTY_HEADER));
+    let header = count.replace(&header, |x: &Captures| format!("{}{}", &x[1], pages.len()));
+    let document = format!("{}{} </body>\n</html>\n", header, body);
+    match fs::write(output, document) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::io(
+            format!("Couldn't write hOCR to {}", output.display()),
+            e,
+        )),
+    }
+}
+
+/// Reads the hOCR of a page.
+fn read(path: &Path) -> Result<String, Error> {
+    match fs::read_to_string(path) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(Error::io(
+            format!("Couldn't read hOCR file {}", path.display()),
+            e,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the hOCR Tesseract writes for a single page with one word.
+    fn page(word: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<html>
+ <head>
+  <meta name='ocr-number-of-pages' content='1'/>
+ </head>
+ <body>
+  <div class='ocr_page' id='page_1' title='image \"x.tiff\"; bbox 0 0 100 100; ppageno 0'>
+   <div class='ocr_carea' id='block_1_1' title=\"bbox 1 1 50 20\">
+    <span class='ocr_line' id='line_1_1' title=\"bbox 1 1 50 20\">
+     <span class='ocrx_word' id='word_1_1' title='bbox 1 1 50 20; x_wconf 90'>{}</span>
+    </span>
+   </div>
+  </div>
+ </body>
+</html>
+",
+            word
+        )
+    }
+
+    #[test]
+    fn merge_renumbers_pages() {
+        let folder = tempfile::tempdir().unwrap();
+        let first = folder.path().join("first.hocr");
+        let third = folder.path().join("third.hocr");
+        fs::write(&first, page("one")).unwrap();
+        fs::write(&third, page("three")).unwrap();
+        let output = folder.path().join("merged.hocr");
+
+        merge(&[Some(first), None, Some(third)], &output).unwrap();
+        let merged = fs::read_to_string(&output).unwrap();
+
+        assert!(merged.contains("content='3'"));
+        assert_eq!(merged.matches("<body>").count(), 1);
+        assert_eq!(merged.matches("</html>").count(), 1);
+        assert!(
+            merged.contains("id='page_1' title='image \"x.tiff\"; bbox 0 0 100 100; ppageno 0'")
+        );
+        assert!(merged.contains("id='page_2' title='ppageno 1'"));
+        assert!(
+            merged.contains("id='page_3' title='image \"x.tiff\"; bbox 0 0 100 100; ppageno 2'")
+        );
+        assert!(merged.contains("id='word_1_1' title='bbox 1 1 50 20; x_wconf 90'>one<"));
+        assert!(merged.contains("id='block_3_1'"));
+        assert!(merged.contains("id='line_3_1'"));
+        assert!(merged.contains("id='word_3_1' title='bbox 1 1 50 20; x_wconf 90'>three<"));
+    }
+
+    #[test]
+    fn merge_without_hocr_files() {
+        let folder = tempfile::tempdir().unwrap();
+        let output = folder.path().join("merged.hocr");
+
+        merge(&[None, None], &output).unwrap();
+        let merged = fs::read_to_string(&output).unwrap();
+
+        assert!(merged.starts_with("<?xml"));
+        assert!(merged.contains("content='2'"));
+        assert!(merged.contains("id='page_1' title='ppageno 0'"));
+        assert!(merged.contains("id='page_2' title='ppageno 1'"));
+    }
+
+    #[test]
+    fn merge_rejects_files_without_body() {
+        let folder = tempfile::tempdir().unwrap();
+        let input = folder.path().join("broken.hocr");
+        fs::write(&input, "<html></html>").unwrap();
+
+        assert!(merge(&[Some(input)], &folder.path().join("merged.hocr")).is_err());
+    }
+}
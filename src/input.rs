@@ -0,0 +1,333 @@
+/// Pdfiron accepts PDF documents and scanned images as input. All inputs are processed as one
+/// logical document in the given order. Images can be given as individual files or as a
+/// directory containing them. The files of a directory are sorted naturally by
+/// their name, this way `scan2.png` comes before `scan10.png`. Images skip the rasterization of
+/// the document and are fed directly into the first stage. Each frame of a multi-page TIFF file
+/// is a page of its own. The input can also be streamed in via stdin, its format is determined by
+/// the content.
+use crate::binary::Executable;
+use crate::error::Error;
+use crate::util;
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Path which stands for stdin as input or stdout as output.
+pub const STDIO: &str = "-";
+
+/// File name (without extension) of the input read from stdin within the folder of the run.
+const STDIN_FILE: &str = "stdin";
+
+/// File extensions of the supported image formats.
+const IMAGE_EXTENSIONS: [&str; 13] = [
+    "bmp", "gif", "jp2", "jpeg", "jpg", "pbm", "pgm", "png", "pnm", "ppm", "tif", "tiff", "webp",
+];
+
+/// File extensions of the image formats which can contain multiple pages.
+const MULTI_PAGE_EXTENSIONS: [&str; 2] = ["tif", "tiff"];
+
+/// The kind of an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    /// A PDF document.
+    Pdf,
+    /// A scanned image.
+    Image,
+}
+
+/// A file given as input.
+#[derive(Debug, Clone)]
+pub struct InputFile {
+    /// Absolute path to the file.
+    pub path: PathBuf,
+    /// Kind of the file.
+    pub kind: InputKind,
+}
+
+/// Expands the given paths into the input files. Directories are replaced by the images they
+/// contain.
+pub fn collect(paths: &[PathBuf]) -> Result<Vec<InputFile>, Error> {
+    let mut rsl = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let images = images_in_dir(path)?;
+            if images.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "The directory {} doesn't contain any images",
+                    path.display()
+                )));
+            }
+            rsl.extend(images.into_iter().map(|x| InputFile {
+                path: x,
+                kind: InputKind::Image,
+            }));
+            continue;
+        }
+        if !path.exists() {
+            return Err(Error::invalid_input(format!(
+                "Given input file {} doesn't exist",
+                path.display(),
+            )));
+        }
+        match kind_of(path) {
+            Some(kind) => rsl.push(InputFile {
+                path: path.clone(),
+                kind,
+            }),
+            None => {
+                return Err(Error::invalid_input(format!(
+                    "Given input file {} is neither a PDF file nor a supported image",
+                    path.display()
+                )))
+            }
+        }
+    }
+    match rsl.is_empty() {
+        true => Err(Error::invalid_input("No input file given")),
+        false => Ok(rsl),
+    }
+}
+
+/// Reads the input from stdin and saves it in the given folder. Returns the path of the file,
+/// its extension is chosen by the format of the content.
+pub fn read_stdin(folder: &Path) -> Result<PathBuf, Error> {
+    let mut data = vec![];
+    if let Err(e) = io::stdin().lock().read_to_end(&mut data) {
+        return Err(Error::io("Couldn't read input from stdin", e));
+    }
+    if data.is_empty() {
+        return Err(Error::invalid_input("The input read from stdin is empty"));
+    }
+    let extension = match sniff(&data) {
+        Some(x) => x,
+        None => {
+            return Err(Error::invalid_input(
+                "The input read from stdin is neither a PDF file nor a supported image",
+            ))
+        }
+    };
+    let path = folder.join(format!("{}.{}", STDIN_FILE, extension));
+    debug!("save {} bytes from stdin to {}", data.len(), path.display());
+    match fs::write(&path, data) {
+        Ok(_) => Ok(path),
+        Err(e) => Err(Error::io(
+            format!("Couldn't save input from stdin to {}", path.display()),
+            e,
+        )),
+    }
+}
+
+/// Determines the file extension of a PDF file or image by its magic number.
+fn sniff(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [b'%', b'P', b'D', b'F', ..] => Some("pdf"),
+        [0x89, b'P', b'N', b'G', ..] => Some("png"),
+        [0xff, 0xd8, 0xff, ..] => Some("jpg"),
+        [b'I', b'I', 0x2a, 0x00, ..] | [b'M', b'M', 0x00, 0x2a, ..] => Some("tiff"),
+        [b'G', b'I', b'F', b'8', ..] => Some("gif"),
+        [b'B', b'M', ..] => Some("bmp"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        [0x00, 0x00, 0x00, 0x0c, b'j', b'P', ..] => Some("jp2"),
+        [b'P', b'1' | b'4', ..] => Some("pbm"),
+        [b'P', b'2' | b'5', ..] => Some("pgm"),
+        [b'P', b'3' | b'6', ..] => Some("ppm"),
+        _ => None,
+    }
+}
+
+/// A frame (page) of an input image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Resolution stated in the metadata of the image, None if the image doesn't state it.
+    pub resolution: Option<u64>,
+    /// Whether the frame is bilevel (one bit per pixel, like Group 4 compressed scans).
+    pub bilevel: bool,
+}
+
+/// Returns whether the image can contain multiple frames which are handled as separate pages.
+pub fn is_multi_page(image: &Path) -> bool {
+    match image.extension() {
+        Some(x) => MULTI_PAGE_EXTENSIONS.contains(&x.to_string_lossy().to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Returns the frames of an image. Only multi-page formats are examined for further frames,
+/// otherwise the first frame is returned.
+pub fn frames(image: &Path, convert: &Executable) -> Result<Vec<Frame>, Error> {
+    let mut cmd = convert.command();
+    match is_multi_page(image) {
+        true => cmd.arg(image),
+        false => cmd.arg(format!("{}[0]", image.display())),
+    };
+    cmd.arg("-format").arg("%z %x %U\n").arg("info:");
+    let out = util::cmd_output(cmd, convert)?;
+    let rsl = out
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(parse_frame)
+        .collect::<Option<Vec<Frame>>>();
+    match rsl {
+        Some(x) if !x.is_empty() => Ok(x),
+        _ => Err(Error::parse(format!(
+            "Couldn't parse the properties of {}",
+            image.display()
+        ))),
+    }
+}
+
+/// Parses the depth, resolution and unit of a frame as reported by ImageMagick.
+fn parse_frame(line: &str) -> Option<Frame> {
+    let mut parts = line.split_whitespace();
+    let depth = parts.next()?.parse::<u64>().ok()?;
+    let value = parts.next()?.parse::<f64>().ok()?;
+    // Older versions of ImageMagick append the unit to the value.
+    let factor = match parts.last() {
+        Some("PixelsPerInch") => Some(1.0),
+        Some("PixelsPerCentimeter") => Some(2.54),
+        _ => None,
+    };
+    let resolution = match factor.map(|x| (value * x).round() as u64) {
+        Some(0) | None => None,
+        x => x,
+    };
+    Some(Frame {
+        resolution,
+        bilevel: depth == 1,
+    })
+}
+
+/// Returns whether a given input path can be a PDF document. The content read from stdin isn't
+/// known in advance.
+pub fn may_be_pdf(path: &Path) -> bool {
+    path == Path::new(STDIO) || (!path.is_dir() && kind_of(path) == Some(InputKind::Pdf))
+}
+
+/// Returns the kind of a file based on its extension.
+fn kind_of(path: &Path) -> Option<InputKind> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    match ext.as_str() {
+        "pdf" => Some(InputKind::Pdf),
+        x if IMAGE_EXTENSIONS.contains(&x) => Some(InputKind::Image),
+        _ => None,
+    }
+}
+
+/// Returns the images within a directory in natural order of their file names.
+fn images_in_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(Error::io(
+                format!("Couldn't read content of {}", dir.display()),
+                e,
+            ))
+        }
+    };
+    let mut images: Vec<PathBuf> = entries
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| x.is_file() && kind_of(x) == Some(InputKind::Image))
+        .collect();
+    images.sort_by(|a, b| {
+        natural_cmp(
+            &a.file_name().unwrap().to_string_lossy(),
+            &b.file_name().unwrap().to_string_lossy(),
+        )
+    });
+    Ok(images)
+}
+
+/// Compares two strings in natural order, sequences of digits are compared by their numeric
+/// value.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                let x_trimmed = x.trim_start_matches('0');
+                let y_trimmed = y.trim_start_matches('0');
+                let ord = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x
+                    .to_lowercase()
+                    .cmp(y.to_lowercase())
+                    .then_with(|| x.cmp(&y));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Consumes a sequence of digits.
+fn take_number<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) -> String {
+    let mut rsl = String::new();
+    while let Some(x) = chars.peek().copied() {
+        if !x.is_ascii_digit() {
+            break;
+        }
+        rsl.push(x);
+        chars.next();
+    }
+    rsl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_cmp_compares_numbers_by_value() {
+        assert_eq!(natural_cmp("page2.png", "page10.png"), Ordering::Less);
+        assert_eq!(natural_cmp("page10.png", "page9.png"), Ordering::Greater);
+        assert_eq!(natural_cmp("scan 3 b", "scan 3 a"), Ordering::Greater);
+    }
+
+    #[test]
+    fn natural_cmp_orders_leading_zeros_after_shorter_numbers() {
+        assert_eq!(natural_cmp("1.png", "01.png"), Ordering::Less);
+        assert_eq!(natural_cmp("007.png", "8.png"), Ordering::Less);
+        assert_eq!(natural_cmp("02.png", "02.png"), Ordering::Equal);
+    }
+
+    #[test]
+    fn natural_cmp_ignores_case_first() {
+        assert_eq!(natural_cmp("a.png", "B.png"), Ordering::Less);
+        assert_eq!(natural_cmp("A.png", "a.png"), Ordering::Less);
+    }
+
+    #[test]
+    fn natural_cmp_orders_prefixes_first() {
+        assert_eq!(natural_cmp("page", "page1"), Ordering::Less);
+        assert_eq!(natural_cmp("", ""), Ordering::Equal);
+    }
+
+    #[test]
+    fn natural_sort_of_file_names() {
+        let mut names = vec!["p10.tif", "p2.tif", "P1.tif", "p1b.tif", "p02.tif"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["P1.tif", "p1b.tif", "p2.tif", "p02.tif", "p10.tif"]
+        );
+    }
+}
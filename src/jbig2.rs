@@ -0,0 +1,463 @@
+/// JBIG2 compresses bilevel scans far better than the CCITT Group 4 encoding Tesseract uses for
+/// the page image. After the OCR the image of a bilevel page is replaced with its JBIG2 encoding
+/// created by jbig2enc from the same TIFF file Tesseract worked on. The generic region coding is
+/// lossless. The symbol coding additionally stores similar looking symbols (like the occurrences
+/// of a letter) only once, this shrinks the files further but can swap similar characters on
+/// poor scans, thus it's opt-in.
+use crate::binary::Tool;
+use crate::error::Error;
+use crate::metadata::pdf_error;
+use crate::run::Run;
+use crate::util;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+
+/// The coding of the JBIG2 images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coding {
+    /// Lossless generic region coding.
+    Generic,
+    /// Lossy symbol coding, the symbols are shared within a page.
+    Symbol,
+}
+
+/// Type of the JBIG2 segment holding the page information (like the size of the page).
+const PAGE_INFORMATION: u8 = 48;
+
+/// Reasons to keep the original image.
+#[derive(Debug, PartialEq, Eq)]
+enum Kept {
+    /// The JBIG2 encoding isn't smaller.
+    Larger,
+    /// The JBIG2 encoding has another size (width and height) than the image or none at all.
+    Size(Option<(u32, u32)>),
+}
+
+/// Replaces the bilevel page image of a Tesseract PDF with the JBIG2 encoding of the given image.
+/// Documents which don't contain exactly one bilevel image are left untouched, as are images the
+/// JBIG2 encoding wouldn't make smaller or doesn't match in size.
+pub fn compress(run: &Run, image: &Path, document: &Path, coding: Coding) -> Result<(), Error> {
+    let mut doc = match Document::load(document) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(pdf_error(
+                format!("Couldn't read {}", document.display()),
+                e,
+            ))
+        }
+    };
+    let id = match bilevel_image(&doc) {
+        Some(x) => x,
+        None => {
+            debug!("{} has no bilevel page image", document.display());
+            return Ok(());
+        }
+    };
+
+    let (data, globals) = encode(run, image, document, coding)?;
+    match replace_image(&mut doc, id, data, globals) {
+        Ok(_) => {}
+        Err(Kept::Larger) => {
+            debug!(
+                "JBIG2 doesn't shrink the image of {}, keeping it",
+                document.display()
+            );
+            return Ok(());
+        }
+        Err(Kept::Size(x)) => {
+            warn!(
+                "The JBIG2 encoding of {} has the size {:?} which doesn't match the page image, keeping it",
+                image.display(),
+                x
+            );
+            return Ok(());
+        }
+    }
+    match doc.save(document) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::io(
+            format!("Couldn't write {}", document.display()),
+            e,
+        )),
+    }
+}
+
+/// Replaces the content of the image with the JBIG2 encoding. The encoding is made from the same
+/// file as the image, thus the colours of the image are given by the encoding (black for 1 bits)
+/// and its own decode array is removed.
+fn replace_image(
+    doc: &mut Document,
+    id: ObjectId,
+    data: Vec<u8>,
+    globals: Option<Vec<u8>>,
+) -> Result<(), Kept> {
+    let stream = match doc.get_object(id).and_then(Object::as_stream) {
+        Ok(x) => x,
+        Err(_) => return Err(Kept::Size(None)),
+    };
+    let dimension = |key: &[u8]| stream.dict.get(key).and_then(Object::as_i64).ok();
+    let size = page_size(&data);
+    let expected = (dimension(b"Width"), dimension(b"Height"));
+    match size {
+        Some((width, height)) if expected == (Some(width as i64), Some(height as i64)) => {}
+        _ => return Err(Kept::Size(size)),
+    }
+    if data.len() + globals.as_ref().map_or(0, Vec::len) >= stream.content.len() {
+        return Err(Kept::Larger);
+    }
+    let globals = globals.map(|x| doc.add_object(Stream::new(dictionary! {}, x)));
+    if let Ok(Object::Stream(stream)) = doc.get_object_mut(id) {
+        let dict = &mut stream.dict;
+        dict.remove(b"Decode");
+        dict.remove(b"DecodeParms");
+        dict.set("ColorSpace", "DeviceGray");
+        dict.set("BitsPerComponent", 1);
+        dict.set("Filter", "JBIG2Decode");
+        if let Some(x) = globals {
+            dict.set("DecodeParms", dictionary! { "JBIG2Globals" => x });
+        }
+        stream.set_content(data);
+    }
+    Ok(())
+}
+
+/// Returns the id of the page image if the document contains a single image and it's bilevel.
+/// Images with an inverting decode array are skipped, Tesseract only writes them for other
+/// inputs than the TIFF files, thus their polarity might not match the encoded file.
+fn bilevel_image(doc: &Document) -> Option<ObjectId> {
+    let images: Vec<(&ObjectId, &Stream)> = doc
+        .objects
+        .iter()
+        .filter_map(|(id, x)| match x {
+            Object::Stream(s)
+                if s.dict
+                    .get(b"Subtype")
+                    .and_then(Object::as_name)
+                    .is_ok_and(|x| x == b"Image") =>
+            {
+                Some((id, s))
+            }
+            _ => None,
+        })
+        .collect();
+    match images.as_slice() {
+        [(id, stream)] => {
+            let dict = &stream.dict;
+            let mask = dict
+                .get(b"ImageMask")
+                .and_then(Object::as_bool)
+                .unwrap_or(false);
+            let depth = dict.get(b"BitsPerComponent").and_then(Object::as_i64);
+            let inverted = dict
+                .get(b"Decode")
+                .and_then(Object::as_array)
+                .is_ok_and(|x| x.first().and_then(|x| x.as_i64().ok()) == Some(1));
+            match !mask && !inverted && depth.is_ok_and(|x| x == 1) {
+                true => Some(**id),
+                false => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the size of the page (width and height) stated by the page information segment of
+/// JBIG2 data in the embedded format, None if there is no such segment.
+fn page_size(data: &[u8]) -> Option<(u32, u32)> {
+    let be32 = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let number = be32(i)?;
+        let flags = *data.get(i + 4)?;
+        let mut offset = i + 5;
+        // The number of referred segments is given in the upper three bits, the long form is
+        // used for more than four.
+        let referred = match data.get(offset)? >> 5 {
+            7 => {
+                let count = (be32(offset)? & 0x1fff_ffff) as usize;
+                offset += 4 + (count + 8) / 8;
+                count
+            }
+            x => {
+                offset += 1;
+                x as usize
+            }
+        };
+        offset += referred
+            * match number {
+                0..=256 => 1,
+                257..=65536 => 2,
+                _ => 4,
+            };
+        offset += match flags & 0x40 {
+            0 => 1,
+            _ => 4,
+        };
+        let length = be32(offset)? as usize;
+        offset += 4;
+        if flags & 0x3f == PAGE_INFORMATION {
+            return Some((be32(offset)?, be32(offset + 4)?));
+        }
+        i = offset.checked_add(length)?;
+    }
+    None
+}
+
+/// Encodes the image with jbig2enc and returns the page data and, for the symbol coding, the
+/// shared symbol dictionary.
+fn encode(
+    run: &Run,
+    image: &Path,
+    document: &Path,
+    coding: Coding,
+) -> Result<(Vec<u8>, Option<Vec<u8>>), Error> {
+    let mut cmd = run.command(Tool::Jbig2);
+    match coding {
+        // The generic region coding is written to stdout.
+        Coding::Generic => {
+            cmd.arg("-p").arg(image);
+            let data = util::cmd_bytes(cmd, run.executable(Tool::Jbig2))?;
+            Ok((data, None))
+        }
+        // The symbol coding writes the symbol dictionary and the page into files with the
+        // given base name.
+        Coding::Symbol => {
+            let base = document.with_extension("jbig2");
+            cmd.arg("-s").arg("-p").arg("-b").arg(&base).arg(image);
+            util::run_cmd(cmd, run.executable(Tool::Jbig2))?;
+            let globals = take(suffixed(&base, "sym"))?;
+            let data = take(suffixed(&base, "0000"))?;
+            Ok((data, Some(globals)))
+        }
+    }
+}
+
+/// Appends a suffix to the base name of jbig2enc.
+fn suffixed(base: &Path, suffix: &str) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Reads and removes an output file of jbig2enc.
+fn take(path: PathBuf) -> Result<Vec<u8>, Error> {
+    let data = match fs::read(&path) {
+        Ok(x) => x,
+        Err(e) => return Err(Error::io(format!("Couldn't read {}", path.display()), e)),
+    };
+    if let Err(e) = fs::remove_file(&path) {
+        warn!("Couldn't remove {}, {}", path.display(), e);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an image stream with the given entries and a content of the given size.
+    fn image(entries: lopdf::Dictionary, size: usize) -> Stream {
+        let mut dict = dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => 16,
+            "Height" => 8,
+        };
+        for (key, value) in entries.iter() {
+            dict.set(key.clone(), value.clone());
+        }
+        Stream::new(dict, vec![0; size])
+    }
+
+    /// Returns a document with a single page showing the given images and their ids.
+    fn document(images: Vec<Stream>) -> (Document, Vec<ObjectId>) {
+        let mut doc = Document::with_version("1.7");
+        let pages = doc.new_object_id();
+        let ids: Vec<ObjectId> = images.into_iter().map(|x| doc.add_object(x)).collect();
+        let mut objects = lopdf::Dictionary::new();
+        for (i, id) in ids.iter().enumerate() {
+            objects.set(format!("Im{}", i), *id);
+        }
+        let content = doc.add_object(Stream::new(
+            dictionary! {},
+            b"q 16 0 0 8 0 0 cm /Im0 Do Q".to_vec(),
+        ));
+        let page = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages,
+            "MediaBox" => vec![0.into(), 0.into(), 16.into(), 8.into()],
+            "Resources" => dictionary! { "XObject" => objects },
+            "Contents" => content,
+        });
+        doc.objects.insert(
+            pages,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages });
+        doc.trailer.set("Root", catalog);
+        (doc, ids)
+    }
+
+    /// Returns the entries of a bilevel image as written by Tesseract.
+    fn bilevel() -> lopdf::Dictionary {
+        dictionary! {
+            "ColorSpace" => "DeviceGray",
+            "BitsPerComponent" => 1,
+            "Filter" => "CCITTFaxDecode",
+            "DecodeParms" => dictionary! { "K" => -1, "Columns" => 16, "Rows" => 8 },
+        }
+    }
+
+    /// Returns a JBIG2 segment header (with a short count of referred segments and a one byte
+    /// page association) followed by the data.
+    fn segment(number: u32, kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = number.to_be_bytes().to_vec();
+        segment.extend([kind, 0, 1]);
+        segment.extend((data.len() as u32).to_be_bytes());
+        segment.extend(data);
+        segment
+    }
+
+    /// Returns the JBIG2 page data of a page with the given size.
+    fn page(width: u32, height: u32) -> Vec<u8> {
+        let mut info = width.to_be_bytes().to_vec();
+        info.extend(height.to_be_bytes());
+        info.extend([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut data = segment(0, PAGE_INFORMATION, &info);
+        // A generic region segment.
+        data.extend(segment(1, 38, &[0; 24]));
+        data
+    }
+
+    #[test]
+    fn single_bilevel_image() {
+        let (doc, ids) = document(vec![image(bilevel(), 10)]);
+        assert_eq!(bilevel_image(&doc), Some(ids[0]));
+    }
+
+    #[test]
+    fn other_images() {
+        let mask = image(
+            dictionary! { "ImageMask" => true, "BitsPerComponent" => 1 },
+            10,
+        );
+        assert_eq!(bilevel_image(&document(vec![mask]).0), None);
+
+        let (doc, _) = document(vec![image(bilevel(), 10), image(bilevel(), 10)]);
+        assert_eq!(bilevel_image(&doc), None);
+
+        let gray = image(
+            dictionary! { "ColorSpace" => "DeviceGray", "BitsPerComponent" => 8 },
+            128,
+        );
+        assert_eq!(bilevel_image(&document(vec![gray]).0), None);
+
+        let mut entries = bilevel();
+        entries.set("Decode", vec![1.into(), 0.into()]);
+        assert_eq!(bilevel_image(&document(vec![image(entries, 10)]).0), None);
+
+        let mut entries = bilevel();
+        entries.set("Decode", vec![0.into(), 1.into()]);
+        let (doc, ids) = document(vec![image(entries, 10)]);
+        assert_eq!(bilevel_image(&doc), Some(ids[0]));
+
+        assert_eq!(bilevel_image(&document(Vec::new()).0), None);
+    }
+
+    #[test]
+    fn size_of_pages() {
+        assert_eq!(page_size(&page(2480, 3508)), Some((2480, 3508)));
+        assert_eq!(page_size(&[]), None);
+        assert_eq!(page_size(&page(2480, 3508)[..15]), None);
+        // Without a page information segment.
+        assert_eq!(page_size(&segment(1, 38, &[0; 24])), None);
+
+        // Referred segments, the long page association and the long count of referred segments.
+        let mut data = segment(300, 0, &[0; 4]);
+        data.extend(300_u32.to_be_bytes());
+        data.extend([0x40 | PAGE_INFORMATION, 0x20, 0x01, 0x2c, 0, 0, 0, 1]);
+        data.extend(8_u32.to_be_bytes());
+        data.extend(5_u32.to_be_bytes());
+        data.extend(7_u32.to_be_bytes());
+        assert_eq!(page_size(&data), Some((5, 7)));
+
+        let mut data = 1_u32.to_be_bytes().to_vec();
+        data.extend([PAGE_INFORMATION, 0xe0, 0, 0, 8, 0, 0]);
+        data.extend([0; 8]);
+        data.push(1);
+        data.extend(8_u32.to_be_bytes());
+        data.extend(5_u32.to_be_bytes());
+        data.extend(7_u32.to_be_bytes());
+        assert_eq!(page_size(&data), Some((5, 7)));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut entries = bilevel();
+        entries.set("Decode", vec![0.into(), 1.into()]);
+        let (mut doc, ids) = document(vec![image(entries, 500)]);
+        let id = bilevel_image(&doc).unwrap();
+        assert_eq!(id, ids[0]);
+        let data = page(16, 8);
+        replace_image(&mut doc, id, data.clone(), Some(b"SYMBOLS".to_vec())).unwrap();
+
+        let mut file = Vec::new();
+        doc.save_to(&mut file).unwrap();
+        let doc = Document::load_mem(&file).unwrap();
+        let stream = doc.get_object(id).and_then(Object::as_stream).unwrap();
+        let dict = &stream.dict;
+        let name = |key: &[u8]| dict.get(key).and_then(Object::as_name).unwrap().to_vec();
+        assert_eq!(name(b"Filter"), b"JBIG2Decode");
+        assert_eq!(name(b"ColorSpace"), b"DeviceGray");
+        assert_eq!(
+            dict.get(b"BitsPerComponent")
+                .and_then(Object::as_i64)
+                .unwrap(),
+            1
+        );
+        assert_eq!(dict.get(b"Width").and_then(Object::as_i64).unwrap(), 16);
+        assert_eq!(dict.get(b"Height").and_then(Object::as_i64).unwrap(), 8);
+        assert!(!dict.has(b"Decode"));
+        assert_eq!(stream.content, data);
+        let globals = dict
+            .get(b"DecodeParms")
+            .and_then(Object::as_dict)
+            .and_then(|x| x.get(b"JBIG2Globals"))
+            .and_then(Object::as_reference)
+            .unwrap();
+        let globals = doc.get_object(globals).and_then(Object::as_stream).unwrap();
+        assert_eq!(globals.content, b"SYMBOLS");
+
+        // The generic coding doesn't have globals.
+        let (mut doc, ids) = document(vec![image(bilevel(), 500)]);
+        replace_image(&mut doc, ids[0], data.clone(), None).unwrap();
+        let stream = doc.get_object(ids[0]).and_then(Object::as_stream).unwrap();
+        assert!(!stream.dict.has(b"DecodeParms"));
+        assert_eq!(stream.content, data);
+    }
+
+    #[test]
+    fn kept_images() {
+        let (mut doc, ids) = document(vec![image(bilevel(), 500)]);
+        let original = doc.get_object(ids[0]).unwrap().clone();
+        let kept = replace_image(&mut doc, ids[0], page(8, 16), None);
+        assert_eq!(kept, Err(Kept::Size(Some((8, 16)))));
+        let kept = replace_image(&mut doc, ids[0], b"GENERICDATA".to_vec(), None);
+        assert_eq!(kept, Err(Kept::Size(None)));
+        let kept = replace_image(&mut doc, ids[0], page(16, 8), Some(vec![0; 500]));
+        assert_eq!(kept, Err(Kept::Larger));
+        assert_eq!(doc.get_object(ids[0]).unwrap(), &original);
+        assert_eq!(doc.objects.len(), 5);
+    }
+}
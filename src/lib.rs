@@ -0,0 +1,40 @@
+//! Pdfiron enhances scanned PDF documents and images. The pages of the input document are
+//! extracted as images, optimized with unpaper, recognized with Tesseract and finally combined into a new PDF
+//! file. All the heavy lifting is done by external applications (ImageMagick, unpaper, Tesseract
+//! and Poppler), this crate orchestrates them.
+//!
+//! The [`Pipeline`] is the entry point for library users. It's configured with
+//! [`PipelineOptions`] and returns a [`PipelineResult`] after a successful run.
+
+mod alto;
+pub mod binary;
+pub mod cache;
+pub mod config;
+mod convert;
+pub mod doctor;
+pub mod error;
+mod hocr;
+mod input;
+mod jbig2;
+mod manifest;
+mod metadata;
+mod pages;
+mod pdf;
+mod pdfa;
+mod pipeline;
+mod run;
+mod scheduler;
+pub mod stage;
+mod tesseract;
+mod text;
+mod unpaper;
+mod util;
+
+#[macro_use]
+extern crate log;
+
+pub use binary::Tool;
+pub use pages::PageRanges;
+pub use pipeline::{Layout, Pipeline, PipelineOptions, PipelineResult, Resolution, StageTiming};
+pub use run::{Format, Run};
+pub use stage::{Artifact, CommandStage, Job, Stage};
@@ -1,10 +1,12 @@
-mod convert;
-mod error;
-mod pdf;
-mod run;
-mod tesseract;
-mod unpaper;
-mod util;
+use pdfiron::binary::{Binaries, Executable};
+use pdfiron::cache::Cache;
+use pdfiron::config::{Config, Profile};
+use pdfiron::doctor;
+use pdfiron::error::{Error, ExecutableNameMethod};
+use pdfiron::{Format, Pipeline, PipelineOptions, Resolution, Tool};
+
+use std::process;
+use std::str::FromStr;
 
 #[macro_use]
 extern crate log;
@@ -15,60 +17,347 @@ use clap::{App, ArgMatches};
 
 fn main() {
     let yaml = load_yaml!("cli.yml");
-    let matches = App::from(yaml).get_matches();
+    let matches = App::from_yaml(yaml).get_matches();
     env_logger::Builder::new()
         .filter(
             None,
-            match matches.is_present("debug") {
-                true => log::LevelFilter::Debug,
-                false => log::LevelFilter::Info,
+            if matches.is_present("debug") {
+                log::LevelFilter::Debug
+            } else {
+                log::LevelFilter::Info
             },
         )
         .init();
-    match convert(matches) {
+    let rsl = match matches.subcommand() {
+        Some(("cache", x)) => match x.subcommand() {
+            Some(("clean", y)) => clean_cache(y),
+            _ => Ok(()),
+        },
+        Some(("doctor", x)) => diagnose(x),
+        _ => match matches.is_present("print-config") {
+            true => print_config(&matches),
+            false => convert(matches),
+        },
+    };
+    match rsl {
         Ok(_) => {}
-        Err(e) => error!("{}", e),
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
     };
 }
 
 /// Does the conversion.
-fn convert(matches: ArgMatches) -> Result<(), error::ErrorMessage> {
-    let run = run::Run::new(
-        matches.value_of("INPUT").unwrap(),
-        matches.value_of("output"),
-        matches.is_present("gray"),
-        matches.is_present("rgb"),
-        matches.is_present("step"),
-    )?;
-
-    convert::execute(
-        &run,
-        matches.value_of("resolution").map(|x| x.into()),
-        matches.value_of("rotate").map(|x| x.into()),
-        matches.value_of("convert-options").map(|x| x.into()),
-    )?;
-    unpaper::execute(
-        &run,
-        matches.value_of("layout").map(|x| x.into()),
-        matches.value_of("output-pages").map(|x| x.into()),
-        matches.value_of("unpaper-options").map(|x| x.into()),
-        matches.is_present("no-blackfilter"),
-        matches.is_present("no-grayfilter"),
-    )?;
-    convert::prepare_for_tesseract(
-        &run,
-        matches.value_of("resolution").map(|x| x.into()),
-        matches.is_present("disable-unpaper"),
-        matches.is_present("disable-tesseract"),
-    )?;
-    tesseract::execute(
-        &run,
-        matches.is_present("disable-tesseract"),
-        matches.value_of("lang").map(|x| x.into()),
-        matches.value_of("tesseract-options").map(|x| x.into()),
-        matches.value_of("tesseract-threads").map(|x| x.into()),
-    )?;
-    pdf::unite(&run)?;
+fn convert(matches: ArgMatches) -> Result<(), Error> {
+    let options = options_from_matches(&matches)?;
+    let result = Pipeline::new(options).execute()?;
+    for timing in &result.timings {
+        debug!("{} took {:.2?}", timing.name, timing.duration);
+    }
+    info!(
+        "Saved {} pages to {} in {:.2?}",
+        result.pages,
+        match result.output.as_os_str() == "-" {
+            true => String::from("stdout"),
+            false => result.output.display().to_string(),
+        },
+        result.elapsed
+    );
+    Ok(())
+}
+
+/// Prints the effective options after merging the configuration file and the arguments.
+fn print_config(matches: &ArgMatches) -> Result<(), Error> {
+    let options = options_from_matches(matches)?;
+    print!("{}", Profile::from_options(&options).to_toml()?);
+    Ok(())
+}
 
+/// Removes all entries of the cache.
+fn clean_cache(matches: &ArgMatches) -> Result<(), Error> {
+    let mut options = config_options(matches, "")?;
+    if let Some(x) = matches.value_of("cache-dir") {
+        options = options.cache_dir(x);
+    }
+    let freed = Cache::clean(&options.cache_dir)?;
+    info!(
+        "Removed {:.1} MB from the cache in {}",
+        freed as f64 / (1024.0 * 1024.0),
+        options.cache_dir.display()
+    );
     Ok(())
 }
+
+/// Checks the system and prints the outcome of each check.
+fn diagnose(matches: &ArgMatches) -> Result<(), Error> {
+    let mut options = config_options(matches, "")?;
+    if let Some(x) = explicit_value(matches, "lang") {
+        options = options.lang(x);
+    }
+    apply_binary_args(matches, &mut options.binaries);
+    let report = doctor::diagnose(&options.binaries, &options.lang);
+    for check in &report.checks {
+        println!("{:<4}  {:<20} {}", check.status, check.name, check.detail);
+    }
+    match report.is_ok() {
+        true => {
+            info!("All checks passed");
+            Ok(())
+        }
+        false => Err(Error::environment(format!(
+            "{} of {} checks failed",
+            report.failed(),
+            report.checks.len()
+        ))),
+    }
+}
+
+/// Translates the command line arguments into the options of the pipeline. The arguments take
+/// precedence over the values of the configuration file.
+fn options_from_matches(matches: &ArgMatches) -> Result<PipelineOptions, Error> {
+    let mut inputs = matches.values_of("INPUT").into_iter().flatten();
+    let mut options = config_options(matches, inputs.next().unwrap_or_default())?;
+    for x in inputs {
+        options = options.input(x);
+    }
+
+    if matches.is_present("gray") || matches.is_present("rgb") {
+        options = options.format(Format::from(
+            matches.is_present("gray"),
+            matches.is_present("rgb"),
+        ));
+    }
+    if matches.is_present("step") {
+        options = options.step(true);
+    }
+    if let Some(x) = explicit_value(matches, "resolution") {
+        options = options.resolution(x.parse::<Resolution>()?);
+    }
+    if let Some(x) = parse_value(matches, "max-resolution")? {
+        options = options.max_resolution(x);
+    }
+    if matches.is_present("no-extract-images") {
+        options = options.no_extract_images(true);
+    }
+    if let Some(x) = matches.value_of("pages") {
+        options = options.pages(x.parse()?);
+    }
+    if matches.is_present("splice") {
+        options = options.splice(true);
+    }
+    if matches.is_present("force-ocr") {
+        options = options.force_ocr(true);
+    }
+    if matches.is_present("redo-ocr") {
+        options = options.redo_ocr(true);
+    }
+    if matches.is_present("pdfa") {
+        options = options.pdfa(true);
+    }
+    if matches.is_present("jbig2") {
+        options = options.jbig2(true);
+    }
+    if matches.is_present("jbig2-lossy") {
+        options = options.jbig2_lossy(true);
+    }
+    if matches.is_present("disable-unpaper") {
+        options = options.disable_unpaper(true);
+    }
+    if let Some(x) = parse_value(matches, "layout")? {
+        options = options.layout(x);
+    }
+    if let Some(x) = parse_value(matches, "output-pages")? {
+        options = options.output_pages(x);
+    }
+    if matches.is_present("no-blackfilter") {
+        options = options.no_blackfilter(true);
+    }
+    if matches.is_present("no-grayfilter") {
+        options = options.no_grayfilter(true);
+    }
+    if matches.is_present("disable-tesseract") {
+        options = options.disable_tesseract(true);
+    }
+    if let Some(x) = explicit_value(matches, "lang") {
+        options = options.lang(x);
+    }
+    if let Some(x) = matches.value_of("password") {
+        options = options.password(x);
+    }
+    if let Some(x) = matches.value_of("hocr") {
+        options = options.hocr(x);
+    }
+    if let Some(x) = matches.value_of("alto") {
+        options = options.alto(x);
+    }
+    if let Some(x) = matches.value_of("sidecar") {
+        options = options.sidecar(x);
+    }
+    if let Some(x) = matches.value_of("sidecar-pages") {
+        options = options.sidecar_pages(x);
+    }
+    if let Some(x) = matches.value_of("title") {
+        options = options.title(x);
+    }
+    if let Some(x) = matches.value_of("author") {
+        options = options.author(x);
+    }
+    if let Some(x) = matches.value_of("subject") {
+        options = options.subject(x);
+    }
+    if let Some(x) = matches.value_of("keywords") {
+        options = options.keywords(x);
+    }
+    if matches.is_present("no-preflight") {
+        options = options.preflight(false);
+    }
+    if let Some(x) = matches.value_of("workdir") {
+        options = options.workdir(x);
+    }
+    if matches.is_present("resume") {
+        options = options.resume(true);
+    }
+    if matches.is_present("keep-temp") {
+        options = options.keep_temp(true);
+    }
+    if matches.is_present("cache") {
+        options = options.cache(true);
+    }
+    if let Some(x) = matches.value_of("cache-dir") {
+        options = options.cache_dir(x);
+    }
+    if let Some(x) = parse_value(matches, "cache-size")? {
+        options = options.cache_size(x);
+    }
+    if let Some(x) = matches.value_of("output") {
+        options = options.output(x);
+    }
+    if let Some(x) = parse_value(matches, "rotate")? {
+        options = options.rotate(x);
+    }
+    if let Some(x) = matches.value_of("convert-options") {
+        options = options.convert_options(x);
+    }
+    if let Some(x) = matches.value_of("unpaper-options") {
+        options = options.unpaper_options(x);
+    }
+    if let Some(x) = matches.value_of("tesseract-options") {
+        options = options.tesseract_options(x);
+    }
+    if let Some(x) = parse_value(matches, "tesseract-threads")? {
+        options = options.tesseract_threads(x);
+    }
+    if let Some(x) = parse_value(matches, "jobs")? {
+        options = options.jobs(x);
+    }
+    apply_binary_args(matches, &mut options.binaries);
+    Ok(options)
+}
+
+/// Returns the default options altered by the configuration file and the selected profile.
+fn config_options(matches: &ArgMatches, input: &str) -> Result<PipelineOptions, Error> {
+    let config = match matches.value_of("config") {
+        Some(x) => Config::load(x)?,
+        None => Config::load_default()?,
+    };
+    config.apply(matches.value_of("profile"), PipelineOptions::new(input))
+}
+
+/// Sets the binary names given as arguments, these take precedence over the environment
+/// variables.
+fn apply_binary_args(matches: &ArgMatches, binaries: &mut Binaries) {
+    for tool in Tool::ALL.iter() {
+        if let Some(x) = matches.value_of(tool.arg()) {
+            binaries.set(*tool, Executable::new(*tool, x, ExecutableNameMethod::Arg));
+        }
+    }
+}
+
+/// Returns the value of an argument if it was given by the user. Default values of the command
+/// line definition are ignored, otherwise they would override the configuration file.
+fn explicit_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    match matches.occurrences_of(name) {
+        0 => None,
+        _ => matches.value_of(name),
+    }
+}
+
+/// Parses the value of an argument into the requested type. Returns None if the argument wasn't
+/// given.
+fn parse_value<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>, Error> {
+    match explicit_value(matches, name) {
+        Some(x) => match x.parse::<T>() {
+            Ok(x) => Ok(Some(x)),
+            Err(_) => Err(Error::invalid_input(format!(
+                "Invalid value {} for argument --{}",
+                x, name
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pdfiron::Layout;
+    use std::io::Write;
+
+    fn options(config: &str, args: &[&str]) -> PipelineOptions {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(config.as_bytes()).unwrap();
+        let path = file.path().to_str().unwrap();
+        let mut all = vec!["pdfiron", "in.pdf", "--config", path];
+        all.extend_from_slice(args);
+        let yaml = load_yaml!("cli.yml");
+        let matches = App::from_yaml(yaml).get_matches_from(all);
+        options_from_matches(&matches).unwrap()
+    }
+
+    const CONFIG: &str = r#"
+[default]
+lang = "deu"
+layout = "double"
+output-pages = 2
+resolution = 400
+convert-binary = "magick"
+"#;
+
+    #[test]
+    fn defaults_of_arguments_keep_config_values() {
+        let options = options(CONFIG, &[]);
+        assert_eq!(options.lang, "deu");
+        assert_eq!(options.layout, Layout::Double);
+        assert_eq!(options.output_pages, 2);
+        assert_eq!(options.resolution, Resolution::Fixed(400));
+        assert_eq!(options.binaries.get(Tool::Convert).used(), "magick");
+    }
+
+    #[test]
+    fn arguments_override_config_values() {
+        let options = options(
+            CONFIG,
+            &[
+                "--lang",
+                "fra",
+                "--layout",
+                "single",
+                "--output-pages",
+                "1",
+                "--resolution",
+                "auto",
+                "--convert-binary",
+                "gm-convert",
+            ],
+        );
+        assert_eq!(options.lang, "fra");
+        assert_eq!(options.layout, Layout::Single);
+        assert_eq!(options.output_pages, 1);
+        assert_eq!(options.resolution, Resolution::Auto);
+        let convert = options.binaries.get(Tool::Convert);
+        assert_eq!(convert.used(), "gm-convert");
+        assert_eq!(convert.method(), ExecutableNameMethod::Arg);
+    }
+}
@@ -0,0 +1,416 @@
+/// The manifest makes a run resumable. It describes the run (input files, stages and settings)
+/// and keeps a journal of all completed steps. A step is only recorded after the stage finished
+/// successfully, this way incomplete outputs of an interrupted step are never picked up again.
+/// When resuming a run, the journal is replayed to find the files each page has to continue
+/// with.
+use crate::cache;
+use crate::config::Profile;
+use crate::error::Error;
+use crate::run::Run;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// File name of the manifest within the folder of the run.
+const MANIFEST: &str = "manifest.toml";
+
+/// File name of the journal of the completed steps within the folder of the run.
+const JOURNAL: &str = "journal";
+
+/// Description of a run, a run can only be resumed with the same description.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct Header {
+    /// Names of the stages in execution order.
+    stages: Vec<String>,
+    /// Extensions of the sidecar files written by each stage, a run without the hOCR or text of
+    /// the pages can't provide them later on.
+    #[serde(default)]
+    sidecars: Vec<Vec<String>>,
+    /// The input files.
+    inputs: Vec<Input>,
+    /// Settings which affect the outputs of the stages.
+    settings: Profile,
+}
+
+/// An input file of the run.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct Input {
+    /// Absolute path to the file.
+    path: PathBuf,
+    /// Size of the file in bytes.
+    size: u64,
+    /// SHA-256 hash of the content, detects files changed in place.
+    #[serde(default)]
+    hash: String,
+}
+
+/// A completed step of a page.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Index of the completed stage.
+    pub stage: usize,
+    /// Position of the file within the document.
+    pub key: Vec<usize>,
+    /// Files produced by the stage.
+    pub outputs: Vec<PathBuf>,
+}
+
+/// The manifest of a run and its journal.
+pub struct Manifest {
+    /// Folder of the run.
+    folder: PathBuf,
+    /// The journal, opened for appending.
+    journal: Mutex<File>,
+}
+
+impl Manifest {
+    /// Opens the manifest within the folder of the run and returns the completed steps. If
+    /// `resume` is set, an existing manifest has to describe the same run. Otherwise a new
+    /// manifest is created, a folder already containing a run is refused.
+    pub fn open(
+        run: &Run,
+        stages: Vec<String>,
+        sidecars: Vec<Vec<String>>,
+        settings: Profile,
+        resume: bool,
+    ) -> Result<(Self, Vec<Entry>), Error> {
+        let mut inputs = vec![];
+        for file in run.inputs() {
+            inputs.push(Input {
+                path: file.path.clone(),
+                size: match fs::metadata(&file.path) {
+                    Ok(x) => x.len(),
+                    Err(e) => {
+                        return Err(Error::io(
+                            format!("Couldn't read metadata of {}", file.path.display()),
+                            e,
+                        ))
+                    }
+                },
+                hash: cache::hash_file(&file.path, &[])?,
+            });
+        }
+        let n_stages = stages.len();
+        let header = Header {
+            stages,
+            sidecars,
+            inputs,
+            settings,
+        };
+        let path = run.prepend_with_temp_folder(MANIFEST);
+        let journal = run.prepend_with_temp_folder(JOURNAL);
+        let entries = match (path.exists(), resume) {
+            (true, true) => {
+                Manifest::check(&path, &header)?;
+                Manifest::read_journal(run, &journal, n_stages)?
+            }
+            (true, false) => {
+                return Err(Error::invalid_input(format!(
+                    "The folder {} already contains a run, use --resume to continue it or choose another folder",
+                    run.folder().display()
+                )))
+            }
+            (false, resume) => {
+                if resume {
+                    info!(
+                        "There is no run to resume in {}, starting a new one",
+                        run.folder().display()
+                    );
+                }
+                Manifest::write_header(&path, &header)?;
+                if let Err(e) = File::create(&journal) {
+                    return Err(Error::io("Couldn't create journal of the run", e));
+                }
+                vec![]
+            }
+        };
+        let journal = match OpenOptions::new().append(true).open(&journal) {
+            Ok(x) => x,
+            Err(e) => return Err(Error::io("Couldn't open journal of the run", e)),
+        };
+        Ok((
+            Self {
+                folder: run.folder().to_path_buf(),
+                journal: Mutex::new(journal),
+            },
+            entries,
+        ))
+    }
+
+    /// Records a completed step. Each step is written as one line: the index of the stage, the
+    /// position of the file and the names of the produced files, separated by tabs.
+    pub fn record(&self, stage: usize, key: &[usize], outputs: &[PathBuf]) -> Result<(), Error> {
+        let mut line = format!(
+            "{}\t{}",
+            stage,
+            key.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>()
+                .join(".")
+        );
+        for output in outputs {
+            match output.strip_prefix(&self.folder) {
+                Ok(x) => line.push_str(&format!("\t{}", x.display())),
+                Err(_) => {
+                    return Err(Error::internal(format!(
+                        "Output {} is outside of the folder of the run",
+                        output.display()
+                    )))
+                }
+            }
+        }
+        line.push('\n');
+        let mut journal = self.journal.lock().unwrap();
+        match journal
+            .write_all(line.as_bytes())
+            .and_then(|_| journal.sync_data())
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::io("Couldn't write journal of the run", e)),
+        }
+    }
+
+    /// Checks whether the existing manifest describes the same run.
+    fn check(path: &Path, header: &Header) -> Result<(), Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => return Err(Error::io("Couldn't read manifest of the run", e)),
+        };
+        let existing: Header = match toml::from_str(&content) {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(Error::parse(format!(
+                    "Couldn't parse manifest {}, {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        match existing == *header {
+            true => Ok(()),
+            false => Err(Error::invalid_input(format!(
+                "The run in {} was started with other input files or other settings and can't be resumed",
+                path.parent().unwrap().display()
+            ))),
+        }
+    }
+
+    /// Writes the description of the run.
+    fn write_header(path: &Path, header: &Header) -> Result<(), Error> {
+        let content = match toml::to_string(header) {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(Error::internal(format!(
+                    "Couldn't serialize manifest of the run, {}",
+                    e
+                )))
+            }
+        };
+        match fs::write(path, content) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::io("Couldn't write manifest of the run", e)),
+        }
+    }
+
+    /// Reads the completed steps. An incomplete last line (the run was interrupted while
+    /// writing it) is ignored. Steps of stages beyond the given number are refused.
+    fn read_journal(run: &Run, path: &Path, stages: usize) -> Result<Vec<Entry>, Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => return Err(Error::io("Couldn't read journal of the run", e)),
+        };
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        let mut entries = vec![];
+        for line in lines {
+            if !line.ends_with('\n') {
+                break;
+            }
+            let mut fields = line.trim_end_matches('\n').split('\t');
+            let stage = fields.next().and_then(|x| x.parse::<usize>().ok());
+            let key = fields.next().map(|x| {
+                x.split('.')
+                    .map(|x| x.parse::<usize>())
+                    .collect::<Result<Vec<usize>, _>>()
+            });
+            match (stage, key) {
+                (Some(stage), Some(Ok(key))) if stage < stages => entries.push(Entry {
+                    stage,
+                    key,
+                    outputs: fields.map(|x| run.prepend_with_temp_folder(x)).collect(),
+                }),
+                _ => {
+                    return Err(Error::parse(format!(
+                        "Invalid line in journal {}: {}",
+                        path.display(),
+                        line.trim_end()
+                    )))
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pipeline::PipelineOptions;
+
+    use tempfile::TempDir;
+
+    /// Returns a run of a single image within a new folder.
+    fn run(folder: &TempDir) -> Run {
+        let image = folder.path().join("page.png");
+        if !image.exists() {
+            fs::write(&image, b"not really an image").unwrap();
+        }
+        let options = PipelineOptions::new(&image).workdir(folder.path().join("run"));
+        Run::new(&options).unwrap()
+    }
+
+    fn open(run: &Run, settings: Profile, resume: bool) -> Result<(Manifest, Vec<Entry>), Error> {
+        Manifest::open(
+            run,
+            vec!["convert".to_string(), "tesseract".to_string()],
+            vec![vec![], vec!["hocr".to_string()]],
+            settings,
+            resume,
+        )
+    }
+
+    fn write_journal(run: &Run, content: &str) {
+        fs::write(run.prepend_with_temp_folder(JOURNAL), content).unwrap();
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        let (manifest, entries) = open(&run, Profile::default(), false).unwrap();
+        assert!(entries.is_empty());
+        manifest
+            .record(0, &[0], &[run.prepend_with_temp_folder("a-0000.png")])
+            .unwrap();
+        manifest
+            .record(
+                1,
+                &[0, 0],
+                &[
+                    run.prepend_with_temp_folder("b-0000-0.pdf"),
+                    run.prepend_with_temp_folder("b-0000-1.pdf"),
+                ],
+            )
+            .unwrap();
+        drop(manifest);
+
+        let (_, entries) = open(&run, Profile::default(), true).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].stage, 0);
+        assert_eq!(entries[0].key, vec![0]);
+        assert_eq!(
+            entries[0].outputs,
+            vec![run.prepend_with_temp_folder("a-0000.png")]
+        );
+        assert_eq!(entries[1].stage, 1);
+        assert_eq!(entries[1].key, vec![0, 0]);
+        assert_eq!(entries[1].outputs.len(), 2);
+    }
+
+    #[test]
+    fn hand_written_journal() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        open(&run, Profile::default(), false).unwrap();
+        write_journal(
+            &run,
+            "0\t0\ta-0000.png\n0\t1\n1\t0.0\tb-0000-0.pdf\tb-0000-1.pdf\n",
+        );
+        let (_, entries) = open(&run, Profile::default(), true).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries[1].outputs.is_empty());
+        assert_eq!(entries[2].key, vec![0, 0]);
+        assert_eq!(
+            entries[2].outputs,
+            vec![
+                run.prepend_with_temp_folder("b-0000-0.pdf"),
+                run.prepend_with_temp_folder("b-0000-1.pdf")
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_last_line_is_ignored() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        open(&run, Profile::default(), false).unwrap();
+        write_journal(&run, "0\t0\ta-0000.png\n1\t0.0\tb-00");
+        let (_, entries) = open(&run, Profile::default(), true).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].stage, 0);
+    }
+
+    #[test]
+    fn invalid_lines_are_refused() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        open(&run, Profile::default(), false).unwrap();
+        for journal in &["2\t0\ta-0000.png\n", "x\t0\n", "0\t0.x\n", "0\n"] {
+            write_journal(&run, journal);
+            match open(&run, Profile::default(), true) {
+                Err(Error::Parse(_)) => {}
+                x => panic!("unexpected result for {:?}: {:?}", journal, x.map(|x| x.1)),
+            }
+        }
+    }
+
+    #[test]
+    fn other_run_is_refused() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        open(&run, Profile::default(), false).unwrap();
+        assert!(matches!(
+            open(&run, Profile::default(), false),
+            Err(Error::InvalidInput(_))
+        ));
+
+        let settings = Profile {
+            lang: Some("deu".to_string()),
+            ..Profile::default()
+        };
+        assert!(matches!(
+            open(&run, settings, true),
+            Err(Error::InvalidInput(_))
+        ));
+        let stages = Manifest::open(
+            &run,
+            vec!["convert".to_string()],
+            vec![vec![]],
+            Profile::default(),
+            true,
+        );
+        assert!(matches!(stages, Err(Error::InvalidInput(_))));
+
+        // Same size, other content.
+        fs::write(folder.path().join("page.png"), b"not really an imagE").unwrap();
+        assert!(matches!(
+            open(&run, Profile::default(), true),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn missing_run_starts_a_new_one() {
+        let folder = tempfile::tempdir().unwrap();
+        let run = run(&folder);
+        let (_, entries) = open(&run, Profile::default(), true).unwrap();
+        assert!(entries.is_empty());
+        assert!(run.prepend_with_temp_folder(MANIFEST).exists());
+        let content = fs::read_to_string(run.prepend_with_temp_folder(MANIFEST)).unwrap();
+        assert!(content.contains(&cache::hash_file(&folder.path().join("page.png"), &[]).unwrap()));
+    }
+}
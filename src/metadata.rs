@@ -0,0 +1,917 @@
+/// The metadata of the input documents (title, author, subject, keywords, the outline and the
+/// page labels) would get lost when the output is assembled from the single pages. Thus it's read
+/// from the input documents before the run and written into the output afterwards. Outline
+/// entries and page labels are mapped to the pages of the output, this way they stay correct
+/// when pages are left out or split.
+use crate::error::Error;
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, StringFormat};
+
+/// Maximal depth of nested outline entries and name trees, protects against cyclic references.
+const MAX_DEPTH: usize = 32;
+
+/// The descriptive fields of the document information dictionary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Info {
+    /// Title of the document.
+    pub title: Option<String>,
+    /// Name of the author.
+    pub author: Option<String>,
+    /// Subject of the document.
+    pub subject: Option<String>,
+    /// Keywords associated with the document.
+    pub keywords: Option<String>,
+}
+
+impl Info {
+    /// Returns the fields with their keys in the information dictionary.
+    fn fields(&self) -> [(&'static [u8], &Option<String>); 4] {
+        [
+            (b"Title", &self.title),
+            (b"Author", &self.author),
+            (b"Subject", &self.subject),
+            (b"Keywords", &self.keywords),
+        ]
+    }
+
+    /// Fills the fields which aren't set yet with the values of the other info.
+    fn complete(&mut self, other: Info) {
+        self.title = self.title.take().or(other.title);
+        self.author = self.author.take().or(other.author);
+        self.subject = self.subject.take().or(other.subject);
+        self.keywords = self.keywords.take().or(other.keywords);
+    }
+}
+
+/// An entry of the outline (bookmarks).
+#[derive(Debug, Clone)]
+struct OutlineItem {
+    /// Title of the entry.
+    title: String,
+    /// Index of the target page within all inputs, None if the entry has no target.
+    page: Option<usize>,
+    /// Nested entries.
+    children: Vec<OutlineItem>,
+}
+
+/// The label of a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Label {
+    /// Numbering style (D, R, r, A or a), None for labels consisting only of the prefix.
+    style: Option<Vec<u8>>,
+    /// Prefix of the label.
+    prefix: Option<Vec<u8>>,
+    /// Numeric part of the label.
+    value: i64,
+    /// Index of the first page of the labeling range within all inputs.
+    range: usize,
+}
+
+/// The metadata collected from the input documents. Pages are addressed by their index within all
+/// inputs.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    /// Document information, taken from the first input which states the respective field.
+    pub info: Info,
+    /// The outlines of all inputs.
+    outline: Vec<OutlineItem>,
+    /// Labels of the pages, None if a page has no label.
+    labels: Vec<Option<Label>>,
+}
+
+impl Metadata {
+    /// Reads the metadata of an input document, the pages are addressed starting at zero.
+    pub fn read(document: &Path, password: Option<&str>) -> Result<Self, Error> {
+        let mut doc = match Document::load(document) {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(pdf_error(
+                    format!("Couldn't read {}", document.display()),
+                    e,
+                ))
+            }
+        };
+        if doc.is_encrypted() {
+            if let Err(e) = doc.decrypt(password.unwrap_or_default()) {
+                return Err(pdf_error(
+                    format!("Couldn't decrypt {}", document.display()),
+                    e,
+                ));
+            }
+        }
+        let pages: HashMap<ObjectId, usize> = doc
+            .get_pages()
+            .into_iter()
+            .map(|(n, id)| (id, n as usize - 1))
+            .collect();
+        Ok(Self {
+            info: read_info(&doc),
+            outline: read_outline(&doc, &pages),
+            labels: read_labels(&doc, pages.len()),
+        })
+    }
+
+    /// Appends the metadata of an input document whose first page has the given index within all
+    /// inputs.
+    pub fn append(&mut self, other: Metadata, offset: usize) {
+        self.info.complete(other.info);
+        self.outline.extend(other.outline.into_iter().map(|mut x| {
+            shift_item(&mut x, offset);
+            x
+        }));
+        if other.labels.iter().any(|x| x.is_some()) {
+            self.labels.resize(offset, None);
+            self.labels.extend(other.labels.into_iter().map(|x| {
+                x.map(|mut x| {
+                    x.range += offset;
+                    x
+                })
+            }));
+        }
+    }
+
+    /// Returns whether there is any metadata to write.
+    pub fn is_empty(&self) -> bool {
+        self.info == Info::default() && self.outline.is_empty() && self.labels.is_empty()
+    }
+
+    /// Writes the metadata into the output document. The pages of the output are given by the
+    /// index of the input page they originate from.
+    pub fn write(&self, output: &Path, pages: &[usize]) -> Result<(), Error> {
+        let mut doc = match Document::load(output) {
+            Ok(x) => x,
+            Err(e) => return Err(pdf_error(format!("Couldn't read {}", output.display()), e)),
+        };
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        if page_ids.len() != pages.len() {
+            return Err(Error::internal(format!(
+                "Output has {} pages but {} were expected",
+                page_ids.len(),
+                pages.len()
+            )));
+        }
+        self.write_info(&mut doc);
+        self.write_outline(&mut doc, &page_ids, pages)?;
+        self.write_labels(&mut doc, pages)?;
+        match doc.save(output) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::io(
+                format!("Couldn't write metadata to {}", output.display()),
+                e,
+            )),
+        }
+    }
+
+    /// Sets the fields of the document information dictionary.
+    fn write_info(&self, doc: &mut Document) {
+        let id = match doc.trailer.get(b"Info").and_then(Object::as_reference) {
+            Ok(x) if doc.get_dictionary(x).is_ok() => x,
+            _ => {
+                let id = doc.add_object(Dictionary::new());
+                doc.trailer.set("Info", id);
+                id
+            }
+        };
+        let dict = doc.get_dictionary_mut(id).unwrap();
+        for (key, value) in self.info.fields().iter() {
+            if let Some(x) = value {
+                dict.set(key.to_vec(), text_string(x));
+            }
+        }
+    }
+
+    /// Builds the outline, entries point to the first output page of their target page. Entries
+    /// whose target page isn't part of the output are left out.
+    fn write_outline(
+        &self,
+        doc: &mut Document,
+        page_ids: &[ObjectId],
+        pages: &[usize],
+    ) -> Result<(), Error> {
+        let targets: HashMap<usize, ObjectId> = pages
+            .iter()
+            .zip(page_ids.iter())
+            .rev()
+            .map(|(page, id)| (*page, *id))
+            .collect();
+        let items = map_items(&self.outline, &targets);
+        if items.is_empty() {
+            return Ok(());
+        }
+        let root = doc.new_object_id();
+        let (first, last) = add_items(doc, &items, root);
+        doc.objects.insert(
+            root,
+            Object::Dictionary(dictionary! {
+                "Type" => "Outlines",
+                "First" => first,
+                "Last" => last,
+                "Count" => items.len() as i64,
+            }),
+        );
+        catalog_mut(doc)?.set("Outlines", root);
+        Ok(())
+    }
+
+    /// Builds the page labels. Pages without a label are numbered by their position.
+    fn write_labels(&self, doc: &mut Document, pages: &[usize]) -> Result<(), Error> {
+        if self.labels.is_empty() {
+            return Ok(());
+        }
+        let mut nums = vec![];
+        let mut previous: Option<(usize, Option<&Label>)> = None;
+        for (n, page) in pages.iter().enumerate() {
+            let label = self.labels.get(*page).and_then(|x| x.as_ref());
+            let continues = match (previous, label) {
+                (Some((x, Some(a))), Some(b)) => {
+                    (x == *page || x + 1 == *page)
+                        && a.range == b.range
+                        && a.style == b.style
+                        && a.prefix == b.prefix
+                }
+                (Some((_, None)), None) => true,
+                _ => false,
+            };
+            previous = Some((*page, label));
+            if continues {
+                continue;
+            }
+            let mut dict = Dictionary::new();
+            match label {
+                Some(x) => {
+                    if let Some(style) = &x.style {
+                        dict.set("S", Object::Name(style.clone()));
+                    }
+                    if let Some(prefix) = &x.prefix {
+                        dict.set("P", Object::String(prefix.clone(), StringFormat::Literal));
+                    }
+                    if x.value != 1 {
+                        dict.set("St", x.value);
+                    }
+                }
+                None => {
+                    dict.set("S", "D");
+                    dict.set("St", n as i64 + 1);
+                }
+            }
+            nums.push(Object::Integer(n as i64));
+            nums.push(Object::Dictionary(dict));
+        }
+        catalog_mut(doc)?.set(
+            "PageLabels",
+            dictionary! {
+                "Nums" => nums,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Reads the fields of the document information dictionary.
+fn read_info(doc: &Document) -> Info {
+    let dict = match doc
+        .trailer
+        .get(b"Info")
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_dict())
+    {
+        Ok(x) => x,
+        Err(_) => return Info::default(),
+    };
+    let field = |key: &[u8]| {
+        dict.get(key)
+            .and_then(|x| doc.dereference(x))
+            .and_then(|(_, x)| x.as_str())
+            .map(decode_text)
+            .ok()
+            .filter(|x| !x.trim().is_empty())
+    };
+    Info {
+        title: field(b"Title"),
+        author: field(b"Author"),
+        subject: field(b"Subject"),
+        keywords: field(b"Keywords"),
+    }
+}
+
+/// Reads the outline of a document.
+fn read_outline(doc: &Document, pages: &HashMap<ObjectId, usize>) -> Vec<OutlineItem> {
+    match doc
+        .catalog()
+        .and_then(|x| x.get(b"Outlines"))
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_dict())
+    {
+        Ok(x) => read_items(doc, pages, x, 0),
+        Err(_) => vec![],
+    }
+}
+
+/// Reads the children of an outline entry.
+fn read_items(
+    doc: &Document,
+    pages: &HashMap<ObjectId, usize>,
+    parent: &Dictionary,
+    depth: usize,
+) -> Vec<OutlineItem> {
+    let mut rsl = vec![];
+    if depth > MAX_DEPTH {
+        return rsl;
+    }
+    let mut seen = HashSet::new();
+    let mut next = parent.get(b"First").and_then(Object::as_reference).ok();
+    // A cyclic list of entries ends at the first entry visited twice.
+    while let Some(id) = next.filter(|x| seen.insert(*x)) {
+        let item = match doc.get_dictionary(id) {
+            Ok(x) => x,
+            Err(_) => break,
+        };
+        rsl.push(OutlineItem {
+            title: item
+                .get(b"Title")
+                .and_then(|x| doc.dereference(x))
+                .and_then(|(_, x)| x.as_str())
+                .map(decode_text)
+                .unwrap_or_default(),
+            page: item_page(doc, pages, item),
+            children: read_items(doc, pages, item, depth + 1),
+        });
+        next = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+    rsl
+}
+
+/// Returns the index of the target page of an outline entry. The target is either given as
+/// destination or as GoTo action.
+fn item_page(doc: &Document, pages: &HashMap<ObjectId, usize>, item: &Dictionary) -> Option<usize> {
+    let dest = match item.get(b"Dest") {
+        Ok(x) => x,
+        Err(_) => {
+            let action = doc
+                .dereference(item.get(b"A").ok()?)
+                .ok()?
+                .1
+                .as_dict()
+                .ok()?;
+            if action.get(b"S").and_then(Object::as_name).ok()? != b"GoTo" {
+                return None;
+            }
+            action.get(b"D").ok()?
+        }
+    };
+    dest_page(doc, pages, dest, 0)
+}
+
+/// Resolves a destination (explicit or named) to the index of its page.
+fn dest_page(
+    doc: &Document,
+    pages: &HashMap<ObjectId, usize>,
+    dest: &Object,
+    depth: usize,
+) -> Option<usize> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    match doc.dereference(dest).ok()?.1 {
+        Object::Array(x) => pages.get(&x.first()?.as_reference().ok()?).copied(),
+        Object::Dictionary(x) => dest_page(doc, pages, x.get(b"D").ok()?, depth + 1),
+        Object::Name(x) => {
+            let dests = doc
+                .dereference(doc.catalog().ok()?.get(b"Dests").ok()?)
+                .ok()?;
+            dest_page(doc, pages, dests.1.as_dict().ok()?.get(x).ok()?, depth + 1)
+        }
+        Object::String(x, _) => {
+            let names = doc
+                .dereference(doc.catalog().ok()?.get(b"Names").ok()?)
+                .ok()?;
+            let tree = doc
+                .dereference(names.1.as_dict().ok()?.get(b"Dests").ok()?)
+                .ok()?;
+            let target = find_name(doc, tree.1.as_dict().ok()?, x, 0)?;
+            dest_page(doc, pages, target, depth + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Looks up a key in a name tree.
+fn find_name<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    key: &[u8],
+    depth: usize,
+) -> Option<&'a Object> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    if let Ok(names) = node
+        .get(b"Names")
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_array())
+    {
+        for pair in names.chunks(2) {
+            if let [name, value] = pair {
+                if doc.dereference(name).and_then(|(_, x)| x.as_str()).ok() == Some(key) {
+                    return Some(value);
+                }
+            }
+        }
+    }
+    let kids = node
+        .get(b"Kids")
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_array())
+        .ok()?;
+    kids.iter().find_map(|x| {
+        let kid = doc.dereference(x).ok()?.1.as_dict().ok()?;
+        find_name(doc, kid, key, depth + 1)
+    })
+}
+
+/// Reads the page labels of a document, the result contains the label of each page.
+fn read_labels(doc: &Document, pages: usize) -> Vec<Option<Label>> {
+    let nums = match doc
+        .catalog()
+        .and_then(|x| x.get(b"PageLabels"))
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_dict())
+    {
+        Ok(x) => collect_nums(doc, x, 0),
+        Err(_) => return vec![],
+    };
+    let mut ranges: Vec<(usize, &Dictionary)> = nums
+        .into_iter()
+        .filter_map(|(start, x)| Some((start, doc.dereference(x).ok()?.1.as_dict().ok()?)))
+        .collect();
+    ranges.sort_by_key(|(start, _)| *start);
+    let mut rsl = vec![None; pages];
+    for (n, (start, dict)) in ranges.iter().enumerate() {
+        let end = match ranges.get(n + 1) {
+            Some((x, _)) => (*x).min(pages),
+            None => pages,
+        };
+        let first = dict.get(b"St").and_then(Object::as_i64).unwrap_or(1);
+        for (page, label) in rsl.iter_mut().enumerate().take(end).skip(*start) {
+            *label = Some(Label {
+                style: dict
+                    .get(b"S")
+                    .and_then(Object::as_name)
+                    .ok()
+                    .map(|x| x.to_vec()),
+                prefix: dict
+                    .get(b"P")
+                    .and_then(|x| doc.dereference(x))
+                    .and_then(|(_, x)| x.as_str())
+                    .ok()
+                    .map(|x| x.to_vec()),
+                value: first + (page - start) as i64,
+                range: *start,
+            });
+        }
+    }
+    rsl
+}
+
+/// Collects the entries of a number tree.
+fn collect_nums<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    depth: usize,
+) -> Vec<(usize, &'a Object)> {
+    let mut rsl = vec![];
+    if depth > MAX_DEPTH {
+        return rsl;
+    }
+    if let Ok(nums) = node
+        .get(b"Nums")
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_array())
+    {
+        for pair in nums.chunks(2) {
+            if let [key, value] = pair {
+                if let Ok(x) = key.as_i64() {
+                    rsl.push((x.max(0) as usize, value));
+                }
+            }
+        }
+    }
+    if let Ok(kids) = node
+        .get(b"Kids")
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_array())
+    {
+        for kid in kids {
+            if let Ok((_, Object::Dictionary(x))) = doc.dereference(kid) {
+                rsl.extend(collect_nums(doc, x, depth + 1));
+            }
+        }
+    }
+    rsl
+}
+
+/// Moves the target pages of an entry and its children by the given offset.
+fn shift_item(item: &mut OutlineItem, offset: usize) {
+    item.page = item.page.map(|x| x + offset);
+    for child in item.children.iter_mut() {
+        shift_item(child, offset);
+    }
+}
+
+/// An outline entry whose target is resolved to a page of the output.
+struct MappedItem<'a> {
+    /// Title of the entry.
+    title: &'a str,
+    /// The target page within the output.
+    page: Option<ObjectId>,
+    /// Nested entries.
+    children: Vec<MappedItem<'a>>,
+}
+
+/// Maps the targets of the entries to the pages of the output. Entries without a target page in
+/// the output and without children are removed.
+fn map_items<'a>(
+    items: &'a [OutlineItem],
+    targets: &HashMap<usize, ObjectId>,
+) -> Vec<MappedItem<'a>> {
+    items
+        .iter()
+        .filter_map(|x| {
+            let page = x.page.and_then(|x| targets.get(&x).copied());
+            let children = map_items(&x.children, targets);
+            match (x.page, page) {
+                (Some(_), None) if children.is_empty() => None,
+                _ => Some(MappedItem {
+                    title: &x.title,
+                    page,
+                    children,
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Adds the entries as children of the given parent. Returns the first and the last entry.
+fn add_items(doc: &mut Document, items: &[MappedItem], parent: ObjectId) -> (ObjectId, ObjectId) {
+    let ids: Vec<ObjectId> = items.iter().map(|_| doc.new_object_id()).collect();
+    for (n, item) in items.iter().enumerate() {
+        let mut dict = dictionary! {
+            "Title" => text_string(item.title),
+            "Parent" => parent,
+        };
+        if let Some(page) = item.page {
+            dict.set("Dest", vec![page.into(), "Fit".into()]);
+        }
+        if n > 0 {
+            dict.set("Prev", ids[n - 1]);
+        }
+        if n + 1 < ids.len() {
+            dict.set("Next", ids[n + 1]);
+        }
+        if !item.children.is_empty() {
+            let (first, last) = add_items(doc, &item.children, ids[n]);
+            dict.set("First", first);
+            dict.set("Last", last);
+            // A negative count closes the entry.
+            dict.set("Count", -(item.children.len() as i64));
+        }
+        doc.objects.insert(ids[n], Object::Dictionary(dict));
+    }
+    (ids[0], ids[ids.len() - 1])
+}
+
+/// Returns the catalog of a document for modification.
+fn catalog_mut(doc: &mut Document) -> Result<&mut Dictionary, Error> {
+    match doc.catalog_mut() {
+        Ok(x) => Ok(x),
+        Err(e) => Err(Error::parse(format!(
+            "Couldn't find catalog of output, {}",
+            e
+        ))),
+    }
+}
+
+/// Decodes a PDF text string, either UTF-16BE with a byte order mark or PDFDocEncoding. The
+/// latter is treated as Latin-1 which matches for all printable characters but a few symbols.
+pub fn decode_text(bytes: &[u8]) -> String {
+    match bytes {
+        [0xfe, 0xff, rest @ ..] => {
+            let units: Vec<u16> = rest
+                .chunks(2)
+                .map(|x| u16::from_be_bytes([x[0], *x.get(1).unwrap_or(&0)]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => bytes.iter().map(|x| *x as char).collect(),
+    }
+}
+
+/// Encodes a PDF text string, non-ASCII text is written as UTF-16BE.
+pub fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+    let mut bytes = vec![0xfe, 0xff];
+    bytes.extend(text.encode_utf16().flat_map(|x| x.to_be_bytes()));
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Converts an error of the PDF library.
+pub fn pdf_error(context: String, error: lopdf::Error) -> Error {
+    match error {
+        lopdf::Error::IO(x) => Error::io(context, x),
+        x => Error::parse(format!("{}, {}", context, x)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use tempfile::TempDir;
+
+    /// Returns a document with the given number of empty pages and the ids of the pages.
+    fn document(pages: usize) -> (Document, Vec<ObjectId>) {
+        let mut doc = Document::with_version("1.5");
+        let tree = doc.new_object_id();
+        let ids: Vec<ObjectId> = (0..pages)
+            .map(|_| doc.add_object(dictionary! {"Type" => "Page", "Parent" => tree}))
+            .collect();
+        doc.objects.insert(
+            tree,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => ids.iter().map(|x| Object::from(*x)).collect::<Vec<Object>>(),
+                "Count" => pages as i64,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog = doc.add_object(dictionary! {"Type" => "Catalog", "Pages" => tree});
+        doc.trailer.set("Root", catalog);
+        (doc, ids)
+    }
+
+    fn save(folder: &TempDir, name: &str, mut doc: Document) -> PathBuf {
+        let path = folder.path().join(name);
+        doc.save(&path).unwrap();
+        path
+    }
+
+    /// Writes the metadata into an output with one page per entry of `pages` and reads it back.
+    fn write(folder: &TempDir, metadata: &Metadata, pages: &[usize]) -> Document {
+        let (doc, _) = document(pages.len());
+        let output = save(folder, "output.pdf", doc);
+        metadata.write(&output, pages).unwrap();
+        Document::load(&output).unwrap()
+    }
+
+    /// Returns the titles of the outline entries with the index of their target page.
+    fn outline(doc: &Document) -> Vec<(String, Option<usize>, usize)> {
+        fn walk(
+            items: &[OutlineItem],
+            depth: usize,
+            rsl: &mut Vec<(String, Option<usize>, usize)>,
+        ) {
+            for item in items {
+                rsl.push((item.title.clone(), item.page, depth));
+                walk(&item.children, depth + 1, rsl);
+            }
+        }
+        let pages: HashMap<ObjectId, usize> = doc
+            .get_pages()
+            .into_iter()
+            .map(|(n, id)| (id, n as usize - 1))
+            .collect();
+        let mut rsl = vec![];
+        walk(&read_outline(doc, &pages), 0, &mut rsl);
+        rsl
+    }
+
+    /// Returns the page label ranges of a document as start and style, prefix and first value.
+    fn labels(doc: &Document) -> Vec<(i64, String)> {
+        let catalog = doc.catalog().unwrap();
+        let labels = catalog.get(b"PageLabels").unwrap().as_dict().unwrap();
+        let nums = labels.get(b"Nums").unwrap().as_array().unwrap();
+        nums.chunks(2)
+            .map(|x| {
+                let dict = x[1].as_dict().unwrap();
+                let style = dict.get(b"S").and_then(Object::as_name).unwrap_or(b"");
+                let prefix = dict.get(b"P").and_then(Object::as_str).unwrap_or(b"");
+                let first = dict.get(b"St").and_then(Object::as_i64).unwrap_or(1);
+                (
+                    x[0].as_i64().unwrap(),
+                    format!(
+                        "{}/{}/{}",
+                        String::from_utf8_lossy(style),
+                        String::from_utf8_lossy(prefix),
+                        first
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns an input of three pages with an outline: A (page 1), B (page 2) with the child
+    /// B1 (page 3) and C (page 3).
+    fn outlined(folder: &TempDir) -> Metadata {
+        let (mut doc, pages) = document(3);
+        let root = doc.new_object_id();
+        let ids: Vec<ObjectId> = (0..4).map(|_| doc.new_object_id()).collect();
+        let item = |title: &str, page: usize| {
+            dictionary! {
+                "Title" => Object::string_literal(title),
+                "Parent" => root,
+                "Dest" => vec![pages[page].into(), "Fit".into()],
+            }
+        };
+        let mut a = item("A", 0);
+        a.set("Next", ids[1]);
+        let mut b = item("B", 1);
+        b.set("Prev", ids[0]);
+        b.set("Next", ids[3]);
+        b.set("First", ids[2]);
+        b.set("Last", ids[2]);
+        let mut b1 = item("B1", 2);
+        b1.set("Parent", ids[1]);
+        // The target of C is given as GoTo action.
+        let c = dictionary! {
+            "Title" => Object::string_literal("C"),
+            "Parent" => root,
+            "Prev" => ids[1],
+            "A" => dictionary! {"S" => "GoTo", "D" => vec![pages[2].into(), "Fit".into()]},
+        };
+        for (id, dict) in ids.iter().zip([a, b, b1, c]) {
+            doc.objects.insert(*id, Object::Dictionary(dict));
+        }
+        doc.objects.insert(
+            root,
+            Object::Dictionary(
+                dictionary! {"Type" => "Outlines", "First" => ids[0], "Last" => ids[3]},
+            ),
+        );
+        doc.catalog_mut().unwrap().set("Outlines", root);
+        let input = save(folder, "input.pdf", doc);
+        Metadata::read(&input, None).unwrap()
+    }
+
+    #[test]
+    fn outline_of_split_pages() {
+        let folder = tempfile::tempdir().unwrap();
+        let metadata = outlined(&folder);
+        let doc = write(&folder, &metadata, &[0, 0, 1, 1, 2, 2]);
+        assert_eq!(
+            outline(&doc),
+            vec![
+                ("A".to_string(), Some(0), 0),
+                ("B".to_string(), Some(2), 0),
+                ("B1".to_string(), Some(4), 1),
+                ("C".to_string(), Some(4), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn outline_of_dropped_pages() {
+        let folder = tempfile::tempdir().unwrap();
+        let metadata = outlined(&folder);
+        // B stays without target as it has a child, A is removed.
+        let doc = write(&folder, &metadata, &[1, 2]);
+        assert_eq!(
+            outline(&doc),
+            vec![
+                ("B".to_string(), Some(0), 0),
+                ("B1".to_string(), Some(1), 1),
+                ("C".to_string(), Some(1), 0),
+            ]
+        );
+        let doc = write(&folder, &metadata, &[0, 2]);
+        assert_eq!(
+            outline(&doc),
+            vec![
+                ("A".to_string(), Some(0), 0),
+                ("B".to_string(), None, 0),
+                ("B1".to_string(), Some(1), 1),
+                ("C".to_string(), Some(1), 0),
+            ]
+        );
+    }
+
+    /// Returns an input of six pages, the first two are numbered with roman numerals, the others
+    /// are numbered A-1 to A-4.
+    fn labeled(folder: &TempDir) -> Metadata {
+        let (mut doc, _) = document(6);
+        doc.catalog_mut().unwrap().set(
+            "PageLabels",
+            dictionary! {
+                "Nums" => vec![
+                    0.into(),
+                    dictionary! {"S" => "r"}.into(),
+                    2.into(),
+                    dictionary! {"S" => "D", "P" => Object::string_literal("A-")}.into(),
+                ],
+            },
+        );
+        let input = save(folder, "input.pdf", doc);
+        Metadata::read(&input, None).unwrap()
+    }
+
+    #[test]
+    fn labels_of_dropped_pages() {
+        let folder = tempfile::tempdir().unwrap();
+        let metadata = labeled(&folder);
+        let doc = write(&folder, &metadata, &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(
+            labels(&doc),
+            vec![(0, "r//1".to_string()), (2, "D/A-/1".to_string())]
+        );
+        // A-2 is left out, the following pages keep their labels.
+        let doc = write(&folder, &metadata, &[1, 2, 4, 5]);
+        assert_eq!(
+            labels(&doc),
+            vec![
+                (0, "r//2".to_string()),
+                (1, "D/A-/1".to_string()),
+                (2, "D/A-/3".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_of_split_pages() {
+        let folder = tempfile::tempdir().unwrap();
+        let metadata = labeled(&folder);
+        // Both halves of a page continue the range.
+        let doc = write(&folder, &metadata, &[0, 0, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(
+            labels(&doc),
+            vec![(0, "r//1".to_string()), (4, "D/A-/1".to_string())]
+        );
+    }
+
+    #[test]
+    fn labels_of_appended_documents() {
+        let folder = tempfile::tempdir().unwrap();
+        let (doc, _) = document(2);
+        let input = save(&folder, "plain.pdf", doc);
+        let mut metadata = Metadata::read(&input, None).unwrap();
+        metadata.append(labeled(&folder), 2);
+        // The pages of the document without labels are numbered by their position.
+        let doc = write(&folder, &metadata, &[0, 1, 2, 3, 4]);
+        assert_eq!(
+            labels(&doc),
+            vec![
+                (0, "D//1".to_string()),
+                (2, "r//1".to_string()),
+                (4, "D/A-/1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn info_round_trip() {
+        let folder = tempfile::tempdir().unwrap();
+        let (mut doc, _) = document(1);
+        let info = doc.add_object(dictionary! {
+            "Title" => text_string("Übersicht – Teil 1"),
+            "Author" => Object::String(b"Andr\xe9".to_vec(), StringFormat::Literal),
+            "Subject" => Object::string_literal("  "),
+        });
+        doc.trailer.set("Info", info);
+        let input = save(&folder, "input.pdf", doc);
+        let metadata = Metadata::read(&input, None).unwrap();
+        assert_eq!(
+            metadata.info,
+            Info {
+                title: Some("Übersicht – Teil 1".to_string()),
+                author: Some("André".to_string()),
+                subject: None,
+                keywords: None,
+            }
+        );
+        let doc = write(&folder, &metadata, &[0]);
+        assert_eq!(read_info(&doc), metadata.info);
+    }
+
+    #[test]
+    fn text_strings() {
+        assert_eq!(
+            text_string("plain"),
+            Object::String(b"plain".to_vec(), StringFormat::Literal)
+        );
+        for text in ["Übersicht", "日本語", "emoji 📄", "é"] {
+            let encoded = text_string(text);
+            let bytes = encoded.as_str().unwrap();
+            assert_eq!(&bytes[..2], &[0xfe, 0xff]);
+            assert_eq!(decode_text(bytes), text);
+        }
+        // PDFDocEncoding matches Latin-1 for the printable characters.
+        assert_eq!(decode_text(b"Caf\xe9 \xfcber"), "Café über");
+        // A missing byte of the last unit doesn't fail.
+        assert_eq!(decode_text(&[0xfe, 0xff, 0x00, 0x41, 0x00]), "A\u{0}");
+    }
+}
@@ -0,0 +1,132 @@
+/// Only a part of the input can be processed by selecting pages with ranges like `3-10,15,20-`.
+/// The pages are numbered starting at one across all input files. A range without an end (`20-`)
+/// reaches to the last page, a range without a start (`-5`) starts at the first page.
+use crate::error::Error;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A selection of pages given as a list of ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRanges {
+    /// The ranges with the first and (optionally) the last page number, both inclusive.
+    ranges: Vec<(usize, Option<usize>)>,
+}
+
+impl PageRanges {
+    /// Returns whether the page with the given number (starting at one) is selected.
+    pub fn contains(&self, number: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|(start, end)| number >= *start && end.is_none_or(|x| number <= x))
+    }
+
+    /// Checks whether all ranges lie within a document with the given number of pages.
+    pub fn check(&self, pages: usize) -> Result<(), Error> {
+        match self.ranges.iter().find(|(start, _)| *start > pages) {
+            Some(x) => Err(Error::invalid_input(format!(
+                "The page range {} is outside of the document with {} pages",
+                format_range(*x),
+                pages
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for PageRanges {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |x: &str| {
+            Error::invalid_input(format!(
+                "Invalid page range {}, has to be a list of pages and ranges like 3-10,15,20-",
+                x
+            ))
+        };
+        let number = |x: &str, part: &str| match x.trim().parse::<usize>() {
+            Ok(0) | Err(_) => Err(invalid(part)),
+            Ok(x) => Ok(x),
+        };
+        let mut ranges = vec![];
+        for part in s.split(',').map(|x| x.trim()) {
+            let range = match part.split_once('-') {
+                Some((start, end)) => (
+                    match start.trim() {
+                        "" => 1,
+                        x => number(x, part)?,
+                    },
+                    match end.trim() {
+                        "" => None,
+                        x => Some(number(x, part)?),
+                    },
+                ),
+                None => {
+                    let x = number(part, part)?;
+                    (x, Some(x))
+                }
+            };
+            if let (start, Some(end)) = range {
+                if start > end {
+                    return Err(invalid(part));
+                }
+            }
+            ranges.push(range);
+        }
+        Ok(Self { ranges })
+    }
+}
+
+impl fmt::Display for PageRanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self.ranges.iter().map(|x| format_range(*x)).collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+/// Formats a single range like it was given.
+fn format_range(range: (usize, Option<usize>)) -> String {
+    match range {
+        (start, Some(end)) if start == end => start.to_string(),
+        (start, Some(end)) => format!("{}-{}", start, end),
+        (start, None) => format!("{}-", start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(s: &str) -> PageRanges {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_pages_and_ranges() {
+        let x = ranges("3-10, 15,20-");
+        assert_eq!(x.ranges, vec![(3, Some(10)), (15, Some(15)), (20, None)]);
+        assert_eq!(x.to_string(), "3-10,15,20-");
+        assert_eq!(ranges("-5").ranges, vec![(1, Some(5))]);
+    }
+
+    #[test]
+    fn parse_invalid_ranges() {
+        for x in ["", "0", "5-3", "a", "1-b", "3,,4", "1-2-3"] {
+            assert!(x.parse::<PageRanges>().is_err(), "{} was accepted", x);
+        }
+    }
+
+    #[test]
+    fn contains_selected_pages() {
+        let x = ranges("2-3,5,8-");
+        let selected: Vec<usize> = (1..=10).filter(|n| x.contains(*n)).collect();
+        assert_eq!(selected, vec![2, 3, 5, 8, 9, 10]);
+    }
+
+    #[test]
+    fn check_against_page_count() {
+        assert!(ranges("2-3,5").check(5).is_ok());
+        assert!(ranges("2-30").check(5).is_ok());
+        assert!(ranges("2,6-").check(5).is_err());
+    }
+}
@@ -1,23 +1,617 @@
-use crate::error::ErrorMessage;
+use crate::binary::Tool;
+use crate::error::Error;
+use crate::metadata::pdf_error;
 use crate::run::Run;
 use crate::util;
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-/// Name of the pdfunite binary.
-const PDFUNITE_BINARY: &str = "pdfunite";
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, Stream};
 
-/// Unites the PDF-files into one file.
-pub fn unite(run: &Run) -> Result<(), ErrorMessage> {
+/// Unites the PDF-files into one file. The files are combined in the given order.
+pub fn unite(run: &Run, inputs: &[PathBuf]) -> Result<(), Error> {
     run.log_step("Combine PDF");
 
-    let mut cmd = Command::new(PDFUNITE_BINARY);
-    let mut inputs: Vec<_> = run.query_files("d_")?;
-    inputs.sort();
+    // pdfunite needs at least two input files.
+    if inputs.len() == 1 {
+        if let Err(e) = fs::copy(&inputs[0], run.output_path()) {
+            return Err(Error::io(
+                format!(
+                    "Couldn't copy {} to {}",
+                    inputs[0].display(),
+                    run.output_path().display()
+                ),
+                e,
+            ));
+        }
+        run.wait();
+        return Ok(());
+    }
+
+    let mut cmd = run.command(Tool::Pdfunite);
     cmd.args(inputs);
     cmd.arg(run.output_path());
 
-    util::run_cmd(cmd, PDFUNITE_BINARY)?;
+    util::run_cmd(cmd, run.executable(Tool::Pdfunite))?;
     run.wait();
     Ok(())
 }
+
+/// Extracts a single page (index starting at zero) of a document into a new PDF file. The page is
+/// taken over untouched. pdfseparate can't open encrypted documents, their pages are rewritten
+/// with pdftocairo instead which accepts the password.
+pub fn extract_page(
+    run: &Run,
+    document: &Path,
+    encrypted: bool,
+    page: usize,
+    output: &Path,
+) -> Result<(), Error> {
+    let tool = match encrypted {
+        true => Tool::Pdftocairo,
+        false => Tool::Pdfseparate,
+    };
+    let mut cmd = run.command(tool);
+    if tool == Tool::Pdftocairo {
+        add_password(run, &mut cmd);
+        cmd.arg("-pdf");
+    }
+    cmd.arg("-f")
+        .arg((page + 1).to_string())
+        .arg("-l")
+        .arg((page + 1).to_string())
+        .arg(document)
+        .arg(output);
+    debug!(
+        "extract page {} of {} with {}",
+        page + 1,
+        document.display(),
+        tool
+    );
+    util::run_cmd(cmd, run.executable(tool))
+}
+
+/// Returns whether a document is encrypted.
+pub fn is_encrypted(run: &Run, document: &Path) -> Result<bool, Error> {
+    let mut cmd = run.command(Tool::Pdfinfo);
+    add_password(run, &mut cmd);
+    cmd.arg(document);
+    let info = util::cmd_output(cmd, run.executable(Tool::Pdfinfo))?;
+    Ok(info.lines().any(|x| {
+        let mut parts = x.split_whitespace();
+        parts.next() == Some("Encrypted:") && parts.next() == Some("yes")
+    }))
+}
+
+/// Returns the pages (index starting at zero) of a document which already contain text. This is
+/// the case if pdftotext extracts anything but whitespace from them. The whole document is
+/// extracted at once, pdftotext ends each page with a form feed.
+pub fn text_pages(run: &Run, document: &Path) -> Result<BTreeSet<usize>, Error> {
+    let mut cmd = run.command(Tool::Pdftotext);
+    add_password(run, &mut cmd);
+    cmd.arg("-enc").arg("UTF-8").arg(document).arg("-");
+    let out = util::cmd_output(cmd, run.executable(Tool::Pdftotext))?;
+    Ok(pages_with_text(&out))
+}
+
+/// Returns the pages of the output of pdftotext which contain anything but whitespace.
+fn pages_with_text(text: &str) -> BTreeSet<usize> {
+    text.split('\x0c')
+        .enumerate()
+        .filter(|(_, x)| x.chars().any(|x| !x.is_whitespace()))
+        .map(|(n, _)| n)
+        .collect()
+}
+
+/// Text rendering mode which neither fills nor strokes the glyphs.
+const INVISIBLE: i64 = 3;
+
+/// Maximal nesting of forms which is followed when looking for text.
+const MAX_FORM_DEPTH: usize = 8;
+
+/// The kinds of text shown by the content of a page.
+#[derive(Debug, Default, PartialEq)]
+struct Text {
+    visible: bool,
+    invisible: bool,
+}
+
+/// Returns the pages (index starting at zero) of a document whose text is entirely invisible.
+/// This is the text layer OCR software puts over the scanned image. The content of the pages and
+/// of the forms they use is checked, content which can't be read counts as visible text.
+pub fn invisible_text_pages(
+    document: &Path,
+    password: Option<&str>,
+) -> Result<BTreeSet<usize>, Error> {
+    let mut doc = match Document::load(document) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(pdf_error(
+                format!("Couldn't read {}", document.display()),
+                e,
+            ))
+        }
+    };
+    if doc.is_encrypted() {
+        if let Err(e) = doc.decrypt(password.unwrap_or_default()) {
+            return Err(pdf_error(
+                format!("Couldn't decrypt {}", document.display()),
+                e,
+            ));
+        }
+    }
+    let mut rsl = BTreeSet::new();
+    for (number, id) in doc.get_pages() {
+        let mut text = Text::default();
+        match doc.get_page_content(id) {
+            Ok(content) => {
+                let (direct, ids) = doc.get_page_resources(id);
+                let resources: Vec<&Dictionary> = direct
+                    .into_iter()
+                    .chain(ids.into_iter().filter_map(|x| doc.get_dictionary(x).ok()))
+                    .collect();
+                scan_text(&doc, &content, &resources, 0, 0, &mut text);
+            }
+            Err(_) => text.visible = true,
+        }
+        if text.invisible && !text.visible {
+            rsl.insert(number as usize - 1);
+        }
+    }
+    Ok(rsl)
+}
+
+/// Records the kinds of text shown by a content stream. The forms it draws are followed, they
+/// start with the text rendering mode in effect when they are drawn.
+fn scan_text(
+    doc: &Document,
+    content: &[u8],
+    resources: &[&Dictionary],
+    mode: i64,
+    depth: usize,
+    text: &mut Text,
+) {
+    let content = match Content::decode(content) {
+        Ok(x) => x,
+        Err(_) => {
+            text.visible = true;
+            return;
+        }
+    };
+    let mut mode = mode;
+    let mut saved = vec![];
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "q" => saved.push(mode),
+            "Q" => mode = saved.pop().unwrap_or(mode),
+            "Tr" => {
+                if let Some(x) = operation.operands.first().and_then(|x| x.as_i64().ok()) {
+                    mode = x;
+                }
+            }
+            "Tj" | "TJ" | "'" | "\"" => match mode == INVISIBLE {
+                true => text.invisible = true,
+                false => text.visible = true,
+            },
+            "Do" if depth < MAX_FORM_DEPTH => {
+                let name = match operation.operands.first().and_then(|x| x.as_name().ok()) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let form = match find_form(doc, resources, name) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let content = form
+                    .decompressed_content()
+                    .unwrap_or_else(|_| form.content.clone());
+                let own = form
+                    .dict
+                    .get(b"Resources")
+                    .and_then(|x| doc.dereference(x))
+                    .and_then(|(_, x)| x.as_dict());
+                match own {
+                    Ok(x) => scan_text(doc, &content, &[x], mode, depth + 1, text),
+                    Err(_) => scan_text(doc, &content, resources, mode, depth + 1, text),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the form XObject with the given name within the resources.
+fn find_form<'a>(
+    doc: &'a Document,
+    resources: &[&'a Dictionary],
+    name: &[u8],
+) -> Option<&'a Stream> {
+    resources.iter().find_map(|resources| {
+        let xobjects = resources.get(b"XObject").ok()?;
+        let (_, xobjects) = doc.dereference(xobjects).ok()?;
+        let (_, object) = doc
+            .dereference(xobjects.as_dict().ok()?.get(name).ok()?)
+            .ok()?;
+        match object {
+            Object::Stream(x)
+                if x.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Form") =>
+            {
+                Some(x)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Tolerance when comparing the size of an embedded image with the size of its page.
+const SIZE_TOLERANCE: f64 = 0.02;
+
+/// The images embedded in a page.
+#[derive(Debug, Clone, Copy)]
+pub struct PageImage {
+    /// Native resolution in DPI of the largest image of the page.
+    pub resolution: u64,
+    /// Whether the largest image is bilevel (one bit per pixel).
+    pub bilevel: bool,
+    /// Whether the largest image covers the whole page, other content may be drawn over it.
+    pub covers: bool,
+    /// Whether the page consists of this single image covering the whole page. Pages with
+    /// rotation, masks, non-square pixels or unusual color spaces never do.
+    pub full_page: bool,
+}
+
+/// An entry in the image list of pdfimages.
+struct ListedImage<'a> {
+    kind: &'a str,
+    width: f64,
+    height: f64,
+    color: &'a str,
+    bpc: &'a str,
+    /// Resolution, None if pdfimages couldn't determine it (`-` in the list).
+    ppi: Option<(f64, f64)>,
+}
+
+/// Returns the images of the pages (index starting at zero) of a document. Pages without images
+/// are missing.
+pub fn page_images(run: &Run, document: &Path) -> Result<BTreeMap<usize, PageImage>, Error> {
+    let mut cmd = run.command(Tool::Pdfimages);
+    add_password(run, &mut cmd);
+    cmd.arg("-list").arg(document);
+    let list = util::cmd_output(cmd, run.executable(Tool::Pdfimages))?;
+
+    let mut cmd = run.command(Tool::Pdfinfo);
+    add_password(run, &mut cmd);
+    cmd.arg("-f").arg("1").arg("-l").arg("-1").arg(document);
+    let info = util::cmd_output(cmd, run.executable(Tool::Pdfinfo))?;
+    Ok(parse_page_images(&list, &info))
+}
+
+/// Determines the images of the pages from the image list of pdfimages and the page sizes stated
+/// by pdfinfo.
+fn parse_page_images(list: &str, info: &str) -> BTreeMap<usize, PageImage> {
+    // Columns: page num type width height color comp bpc enc interp object ID x-ppi y-ppi size
+    // ratio. The list starts after a line of dashes.
+    let mut images: BTreeMap<usize, Vec<ListedImage>> = BTreeMap::new();
+    for line in list.lines().skip_while(|x| !x.starts_with("---")).skip(1) {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.len() < 14 {
+            continue;
+        }
+        if let (Ok(page), Ok(width), Ok(height)) = (
+            columns[0].parse::<usize>(),
+            columns[3].parse::<f64>(),
+            columns[4].parse::<f64>(),
+        ) {
+            let ppi = match (columns[12].parse::<f64>(), columns[13].parse::<f64>()) {
+                (Ok(x), Ok(y)) if x > 0.0 && y > 0.0 => Some((x, y)),
+                _ => None,
+            };
+            images.entry(page).or_default().push(ListedImage {
+                kind: columns[2],
+                width,
+                height,
+                color: columns[5],
+                bpc: columns[7],
+                ppi,
+            });
+        }
+    }
+    let sizes = page_sizes(info);
+
+    let mut rsl = BTreeMap::new();
+    for (page, items) in images {
+        let largest = items
+            .iter()
+            .filter_map(|x| match (x.kind, x.ppi) {
+                ("image", Some(ppi)) => Some((x, ppi)),
+                _ => None,
+            })
+            .max_by(|(a, _), (b, _)| (a.width * a.height).total_cmp(&(b.width * b.height)));
+        let (image, (xppi, yppi)) = match largest {
+            Some(x) => x,
+            None => continue,
+        };
+        let fits = |a: f64, b: f64| (a - b).abs() <= b * SIZE_TOLERANCE;
+        let (image_width, image_height) = (image.width / xppi * 72.0, image.height / yppi * 72.0);
+        let covers = match sizes.get(&page) {
+            Some((width, height, _)) => {
+                (fits(image_width, *width) && fits(image_height, *height))
+                    || (fits(image_width, *height) && fits(image_height, *width))
+            }
+            None => false,
+        };
+        let full_page = match sizes.get(&page) {
+            Some((width, height, 0)) => {
+                items.len() == 1
+                    && ["gray", "rgb", "icc", "index"].contains(&image.color)
+                    && fits(xppi, yppi)
+                    && fits(image_width, *width)
+                    && fits(image_height, *height)
+            }
+            _ => false,
+        };
+        rsl.insert(
+            page - 1,
+            PageImage {
+                resolution: xppi.max(yppi).round() as u64,
+                bilevel: image.bpc == "1",
+                covers,
+                full_page,
+            },
+        );
+    }
+    rsl
+}
+
+/// Extracts the image of a page (index starting at zero) without altering it. JPEG images are
+/// taken over as they are, all others are saved as TIFF. Returns the path of the image.
+pub fn extract_image(
+    run: &Run,
+    document: &Path,
+    page: usize,
+    prefix: &str,
+) -> Result<PathBuf, Error> {
+    let mut cmd = run.command(Tool::Pdfimages);
+    add_password(run, &mut cmd);
+    cmd.arg("-j")
+        .arg("-tiff")
+        .arg("-f")
+        .arg((page + 1).to_string())
+        .arg("-l")
+        .arg((page + 1).to_string())
+        .arg(document)
+        .arg(run.prepend_with_temp_folder(prefix));
+    debug!(
+        "extract image of page {} of {}",
+        page + 1,
+        document.display()
+    );
+    util::run_cmd(cmd, run.executable(Tool::Pdfimages))?;
+    let prefix = format!("{}-", prefix);
+    match run.query_files(&prefix)?.into_iter().next() {
+        Some(x) => Ok(x),
+        None => Err(Error::parse(format!(
+            "pdfimages didn't extract the image of page {} of {}",
+            page + 1,
+            document.display()
+        ))),
+    }
+}
+
+/// Returns the sizes in points (width and height) of all pages of an unencrypted document as they
+/// are displayed, that is with their rotation applied.
+pub fn displayed_page_sizes(run: &Run, document: &Path) -> Result<Vec<(f64, f64)>, Error> {
+    let mut cmd = run.command(Tool::Pdfinfo);
+    cmd.arg("-f").arg("1").arg("-l").arg("-1").arg(document);
+    let info = util::cmd_output(cmd, run.executable(Tool::Pdfinfo))?;
+    Ok(page_sizes(&info)
+        .into_values()
+        .map(|(width, height, rotation)| match rotation % 180 {
+            90 => (height, width),
+            _ => (width, height),
+        })
+        .collect())
+}
+
+/// Parses the sizes (in points) and rotations of the pages as stated by pdfinfo.
+fn page_sizes(info: &str) -> BTreeMap<usize, (f64, f64, u64)> {
+    let mut rsl = BTreeMap::new();
+    let mut rotations = BTreeMap::new();
+    for line in info.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["Page", page, "size:", width, "x", height, ..] => {
+                if let (Ok(p), Ok(w), Ok(h)) = (page.parse(), width.parse(), height.parse()) {
+                    rsl.insert(p, (w, h, 0));
+                }
+            }
+            ["Page", page, "rot:", rotation] => {
+                if let (Ok(p), Ok(r)) = (page.parse::<usize>(), rotation.parse::<u64>()) {
+                    rotations.insert(p, r);
+                }
+            }
+            _ => {}
+        }
+    }
+    for (page, rotation) in rotations {
+        if let Some(x) = rsl.get_mut(&page) {
+            x.2 = rotation;
+        }
+    }
+    rsl
+}
+
+/// Adds the password of the input documents to a Poppler command.
+fn add_password(run: &Run, cmd: &mut Command) {
+    if let Some(x) = run.password() {
+        cmd.arg("-opw").arg(x).arg("-upw").arg(x);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lopdf::dictionary;
+
+    /// Output of `pdfimages -list` (poppler 22.02).
+    const IMAGE_LIST: &str = "\
+page   num  type   width height color comp bpc  enc interp  object ID x-ppi y-ppi size ratio
+--------------------------------------------------------------------------------------------
+   1     0 image    2480  3508  gray    1   8  jpeg   no        10  0   300   300  612K 7.2%
+   2     1 image    1654  2339  rgb     3   8  jpeg   no        15  0   200   200  301K 2.7%
+   3     2 image    2480  3508  gray    1   1  ccitt  no        20  0   300   300 43.2K 4.0%
+   3     3 smask    2480  3508  gray    1   8  image  no        21  0   300   300 12.1K 0.1%
+   4     4 image     800   600  rgb     3   8  jpeg   no        25  0   150   150 80.0K 5.7%
+   4     5 image    1000   400  rgb     3   8  jpeg   no        26  0   144   144 20.0K 1.7%
+   6     6 image       1     1  gray    1   8  image  no        30  0     -     -    1B 100%
+   7     7 image    2480  3508  gray    1   8  jpeg   no        31  0   300   300  612K 7.2%
+   7     8 image       1     1  gray    1   8  image  no        32  0     -     -    1B 100%
+   8     9 image    2480  3508  cmyk    4   8  jpeg   no        35  0   300   300  812K 2.4%
+";
+
+    /// Output of `pdfinfo -f 1 -l -1` (poppler 22.02), page 2 is rotated.
+    const INFO: &str = "\
+Producer:        ScanSoft
+Tagged:          no
+Encrypted:       no
+Pages:           8
+Page    1 size: 595.276 x 841.89 pts (A4)
+Page    1 rot:  0
+Page    2 size: 595.276 x 841.89 pts (A4)
+Page    2 rot:  90
+Page    3 size: 595.276 x 841.89 pts (A4)
+Page    3 rot:  0
+Page    4 size: 595.276 x 841.89 pts (A4)
+Page    4 rot:  0
+Page    5 size: 612 x 792 pts (letter)
+Page    5 rot:  0
+Page    6 size: 595.276 x 841.89 pts (A4)
+Page    6 rot:  0
+Page    7 size: 595.276 x 841.89 pts (A4)
+Page    7 rot:  0
+Page    8 size: 595.276 x 841.89 pts (A4)
+Page    8 rot:  0
+File size:       4710123 bytes
+PDF version:     1.4
+";
+
+    #[test]
+    fn images_of_pages() {
+        let images = parse_page_images(IMAGE_LIST, INFO);
+        let summary: Vec<(usize, u64, bool, bool, bool)> = images
+            .iter()
+            .map(|(page, x)| (*page, x.resolution, x.bilevel, x.covers, x.full_page))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                // A single image covering the page.
+                (0, 300, false, true, true),
+                // The page is rotated.
+                (1, 200, false, true, false),
+                // The image has a soft mask.
+                (2, 300, true, true, false),
+                // The largest of two images, it doesn't cover the page.
+                (3, 150, false, false, false),
+                // Page 5 has no images, the resolution of the only image of page 6 is unknown.
+                // A full page image next to an image of unknown resolution.
+                (6, 300, false, true, false),
+                // Unusual color space.
+                (7, 300, false, true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn sizes_of_pages() {
+        let sizes = page_sizes(INFO);
+        assert_eq!(sizes.len(), 8);
+        assert_eq!(sizes[&1], (595.276, 841.89, 0));
+        assert_eq!(sizes[&2], (595.276, 841.89, 90));
+        assert_eq!(sizes[&5], (612.0, 792.0, 0));
+        assert!(parse_page_images("", INFO).is_empty());
+        assert!(page_sizes("").is_empty());
+    }
+
+    #[test]
+    fn text_of_pages() {
+        assert_eq!(
+            pages_with_text("a\n\x0c\x0c \n\x0c\n b\n\x0c"),
+            BTreeSet::from([0, 3])
+        );
+        assert_eq!(pages_with_text("\x0c\x0c"), BTreeSet::new());
+        assert_eq!(pages_with_text(""), BTreeSet::new());
+    }
+
+    /// Writes a document whose pages have the given content, all pages share a form XObject
+    /// with the given content.
+    fn document(path: &Path, pages: &[&str], form: &str) {
+        let mut doc = Document::with_version("1.5");
+        let tree = doc.new_object_id();
+        let form = doc.add_object(Stream::new(
+            dictionary! {"Type" => "XObject", "Subtype" => "Form", "BBox" => vec![0.into(), 0.into(), 10.into(), 10.into()]},
+            form.as_bytes().to_vec(),
+        ));
+        let resources = doc.add_object(dictionary! {"XObject" => dictionary! {"Fm0" => form}});
+        let kids: Vec<Object> = pages
+            .iter()
+            .map(|content| {
+                let content =
+                    doc.add_object(Stream::new(dictionary! {}, content.as_bytes().to_vec()));
+                doc.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => tree,
+                    "Contents" => content,
+                })
+                .into()
+            })
+            .collect();
+        doc.objects.insert(
+            tree,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => pages.len() as i64,
+                "Resources" => resources,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog = doc.add_object(dictionary! {"Type" => "Catalog", "Pages" => tree});
+        doc.trailer.set("Root", catalog);
+        doc.save(path).unwrap();
+    }
+
+    #[test]
+    fn invisible_text() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("doc.pdf");
+        let text = "BT /F1 12 Tf (text) Tj ET";
+        let invisible = "BT 3 Tr /F1 12 Tf (text) Tj ET";
+        let pages = [
+            // Visible text.
+            text,
+            // Invisible text.
+            invisible,
+            // Without text.
+            "0 0 10 10 re f",
+            // Invisible text within a form.
+            "q 3 Tr /Fm0 Do Q",
+            // The rendering mode is restored with the graphics state.
+            "q 3 Tr BT (a) Tj ET Q BT (b) Tj ET",
+            // Visible and invisible text within the form.
+            "/Fm0 Do BT 3 Tr (a) Tj ET",
+            // Invisible text with the other showing operators.
+            "BT 3 Tr [(a) 10 (b)] TJ (c) ' 1 2 (d) \" ET",
+        ];
+        document(&path, &pages, "BT (form) Tj ET");
+        assert_eq!(
+            invisible_text_pages(&path, None).unwrap(),
+            BTreeSet::from([1, 3, 6])
+        );
+    }
+}
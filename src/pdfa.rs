@@ -0,0 +1,1017 @@
+/// Converts the output into a PDF/A-2b document. The PDF files of Tesseract are close to PDF/A
+/// already (the fonts are embedded, the images use device independent or gray colour spaces) but
+/// the combined document lacks the XMP metadata, the output intent and the document id. These are
+/// added afterwards. Pages which were taken over untouched can contain anything, thus the whole
+/// document is searched for the common features which are prohibited by PDF/A and can't be
+/// fixed, in which case the conversion fails with the list of the found problems. This isn't a
+/// validation, the output isn't guaranteed to be conform.
+use crate::error::Error;
+use crate::metadata::{decode_text, pdf_error, text_string};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::str;
+
+use lopdf::content::Content;
+use lopdf::xref::XrefType;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+/// Name of the output condition, the colour space of the output intent.
+const OUTPUT_CONDITION: &str = "sRGB IEC61966-2.1";
+
+/// Actions which aren't allowed in PDF/A-2.
+const FORBIDDEN_ACTIONS: [&[u8]; 11] = [
+    b"Launch",
+    b"Sound",
+    b"Movie",
+    b"ResetForm",
+    b"ImportData",
+    b"Hide",
+    b"SetOCGState",
+    b"Rendition",
+    b"Trans",
+    b"GoTo3DView",
+    b"JavaScript",
+];
+
+/// Named actions which are allowed in PDF/A-2.
+const ALLOWED_NAMED_ACTIONS: [&[u8]; 4] = [b"NextPage", b"PrevPage", b"FirstPage", b"LastPage"];
+
+/// Annotation types which aren't allowed in PDF/A-2b (file attachments would have to be PDF/A
+/// themselves, which can't be verified).
+const FORBIDDEN_ANNOTATIONS: [&[u8]; 5] = [b"3D", b"Sound", b"Screen", b"Movie", b"FileAttachment"];
+
+/// Comment following the header which marks the file as binary, PDF/A requires at least four
+/// bytes above 127.
+const BINARY_COMMENT: &[u8] = b"%\xe2\xe3\xcf\xd3\n";
+
+/// Annotation flag of annotations of unknown types which aren't displayed.
+const ANNOTATION_INVISIBLE: i64 = 1;
+/// Annotation flag of annotations which are neither displayed nor printed.
+const ANNOTATION_HIDDEN: i64 = 2;
+/// Annotation flag of annotations which are printed, required by PDF/A.
+const ANNOTATION_PRINT: i64 = 4;
+/// Annotation flag of annotations which are printed but not displayed.
+const ANNOTATION_NO_VIEW: i64 = 32;
+/// Annotation flag which inverts the no view flag on certain events.
+const ANNOTATION_TOGGLE_NO_VIEW: i64 = 256;
+
+/// Changes which bring an object in line with PDF/A.
+enum Fix {
+    /// The image is interpolated, the flag has to be removed.
+    Interpolate(ObjectId),
+    /// The stream is compressed with LZW, it's compressed again with Flate.
+    Lzw(ObjectId),
+    /// The annotation lacks the print flag.
+    Print(ObjectId),
+}
+
+/// Converts the document at the given path into PDF/A-2b in place.
+pub fn convert(output: &Path) -> Result<(), Error> {
+    let bytes = match fs::read(output) {
+        Ok(x) => x,
+        Err(e) => return Err(Error::io(format!("Couldn't read {}", output.display()), e)),
+    };
+    let mut doc = match Document::load_mem(&bytes) {
+        Ok(x) => x,
+        Err(e) => return Err(pdf_error(format!("Couldn't read {}", output.display()), e)),
+    };
+
+    let mut issues = BTreeSet::new();
+    let mut fixes = Vec::new();
+    check_document(&doc, &mut issues);
+    for (id, object) in doc.objects.iter() {
+        check_object(&doc, *id, object, &mut issues, &mut fixes);
+    }
+    for (number, id) in doc.get_pages() {
+        check_page(&doc, number, id, &mut issues, &mut fixes);
+    }
+    if !issues.is_empty() {
+        return Err(Error::PdfA(issues.into_iter().collect()));
+    }
+    for fix in fixes {
+        apply(&mut doc, fix)?;
+    }
+
+    let now = OffsetDateTime::now_utc();
+    let info = write_info(&mut doc, &now);
+    let metadata = doc.add_object(
+        Stream::new(
+            dictionary! {
+                "Type" => "Metadata",
+                "Subtype" => "XML",
+            },
+            xmp(&info, &now).into_bytes(),
+        )
+        .with_compression(false),
+    );
+    let mut profile = Stream::new(dictionary! { "N" => 3 }, srgb_profile());
+    if let Err(e) = profile.compress() {
+        return Err(pdf_error(
+            String::from("Couldn't compress the ICC profile"),
+            e,
+        ));
+    }
+    let profile = doc.add_object(profile);
+    let intent = dictionary! {
+        "Type" => "OutputIntent",
+        "S" => "GTS_PDFA1",
+        "OutputConditionIdentifier" => Object::string_literal(OUTPUT_CONDITION),
+        "Info" => Object::string_literal(OUTPUT_CONDITION),
+        "DestOutputProfile" => profile,
+    };
+    match doc.catalog_mut() {
+        Ok(catalog) => {
+            catalog.set("Metadata", metadata);
+            catalog.set("OutputIntents", vec![Object::Dictionary(intent)]);
+        }
+        Err(e) => return Err(pdf_error(String::from("Couldn't find the catalog"), e)),
+    }
+    if doc.trailer.get(b"ID").is_err() {
+        let hash = Sha256::digest(&bytes)[..16].to_vec();
+        doc.trailer.set(
+            "ID",
+            vec![
+                Object::String(hash.clone(), StringFormat::Hexadecimal),
+                Object::String(hash, StringFormat::Hexadecimal),
+            ],
+        );
+    }
+    // The cross reference table is needed to insert the binary comment afterwards.
+    doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+    let mut written = Vec::new();
+    if let Err(e) = doc.save_to(&mut written) {
+        return Err(Error::io(
+            format!("Couldn't write PDF/A to {}", output.display()),
+            e,
+        ));
+    }
+    let written = add_binary_comment(written)?;
+    match fs::write(output, written) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::io(
+            format!("Couldn't write PDF/A to {}", output.display()),
+            e,
+        )),
+    }
+}
+
+/// Inserts the binary comment after the header of a written document. lopdf has no way to write
+/// it, so the offsets in the cross reference table and of the table itself are moved by the
+/// length of the comment.
+fn add_binary_comment(mut pdf: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::internal("Couldn't find the cross reference table of the PDF/A");
+    let shift = BINARY_COMMENT.len();
+
+    let marker = b"startxref\n";
+    let start = match pdf.windows(marker.len()).rposition(|x| x == marker) {
+        Some(x) => x + marker.len(),
+        None => return Err(invalid()),
+    };
+    let end = start
+        + pdf[start..]
+            .iter()
+            .take_while(|x| x.is_ascii_digit())
+            .count();
+    let xref: usize = match str::from_utf8(&pdf[start..end]).map(|x| x.parse()) {
+        Ok(Ok(x)) => x,
+        _ => return Err(invalid()),
+    };
+    if !pdf[xref..].starts_with(b"xref\n") {
+        return Err(invalid());
+    }
+
+    // Each subsection starts with the first object number and the number of entries, the
+    // entries have a fixed length of 20 bytes with the offset in the first 10 bytes.
+    let mut i = xref + b"xref\n".len();
+    while !pdf[i..].starts_with(b"trailer") {
+        let line_end = match pdf[i..].iter().position(|x| *x == b'\n') {
+            Some(x) => i + x,
+            None => return Err(invalid()),
+        };
+        let count: usize = match str::from_utf8(&pdf[i..line_end])
+            .ok()
+            .and_then(|x| x.split_whitespace().nth(1))
+            .and_then(|x| x.parse().ok())
+        {
+            Some(x) => x,
+            None => return Err(invalid()),
+        };
+        i = line_end + 1;
+        for _ in 0..count {
+            let entry = match pdf.get_mut(i..i + 20) {
+                Some(x) => x,
+                None => return Err(invalid()),
+            };
+            if entry[17] == b'n' {
+                let offset: usize = match str::from_utf8(&entry[..10]).map(|x| x.parse()) {
+                    Ok(Ok(x)) => x,
+                    _ => return Err(invalid()),
+                };
+                entry[..10].copy_from_slice(format!("{:010}", offset + shift).as_bytes());
+            }
+            i += 20;
+        }
+    }
+    pdf.splice(start..end, (xref + shift).to_string().into_bytes());
+
+    let header = match pdf.iter().position(|x| *x == b'\n') {
+        Some(x) => x + 1,
+        None => return Err(invalid()),
+    };
+    pdf.splice(header..header, BINARY_COMMENT.iter().copied());
+    Ok(pdf)
+}
+
+/// Checks the trailer and the catalog.
+fn check_document(doc: &Document, issues: &mut BTreeSet<String>) {
+    if doc.trailer.get(b"Encrypt").is_ok() {
+        issues.insert(String::from("the document is encrypted"));
+    }
+    let catalog = match doc.catalog() {
+        Ok(x) => x,
+        Err(_) => {
+            issues.insert(String::from("the document has no catalog"));
+            return;
+        }
+    };
+    if let Some(names) = resolve_dict(doc, catalog.get(b"Names").ok()) {
+        if names.has(b"EmbeddedFiles") {
+            issues.insert(String::from("the document contains embedded files"));
+        }
+        if names.has(b"JavaScript") {
+            issues.insert(String::from("the document contains JavaScript"));
+        }
+    }
+    if let Some(form) = resolve_dict(doc, catalog.get(b"AcroForm").ok()) {
+        if form.has(b"XFA") {
+            issues.insert(String::from("the document contains an XFA form"));
+        }
+        if let Ok(true) = form.get(b"NeedAppearances").and_then(Object::as_bool) {
+            issues.insert(String::from(
+                "the form fields of the document have no appearance",
+            ));
+        }
+    }
+}
+
+/// Checks an object and the dictionaries nested within it.
+fn check_object(
+    doc: &Document,
+    id: ObjectId,
+    object: &Object,
+    issues: &mut BTreeSet<String>,
+    fixes: &mut Vec<Fix>,
+) {
+    if let Object::Stream(stream) = object {
+        let dict = &stream.dict;
+        if dict.has(b"F") || dict.has(b"FFilter") || dict.has(b"FDecodeParms") {
+            issues.insert(String::from("the document references external streams"));
+        }
+        match dict.get(b"Filter") {
+            Ok(Object::Name(x)) if x == b"LZWDecode" => fixes.push(Fix::Lzw(id)),
+            Ok(Object::Array(x)) if x.iter().any(|x| is_name(x, b"LZWDecode")) => {
+                issues.insert(String::from("the document contains LZW compressed streams"));
+            }
+            _ => {}
+        }
+        if is_name_of(dict, b"Subtype", b"Image") {
+            if let Ok(true) = dict.get(b"Interpolate").and_then(Object::as_bool) {
+                fixes.push(Fix::Interpolate(id));
+            }
+        }
+        if is_name_of(dict, b"Subtype", b"Form") {
+            if let Ok(content) = stream.decompressed_content() {
+                check_content(&content, "a form", issues);
+            }
+        }
+    }
+    visit(object, &mut |dict| check_dictionary(doc, dict, issues));
+}
+
+/// Checks a single dictionary for prohibited features.
+fn check_dictionary(doc: &Document, dict: &Dictionary, issues: &mut BTreeSet<String>) {
+    if dict.has(b"AA") {
+        issues.insert(String::from("the document contains additional actions"));
+    }
+    if let Ok(Object::Name(action)) = dict.get(b"S") {
+        if FORBIDDEN_ACTIONS.contains(&action.as_slice()) {
+            issues.insert(format!(
+                "the document contains {} actions",
+                String::from_utf8_lossy(action)
+            ));
+        }
+        if action == b"Named" {
+            let allowed = match dict.get(b"N") {
+                Ok(Object::Name(x)) => ALLOWED_NAMED_ACTIONS.contains(&x.as_slice()),
+                _ => false,
+            };
+            if !allowed {
+                issues.insert(String::from(
+                    "the document contains prohibited named actions",
+                ));
+            }
+        }
+    }
+    if dict.has(b"TR") || dict.get(b"TR2").is_ok_and(|x| !is_name(x, b"Default")) {
+        issues.insert(String::from("the document uses transfer functions"));
+    }
+    if dict.has(b"OPI") || dict.has(b"Alternates") {
+        issues.insert(String::from(
+            "the document contains alternate or OPI images",
+        ));
+    }
+    if is_name_of(dict, b"Subtype", b"PS") {
+        issues.insert(String::from("the document contains PostScript"));
+    }
+    if is_name_of(dict, b"Subtype", b"Form") && dict.has(b"Ref") {
+        issues.insert(String::from("the document references external pages"));
+    }
+    if dict.iter().any(|(_, x)| uses_cmyk(x)) {
+        issues.insert(String::from(
+            "the document uses the DeviceCMYK colour space which doesn't match the sRGB output intent",
+        ));
+    }
+    if is_name_of(dict, b"Type", b"Font") {
+        check_font(doc, dict, issues);
+    }
+}
+
+/// Checks whether the font program of a font is embedded.
+fn check_font(doc: &Document, font: &Dictionary, issues: &mut BTreeSet<String>) {
+    // Type 3 fonts are defined within the document, the descendants of composite fonts are
+    // checked on their own.
+    if is_name_of(font, b"Subtype", b"Type3") || is_name_of(font, b"Subtype", b"Type0") {
+        return;
+    }
+    let embedded = resolve_dict(doc, font.get(b"FontDescriptor").ok())
+        .is_some_and(|x| x.has(b"FontFile") || x.has(b"FontFile2") || x.has(b"FontFile3"));
+    if !embedded {
+        let name = match font.get(b"BaseFont") {
+            Ok(Object::Name(x)) => String::from_utf8_lossy(x).into_owned(),
+            _ => String::from("unknown"),
+        };
+        issues.insert(format!("the font {} isn't embedded", name));
+    }
+}
+
+/// Checks the content and the annotations of a page.
+fn check_page(
+    doc: &Document,
+    number: u32,
+    id: ObjectId,
+    issues: &mut BTreeSet<String>,
+    fixes: &mut Vec<Fix>,
+) {
+    if let Ok(content) = doc.get_page_content(id) {
+        check_content(&content, &format!("page {}", number), issues);
+    }
+    let page = match doc.get_dictionary(id) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let annotations = match page.get(b"Annots").and_then(|x| doc.dereference(x)) {
+        Ok((_, Object::Array(x))) => x,
+        _ => return,
+    };
+    for annotation in annotations {
+        let (annotation_id, dict) = match doc.dereference(annotation) {
+            Ok((id, Object::Dictionary(x))) => (id, x),
+            _ => continue,
+        };
+        let subtype = match dict.get(b"Subtype") {
+            Ok(Object::Name(x)) => x.as_slice(),
+            _ => b"",
+        };
+        if FORBIDDEN_ANNOTATIONS.contains(&subtype) {
+            issues.insert(format!(
+                "page {} contains {} annotations",
+                number,
+                String::from_utf8_lossy(subtype)
+            ));
+            continue;
+        }
+        if subtype == b"Popup" {
+            continue;
+        }
+        let flags = dict.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+        let hidden = ANNOTATION_INVISIBLE
+            | ANNOTATION_HIDDEN
+            | ANNOTATION_NO_VIEW
+            | ANNOTATION_TOGGLE_NO_VIEW;
+        if flags & hidden != 0 {
+            issues.insert(format!("page {} contains hidden annotations", number));
+        } else if flags & ANNOTATION_PRINT == 0 {
+            match annotation_id {
+                Some(x) => fixes.push(Fix::Print(x)),
+                None => {
+                    issues.insert(format!(
+                        "page {} contains annotations which aren't printed",
+                        number
+                    ));
+                }
+            }
+        }
+        let appearance = resolve_dict(doc, dict.get(b"AP").ok()).is_some_and(|x| x.has(b"N"));
+        if subtype != b"Link" && !appearance {
+            issues.insert(format!(
+                "page {} contains annotations without appearance",
+                number
+            ));
+        }
+    }
+}
+
+/// Checks a content stream for operators which use the DeviceCMYK colour space.
+fn check_content(content: &[u8], location: &str, issues: &mut BTreeSet<String>) {
+    let content = match Content::decode(content) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(
+                "Couldn't check the content of {} for PDF/A, {}",
+                location, e
+            );
+            return;
+        }
+    };
+    if content
+        .operations
+        .iter()
+        .any(|x| x.operator == "k" || x.operator == "K")
+    {
+        issues.insert(format!(
+            "{} uses the DeviceCMYK colour space which doesn't match the sRGB output intent",
+            location
+        ));
+    }
+}
+
+/// Applies a fix to the document.
+fn apply(doc: &mut Document, fix: Fix) -> Result<(), Error> {
+    let id = match &fix {
+        Fix::Interpolate(x) | Fix::Lzw(x) | Fix::Print(x) => *x,
+    };
+    let object = match doc.get_object_mut(id) {
+        Ok(x) => x,
+        Err(e) => return Err(pdf_error(format!("Couldn't find object {:?}", id), e)),
+    };
+    match (fix, object) {
+        (Fix::Interpolate(_), Object::Stream(stream)) => {
+            stream.dict.remove(b"Interpolate");
+        }
+        (Fix::Lzw(_), Object::Stream(stream)) => {
+            let content = match stream.decompressed_content() {
+                Ok(x) => x,
+                Err(e) => return Err(pdf_error(String::from("Couldn't decode LZW stream"), e)),
+            };
+            stream.set_plain_content(content);
+            if let Err(e) = stream.compress() {
+                return Err(pdf_error(String::from("Couldn't compress stream"), e));
+            }
+        }
+        (Fix::Print(_), Object::Dictionary(dict)) => {
+            let flags = dict.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+            dict.set("F", flags | ANNOTATION_PRINT);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The fields of the document information dictionary which are mirrored in the XMP metadata.
+#[derive(Default)]
+struct Info {
+    title: Option<String>,
+    author: Option<String>,
+    subject: Option<String>,
+    keywords: Option<String>,
+    creator: Option<String>,
+    producer: String,
+}
+
+/// Updates the document information dictionary and returns its fields. The dates are set to the
+/// current time and pdfiron becomes the producer.
+fn write_info(doc: &mut Document, now: &OffsetDateTime) -> Info {
+    let id = match doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(x) if doc.get_dictionary(x).is_ok() => x,
+        _ => {
+            let id = doc.add_object(Dictionary::new());
+            doc.trailer.set("Info", id);
+            id
+        }
+    };
+    let dict = doc.get_dictionary_mut(id).unwrap();
+    let text = |key: &[u8]| match dict.get(key) {
+        Ok(Object::String(x, _)) => Some(decode_text(x)).filter(|x| !x.is_empty()),
+        _ => None,
+    };
+    let info = Info {
+        title: text(b"Title"),
+        author: text(b"Author"),
+        subject: text(b"Subject"),
+        keywords: text(b"Keywords"),
+        creator: text(b"Creator"),
+        producer: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+    };
+    let date = Object::string_literal(format!(
+        "D:{:04}{:02}{:02}{:02}{:02}{:02}+00'00'",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    ));
+    // Entries without a counterpart in the XMP metadata are removed, empty ones aren't allowed.
+    dict.remove(b"Trapped");
+    for key in [
+        &b"Title"[..],
+        b"Author",
+        b"Subject",
+        b"Keywords",
+        b"Creator",
+    ] {
+        if dict
+            .get(key)
+            .is_ok_and(|x| !matches!(x, Object::String(x, _) if !x.is_empty()))
+        {
+            dict.remove(key);
+        }
+    }
+    dict.set("Producer", text_string(&info.producer));
+    dict.set("CreationDate", date.clone());
+    dict.set("ModDate", date);
+    info
+}
+
+/// Returns the XMP metadata with the PDF/A identification and the fields of the document
+/// information dictionary.
+fn xmp(info: &Info, now: &OffsetDateTime) -> String {
+    let date = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    let mut fields = String::new();
+    if let Some(x) = &info.title {
+        fields.push_str(&format!(
+            "   <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+            escape(x)
+        ));
+    }
+    if let Some(x) = &info.author {
+        fields.push_str(&format!(
+            "   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+            escape(x)
+        ));
+    }
+    if let Some(x) = &info.subject {
+        fields.push_str(&format!(
+            "   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+            escape(x)
+        ));
+    }
+    if let Some(x) = &info.keywords {
+        fields.push_str(&format!("   <pdf:Keywords>{}</pdf:Keywords>\n", escape(x)));
+    }
+    if let Some(x) = &info.creator {
+        fields.push_str(&format!(
+            "   <xmp:CreatorTool>{}</xmp:CreatorTool>\n",
+            escape(x)
+        ));
+    }
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"
+    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"
+    xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"
+    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"
+    xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">
+   <pdfaid:part>2</pdfaid:part>
+   <pdfaid:conformance>B</pdfaid:conformance>
+   <dc:format>application/pdf</dc:format>
+{}   <pdf:Producer>{}</pdf:Producer>
+   <xmp:CreateDate>{}</xmp:CreateDate>
+   <xmp:ModifyDate>{}</xmp:ModifyDate>
+   <xmp:MetadataDate>{}</xmp:MetadataDate>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>",
+        fields,
+        escape(&info.producer),
+        date,
+        date,
+        date
+    )
+}
+
+/// Escapes a text for the use in XML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns an ICC version 2 display profile of the sRGB colour space. The colorants are adapted
+/// to the D50 illuminant of the profile connection space, the tone curve is sampled.
+fn srgb_profile() -> Vec<u8> {
+    fn s15(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+    fn xyz(values: [f64; 3]) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        values.iter().for_each(|x| tag.extend(s15(*x)));
+        tag
+    }
+
+    let description = "sRGB IEC61966-2.1";
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    desc.extend((description.len() as u32 + 1).to_be_bytes());
+    desc.extend(description.as_bytes());
+    // Terminating null, empty Unicode and ScriptCode descriptions.
+    desc.extend([0; 1 + 4 + 4 + 2 + 1 + 67]);
+    let mut copyright = b"text\0\0\0\0".to_vec();
+    copyright.extend(b"No copyright, use freely\0");
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    let samples = 1024;
+    curve.extend((samples as u32).to_be_bytes());
+    for i in 0..samples {
+        let v = i as f64 / (samples - 1) as f64;
+        let linear = match v <= 0.04045 {
+            true => v / 12.92,
+            false => ((v + 0.055) / 1.055).powf(2.4),
+        };
+        curve.extend(((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", desc),
+        (b"cprt", copyright),
+        (b"wtpt", xyz([0.9642, 1.0, 0.8249])),
+        (b"rXYZ", xyz([0.4361, 0.2225, 0.0139])),
+        (b"gXYZ", xyz([0.3851, 0.7169, 0.0971])),
+        (b"bXYZ", xyz([0.1431, 0.0606, 0.7141])),
+        (b"rTRC", curve),
+    ];
+    // The green and blue tone curves share the data of the red one.
+    let count = tags.len() + 2;
+    let mut table = (count as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let mut offset = 128 + 4 + 12 * count;
+    let mut curve_entry = (0, 0);
+    for (signature, tag) in tags.iter() {
+        table.extend(*signature);
+        table.extend((offset as u32).to_be_bytes());
+        table.extend((tag.len() as u32).to_be_bytes());
+        if *signature == b"rTRC" {
+            curve_entry = (offset, tag.len());
+        }
+        data.extend(tag);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        offset = 128 + 4 + 12 * count + data.len();
+    }
+    for signature in [b"gTRC", b"bTRC"] {
+        table.extend(signature);
+        table.extend((curve_entry.0 as u32).to_be_bytes());
+        table.extend((curve_entry.1 as u32).to_be_bytes());
+    }
+
+    let size = 128 + table.len() + data.len();
+    let mut header = vec![0; 128];
+    header[0..4].copy_from_slice(&(size as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[24..36].copy_from_slice(&[0x07, 0xe4, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0]);
+    header[36..40].copy_from_slice(b"acsp");
+    header[68..72].copy_from_slice(&s15(0.9642));
+    header[72..76].copy_from_slice(&s15(1.0));
+    header[76..80].copy_from_slice(&s15(0.8249));
+
+    let mut profile = header;
+    profile.extend(table);
+    profile.extend(data);
+    profile
+}
+
+/// Calls the closure for the dictionary of the object and all dictionaries nested within it.
+/// References aren't followed, the referenced objects are visited on their own.
+fn visit<F: FnMut(&Dictionary)>(object: &Object, f: &mut F) {
+    match object {
+        Object::Dictionary(x) => {
+            f(x);
+            x.iter().for_each(|(_, x)| visit(x, f));
+        }
+        Object::Stream(x) => {
+            f(&x.dict);
+            x.dict.iter().for_each(|(_, x)| visit(x, f));
+        }
+        Object::Array(x) => x.iter().for_each(|x| visit(x, f)),
+        _ => {}
+    }
+}
+
+/// Returns whether an object is the DeviceCMYK colour space or an array containing it.
+fn uses_cmyk(object: &Object) -> bool {
+    match object {
+        Object::Name(x) => x == b"DeviceCMYK",
+        Object::Array(x) => x.iter().any(uses_cmyk),
+        _ => false,
+    }
+}
+
+/// Resolves an optional reference to a dictionary.
+fn resolve_dict<'a>(doc: &'a Document, object: Option<&'a Object>) -> Option<&'a Dictionary> {
+    match doc.dereference(object?) {
+        Ok((_, Object::Dictionary(x))) => Some(x),
+        _ => None,
+    }
+}
+
+/// Returns whether an object is the name with the given bytes.
+fn is_name(object: &Object, name: &[u8]) -> bool {
+    matches!(object, Object::Name(x) if x == name)
+}
+
+/// Returns whether the value of a key in a dictionary is the name with the given bytes.
+fn is_name_of(dict: &Dictionary, key: &[u8], name: &[u8]) -> bool {
+    dict.get(key).is_ok_and(|x| is_name(x, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+
+    use tempfile::TempDir;
+
+    /// Returns a document with a single empty page and the ids of the page and the catalog.
+    fn document() -> (Document, ObjectId, ObjectId) {
+        let mut doc = Document::with_version("1.7");
+        let pages = doc.new_object_id();
+        let page = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        doc.objects.insert(
+            pages,
+            Object::Dictionary(
+                dictionary! {"Type" => "Pages", "Kids" => vec![page.into()], "Count" => 1},
+            ),
+        );
+        let catalog = doc.add_object(dictionary! {"Type" => "Catalog", "Pages" => pages});
+        doc.trailer.set("Root", catalog);
+        (doc, page, catalog)
+    }
+
+    /// Converts the document and returns the found problems.
+    fn problems(folder: &TempDir, mut doc: Document) -> Vec<String> {
+        let path = folder.path().join("output.pdf");
+        doc.save(&path).unwrap();
+        match convert(&path) {
+            Err(Error::PdfA(x)) => x,
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    fn be32(bytes: &[u8], offset: usize) -> usize {
+        u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn icc_profile_layout() {
+        let profile = srgb_profile();
+        assert_eq!(be32(&profile, 0), profile.len());
+        assert_eq!(&profile[12..20], b"mntrRGB ");
+        assert_eq!(&profile[36..40], b"acsp");
+
+        let count = be32(&profile, 128);
+        let mut tags = HashMap::new();
+        for n in 0..count {
+            let entry = 132 + n * 12;
+            let (offset, size) = (be32(&profile, entry + 4), be32(&profile, entry + 8));
+            assert_eq!(offset % 4, 0);
+            assert!(offset >= 132 + count * 12 && offset + size <= profile.len());
+            tags.insert(&profile[entry..entry + 4], &profile[offset..offset + size]);
+        }
+        let signatures: BTreeSet<&[u8]> = tags.keys().copied().collect();
+        assert_eq!(
+            signatures,
+            BTreeSet::from([
+                &b"bTRC"[..],
+                b"bXYZ",
+                b"cprt",
+                b"desc",
+                b"gTRC",
+                b"gXYZ",
+                b"rTRC",
+                b"rXYZ",
+                b"wtpt"
+            ])
+        );
+
+        let desc = tags[&b"desc"[..]];
+        assert_eq!(&desc[..4], b"desc");
+        let length = be32(desc, 8);
+        assert_eq!(&desc[12..12 + length], b"sRGB IEC61966-2.1\0");
+
+        // D50 in s15Fixed16.
+        let wtpt = tags[&b"wtpt"[..]];
+        assert_eq!(&wtpt[..4], b"XYZ ");
+        assert_eq!(be32(wtpt, 8), 0xf6d6);
+        assert_eq!(be32(wtpt, 12), 0x10000);
+        assert_eq!(be32(wtpt, 16), 0xd32d);
+
+        let curve = tags[&b"rTRC"[..]];
+        assert_eq!(&curve[..4], b"curv");
+        let samples = be32(curve, 8);
+        assert_eq!(curve.len(), 12 + 2 * samples);
+        let sample = |n: usize| u16::from_be_bytes([curve[12 + 2 * n], curve[13 + 2 * n]]);
+        assert_eq!(sample(0), 0);
+        assert_eq!(sample(samples - 1), 65535);
+        assert!((1..samples).all(|n| sample(n) >= sample(n - 1)));
+        assert_eq!(tags[&b"gTRC"[..]], curve);
+        assert_eq!(tags[&b"bTRC"[..]], curve);
+    }
+
+    #[test]
+    fn xmp_escapes_text() {
+        let info = Info {
+            title: Some(String::from("Profit & Loss <2020>")),
+            author: Some(String::from("Smith & \"Sons\"")),
+            producer: String::from("pdfiron"),
+            ..Info::default()
+        };
+        let xmp = xmp(&info, &OffsetDateTime::UNIX_EPOCH);
+        assert!(
+            xmp.contains("<rdf:li xml:lang=\"x-default\">Profit &amp; Loss &lt;2020&gt;</rdf:li>")
+        );
+        assert!(xmp.contains("<rdf:li>Smith &amp; &quot;Sons&quot;</rdf:li>"));
+        assert!(!xmp.contains("<2020>") && !xmp.contains("& L"));
+        assert!(xmp.contains("<xmp:CreateDate>1970-01-01T00:00:00+00:00</xmp:CreateDate>"));
+        assert!(!xmp.contains("dc:description"));
+    }
+
+    #[test]
+    fn conversion() {
+        let folder = tempfile::tempdir().unwrap();
+        let (mut doc, _, _) = document();
+        let info = doc.add_object(dictionary! {"Title" => Object::string_literal("Report")});
+        doc.trailer.set("Info", info);
+        let path = folder.path().join("output.pdf");
+        doc.save(&path).unwrap();
+        convert(&path).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n"));
+        let doc = Document::load_mem(&bytes).unwrap();
+        assert!(doc.trailer.get(b"ID").is_ok());
+        let catalog = doc.catalog().unwrap();
+        let metadata = doc
+            .get_object(catalog.get(b"Metadata").unwrap().as_reference().unwrap())
+            .unwrap()
+            .as_stream()
+            .unwrap();
+        let xmp = String::from_utf8_lossy(&metadata.content);
+        assert!(xmp.contains("<pdfaid:part>2</pdfaid:part>"));
+        assert!(xmp.contains(">Report</rdf:li>"));
+        let intents = catalog.get(b"OutputIntents").unwrap().as_array().unwrap();
+        let intent = intents[0].as_dict().unwrap();
+        assert_eq!(
+            intent
+                .get(b"OutputConditionIdentifier")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+            OUTPUT_CONDITION.as_bytes()
+        );
+    }
+
+    #[test]
+    fn encrypted_document_is_refused() {
+        let folder = tempfile::tempdir().unwrap();
+        let (mut doc, _, _) = document();
+        let encrypt = doc.add_object(dictionary! {"Filter" => "Standard", "V" => 2, "R" => 3});
+        doc.trailer.set("Encrypt", encrypt);
+        assert_eq!(problems(&folder, doc), ["the document is encrypted"]);
+    }
+
+    #[test]
+    fn javascript_is_refused() {
+        let folder = tempfile::tempdir().unwrap();
+        let (mut doc, _, catalog) = document();
+        let action =
+            dictionary! {"S" => "JavaScript", "JS" => Object::string_literal("app.alert(1)")};
+        let names = dictionary! {"JavaScript" => dictionary! {"Names" => vec![]}};
+        let catalog = doc.get_dictionary_mut(catalog).unwrap();
+        catalog.set("OpenAction", action);
+        catalog.set("Names", names);
+        assert_eq!(
+            problems(&folder, doc),
+            [
+                "the document contains JavaScript",
+                "the document contains JavaScript actions"
+            ]
+        );
+    }
+
+    #[test]
+    fn font_without_program_is_refused() {
+        let folder = tempfile::tempdir().unwrap();
+        let (mut doc, page, _) = document();
+        let descriptor =
+            doc.add_object(dictionary! {"Type" => "FontDescriptor", "FontName" => "Arial"});
+        let font = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "TrueType",
+            "BaseFont" => "Arial",
+            "FontDescriptor" => descriptor,
+        });
+        let resources = dictionary! {"Font" => dictionary! {"F1" => font}};
+        doc.get_dictionary_mut(page)
+            .unwrap()
+            .set("Resources", resources);
+        let rsl = problems(&folder, doc);
+        assert_eq!(rsl, ["the font Arial isn't embedded"]);
+        assert_eq!(
+            Error::PdfA(rsl).to_string(),
+            "The output can't be converted to PDF/A-2b: the font Arial isn't embedded"
+        );
+    }
+
+    #[test]
+    fn hidden_annotation_is_refused() {
+        let folder = tempfile::tempdir().unwrap();
+        let (mut doc, page, _) = document();
+        let appearance = doc.add_object(Stream::new(dictionary! {"Subtype" => "Form"}, vec![]));
+        let annotation = dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Text",
+            "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()],
+            "F" => ANNOTATION_HIDDEN | ANNOTATION_PRINT,
+            "AP" => dictionary! {"N" => appearance},
+        };
+        doc.get_dictionary_mut(page)
+            .unwrap()
+            .set("Annots", vec![Object::Dictionary(annotation)]);
+        assert_eq!(
+            problems(&folder, doc),
+            ["page 1 contains hidden annotations"]
+        );
+    }
+
+    #[test]
+    fn binary_comment_keeps_offsets() {
+        let mut doc = Document::with_version("1.7");
+        let pages = doc.new_object_id();
+        let page = doc.add_object(dictionary! {"Type" => "Page", "Parent" => pages});
+        doc.objects.insert(
+            pages,
+            Object::Dictionary(
+                dictionary! {"Type" => "Pages", "Kids" => vec![page.into()], "Count" => 1},
+            ),
+        );
+        let catalog = doc.add_object(dictionary! {"Type" => "Catalog", "Pages" => pages});
+        doc.trailer.set("Root", catalog);
+        doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+        let mut written = Vec::new();
+        doc.save_to(&mut written).unwrap();
+
+        let pdf = add_binary_comment(written).unwrap();
+        assert!(pdf.starts_with(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n1 0 obj"));
+        // Everything after the comment is ASCII.
+        let header = b"%PDF-1.7\n".len() + BINARY_COMMENT.len();
+        let text = str::from_utf8(&pdf[header..]).unwrap();
+        let xref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(text[xref - header..].starts_with("xref\n0 4\n"));
+        let entries = &text[xref - header + 9..];
+        for (n, entry) in entries.lines().take(4).enumerate().skip(1) {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(text[offset - header..].starts_with(&format!("{} 0 obj", n)));
+        }
+    }
+}
@@ -0,0 +1,1783 @@
+/// The pipeline is the public entry point of pdfiron. It takes the typed options, creates the run
+/// and executes the stages one after another.
+use crate::alto;
+use crate::binary::{Binaries, Executable, Tool};
+use crate::cache::{Cache, DEFAULT_CACHE_SIZE};
+use crate::config::Profile;
+use crate::convert;
+use crate::doctor;
+use crate::error::Error;
+use crate::error::ExecutableNameMethod;
+use crate::hocr;
+use crate::input::{self, InputFile, InputKind, STDIO};
+use crate::jbig2::Coding;
+use crate::manifest::{Entry, Manifest};
+use crate::metadata::{Info, Metadata};
+use crate::pages::PageRanges;
+use crate::pdf;
+use crate::pdfa;
+use crate::run::{Format, Run};
+use crate::scheduler::{Demand, Scheduler};
+use crate::stage::{Artifact, Job, Stage};
+use crate::tesseract;
+use crate::text;
+use crate::unpaper;
+use crate::util;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Default resolution in DPI used to extract the pages from the input document.
+pub const DEFAULT_RESOLUTION: u64 = 300;
+
+/// Default upper limit in DPI of the automatically chosen resolution.
+pub const DEFAULT_MAX_RESOLUTION: u64 = 600;
+
+/// Environment variable holding the password of encrypted input documents.
+pub const PASSWORD_ENV: &str = "PDFIRON_PASSWORD";
+
+/// Default language used by Tesseract.
+pub const DEFAULT_LANG: &str = "eng";
+
+/// Layout of the scanned pages, used by unpaper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// One book page per scanned page.
+    Single,
+    /// Two book pages per scanned page.
+    Double,
+    /// Let unpaper figure out the layout by itself.
+    None,
+}
+
+impl Layout {
+    /// Returns the name of the layout as understood by unpaper.
+    pub fn as_str<'a>(self) -> &'a str {
+        match self {
+            Layout::Single => "single",
+            Layout::Double => "double",
+            Layout::None => "none",
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "single" => Ok(Layout::Single),
+            "double" => Ok(Layout::Double),
+            "none" => Ok(Layout::None),
+            _ => Err(Error::invalid_input(format!(
+                "Invalid layout {}, has to be single, double or none",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Resolution used to rasterize the pages of the input documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// The same resolution in DPI for every page.
+    Fixed(u64),
+    /// The resolution of each page is chosen by the images it contains.
+    Auto,
+}
+
+impl From<u64> for Resolution {
+    fn from(dpi: u64) -> Self {
+        Resolution::Fixed(dpi)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Resolution::Auto),
+            x => match x.parse::<u64>() {
+                Ok(x) => Ok(Resolution::Fixed(x)),
+                Err(_) => Err(Error::invalid_input(format!(
+                    "Invalid resolution {}, has to be a number of DPI or auto",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resolution::Fixed(x) => write!(f, "{}", x),
+            Resolution::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// A resolution is stored as number or as `"auto"` in the configuration file.
+impl Serialize for Resolution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Resolution::Fixed(x) => serializer.serialize_u64(*x),
+            Resolution::Auto => serializer.serialize_str("auto"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Resolution {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Number(u64),
+            Text(String),
+        }
+        match Value::deserialize(deserializer)? {
+            Value::Number(x) => Ok(Resolution::Fixed(x)),
+            Value::Text(x) => x.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+/// All options of a pdfiron run. Use the builder methods to alter the default values.
+#[derive(Debug, Clone)]
+pub struct PipelineOptions {
+    /// Paths to the input files, PDF files, images and folders containing images. They are
+    /// processed as one document in the given order. `-` reads the input from stdin.
+    pub inputs: Vec<PathBuf>,
+    /// Optional path to the output file, `-` writes to stdout. If none is given, the output will
+    /// be saved next to the input file (or written to stdout if the input is read from stdin).
+    pub output: Option<PathBuf>,
+    /// Image file format used internally.
+    pub format: Format,
+    /// Whether to pause between the steps.
+    pub step: bool,
+    /// Resolution in DPI used to extract the pages, or automatically chosen per page. Input
+    /// images stating their resolution keep it.
+    pub resolution: Resolution,
+    /// Upper limit in DPI of the automatically chosen resolution.
+    pub max_resolution: u64,
+    /// Rasterizes pages consisting of a single image instead of extracting the image.
+    pub no_extract_images: bool,
+    /// Optional clockwise rotation of the input document in degrees.
+    pub rotate: Option<f64>,
+    /// Additional options for convert.
+    pub convert_options: Option<String>,
+    /// Optional selection of the pages to process, the other pages are left out.
+    pub pages: Option<PageRanges>,
+    /// Takes the pages which aren't selected untouched into the output.
+    pub splice: bool,
+    /// Processes pages which already contain text instead of taking them over untouched.
+    pub force_ocr: bool,
+    /// Processes pages whose text is entirely invisible, the OCR layer of an earlier run.
+    pub redo_ocr: bool,
+    /// Skips the enhancement with unpaper.
+    pub disable_unpaper: bool,
+    /// Layout of the scanned pages.
+    pub layout: Layout,
+    /// Number of output pages per input page, used for splitting double layout documents.
+    pub output_pages: u8,
+    /// Additional options for unpaper.
+    pub unpaper_options: Option<String>,
+    /// Disables the blackfilter of unpaper.
+    pub no_blackfilter: bool,
+    /// Disables the grayfilter of unpaper.
+    pub no_grayfilter: bool,
+    /// Skips the OCR.
+    pub disable_tesseract: bool,
+    /// Language(s) used by Tesseract.
+    pub lang: String,
+    /// Additional options for Tesseract.
+    pub tesseract_options: Option<String>,
+    /// Overwrites the number of parallel Tesseract processes.
+    pub tesseract_threads: Option<usize>,
+    /// Number of cores used in parallel, defaults to the number of cores of the system.
+    pub jobs: usize,
+    /// Names of the external binaries, taken from the environment variables by default.
+    pub binaries: Binaries,
+    /// Password of encrypted input documents, taken from the environment variable
+    /// `PDFIRON_PASSWORD` by default.
+    pub password: Option<String>,
+    /// Optional path of the merged hOCR of all pages.
+    pub hocr: Option<PathBuf>,
+    /// Optional directory or zip archive of the ALTO files of the pages.
+    pub alto: Option<PathBuf>,
+    /// Optional path of the recognized text of all pages, separated by form feeds.
+    pub sidecar: Option<PathBuf>,
+    /// Optional folder for the recognized text with a file per page.
+    pub sidecar_pages: Option<PathBuf>,
+    /// Converts the output into PDF/A-2b.
+    pub pdfa: bool,
+    /// Compresses the image of bilevel pages with JBIG2.
+    pub jbig2: bool,
+    /// Uses the lossy symbol coding of JBIG2 instead of the lossless generic coding.
+    pub jbig2_lossy: bool,
+    /// Title of the output, overrides the title of the input.
+    pub title: Option<String>,
+    /// Author of the output, overrides the author of the input.
+    pub author: Option<String>,
+    /// Subject of the output, overrides the subject of the input.
+    pub subject: Option<String>,
+    /// Keywords of the output, override the keywords of the input.
+    pub keywords: Option<String>,
+    /// Checks the needed tools, languages and the ImageMagick policy before the run.
+    pub preflight: bool,
+    /// Optional persistent folder used instead of a temporary folder.
+    pub workdir: Option<PathBuf>,
+    /// Continues the run in the working directory, completed steps are skipped.
+    pub resume: bool,
+    /// Keeps the temporary folder after the run.
+    pub keep_temp: bool,
+    /// Reuses the outputs of the stages from previous runs.
+    pub cache: bool,
+    /// Folder of the cache.
+    pub cache_dir: PathBuf,
+    /// Maximal size of the cache in megabytes.
+    pub cache_size: u64,
+}
+
+impl PipelineOptions {
+    /// Returns the default options for a given input file.
+    pub fn new<P: Into<PathBuf>>(input: P) -> Self {
+        Self {
+            inputs: vec![input.into()],
+            output: None,
+            format: Format::Bitmap,
+            step: false,
+            resolution: Resolution::Fixed(DEFAULT_RESOLUTION),
+            max_resolution: DEFAULT_MAX_RESOLUTION,
+            no_extract_images: false,
+            rotate: None,
+            convert_options: None,
+            pages: None,
+            splice: false,
+            force_ocr: false,
+            redo_ocr: false,
+            disable_unpaper: false,
+            layout: Layout::None,
+            output_pages: 1,
+            unpaper_options: None,
+            no_blackfilter: false,
+            no_grayfilter: false,
+            disable_tesseract: false,
+            lang: String::from(DEFAULT_LANG),
+            tesseract_options: None,
+            tesseract_threads: None,
+            jobs: num_cpus::get(),
+            binaries: Binaries::from_env(),
+            password: env::var(PASSWORD_ENV).ok().filter(|x| !x.is_empty()),
+            hocr: None,
+            alto: None,
+            sidecar: None,
+            sidecar_pages: None,
+            pdfa: false,
+            jbig2: false,
+            jbig2_lossy: false,
+            title: None,
+            author: None,
+            subject: None,
+            keywords: None,
+            preflight: true,
+            workdir: None,
+            resume: false,
+            keep_temp: false,
+            cache: false,
+            cache_dir: Cache::default_folder(),
+            cache_size: DEFAULT_CACHE_SIZE,
+        }
+    }
+
+    /// Adds another input file or folder.
+    pub fn input<P: Into<PathBuf>>(mut self, input: P) -> Self {
+        self.inputs.push(input.into());
+        self
+    }
+
+    /// Sets the path of the output file.
+    pub fn output<P: Into<PathBuf>>(mut self, output: P) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+
+    /// Sets the image format used internally.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Enables the pause between the steps.
+    pub fn step(mut self, step: bool) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Sets the resolution in DPI or enables the automatic resolution.
+    pub fn resolution<R: Into<Resolution>>(mut self, resolution: R) -> Self {
+        self.resolution = resolution.into();
+        self
+    }
+
+    /// Sets the upper limit in DPI of the automatically chosen resolution.
+    pub fn max_resolution(mut self, max_resolution: u64) -> Self {
+        self.max_resolution = max_resolution;
+        self
+    }
+
+    /// Rasterizes pages consisting of a single image instead of extracting the image.
+    pub fn no_extract_images(mut self, disable: bool) -> Self {
+        self.no_extract_images = disable;
+        self
+    }
+
+    /// Rotates the input document clockwise by the given degrees.
+    pub fn rotate(mut self, degrees: f64) -> Self {
+        self.rotate = Some(degrees);
+        self
+    }
+
+    /// Sets additional options for convert.
+    pub fn convert_options<S: Into<String>>(mut self, options: S) -> Self {
+        self.convert_options = Some(options.into());
+        self
+    }
+
+    /// Selects the pages to process.
+    pub fn pages(mut self, pages: PageRanges) -> Self {
+        self.pages = Some(pages);
+        self
+    }
+
+    /// Takes the pages which aren't selected untouched into the output.
+    pub fn splice(mut self, splice: bool) -> Self {
+        self.splice = splice;
+        self
+    }
+
+    /// Processes pages which already contain text instead of taking them over untouched.
+    pub fn force_ocr(mut self, force_ocr: bool) -> Self {
+        self.force_ocr = force_ocr;
+        self
+    }
+
+    /// Processes pages whose text is entirely invisible instead of taking them over untouched.
+    /// Such text is the OCR layer of an earlier run, it's replaced by a new one.
+    pub fn redo_ocr(mut self, redo_ocr: bool) -> Self {
+        self.redo_ocr = redo_ocr;
+        self
+    }
+
+    /// Skips the enhancement with unpaper.
+    pub fn disable_unpaper(mut self, disable: bool) -> Self {
+        self.disable_unpaper = disable;
+        self
+    }
+
+    /// Sets the layout of the scanned pages.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Sets the number of output pages per input page.
+    pub fn output_pages(mut self, pages: u8) -> Self {
+        self.output_pages = pages;
+        self
+    }
+
+    /// Sets additional options for unpaper.
+    pub fn unpaper_options<S: Into<String>>(mut self, options: S) -> Self {
+        self.unpaper_options = Some(options.into());
+        self
+    }
+
+    /// Disables the blackfilter of unpaper.
+    pub fn no_blackfilter(mut self, disable: bool) -> Self {
+        self.no_blackfilter = disable;
+        self
+    }
+
+    /// Disables the grayfilter of unpaper.
+    pub fn no_grayfilter(mut self, disable: bool) -> Self {
+        self.no_grayfilter = disable;
+        self
+    }
+
+    /// Skips the OCR.
+    pub fn disable_tesseract(mut self, disable: bool) -> Self {
+        self.disable_tesseract = disable;
+        self
+    }
+
+    /// Sets the language(s) used by Tesseract.
+    pub fn lang<S: Into<String>>(mut self, lang: S) -> Self {
+        self.lang = lang.into();
+        self
+    }
+
+    /// Sets additional options for Tesseract.
+    pub fn tesseract_options<S: Into<String>>(mut self, options: S) -> Self {
+        self.tesseract_options = Some(options.into());
+        self
+    }
+
+    /// Overwrites the number of parallel Tesseract processes.
+    pub fn tesseract_threads(mut self, threads: usize) -> Self {
+        self.tesseract_threads = Some(threads);
+        self
+    }
+
+    /// Sets the number of cores used in parallel.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Sets the binary name of an external tool.
+    pub fn binary<S: Into<String>>(mut self, tool: Tool, name: S) -> Self {
+        self.binaries
+            .set(tool, Executable::new(tool, name, ExecutableNameMethod::Arg));
+        self
+    }
+
+    /// Sets the password of encrypted input documents.
+    pub fn password<S: Into<String>>(mut self, password: S) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Writes the merged hOCR of all pages to the given path.
+    pub fn hocr<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.hocr = Some(path.into());
+        self
+    }
+
+    /// Writes the ALTO files of the pages and a METS file to the given directory or zip archive.
+    pub fn alto<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.alto = Some(path.into());
+        self
+    }
+
+    /// Writes the recognized text of all pages to the given path.
+    pub fn sidecar<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.sidecar = Some(path.into());
+        self
+    }
+
+    /// Writes the recognized text of each page into a file of its own within the given folder.
+    pub fn sidecar_pages<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.sidecar_pages = Some(path.into());
+        self
+    }
+
+    /// Converts the output into PDF/A-2b, fails if the output can't be made conform.
+    pub fn pdfa(mut self, pdfa: bool) -> Self {
+        self.pdfa = pdfa;
+        self
+    }
+
+    /// Compresses the image of bilevel pages with the lossless JBIG2 generic coding.
+    pub fn jbig2(mut self, jbig2: bool) -> Self {
+        self.jbig2 = jbig2;
+        self
+    }
+
+    /// Uses the lossy JBIG2 symbol coding, which shrinks the pages further but can confuse similar
+    /// looking characters.
+    pub fn jbig2_lossy(mut self, jbig2_lossy: bool) -> Self {
+        self.jbig2_lossy = jbig2_lossy;
+        self
+    }
+
+    /// Sets the title of the output.
+    pub fn title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Sets the author of the output.
+    pub fn author<S: Into<String>>(mut self, author: S) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    /// Sets the subject of the output.
+    pub fn subject<S: Into<String>>(mut self, subject: S) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    /// Sets the keywords of the output.
+    pub fn keywords<S: Into<String>>(mut self, keywords: S) -> Self {
+        self.keywords = Some(keywords.into());
+        self
+    }
+
+    /// Enables the checks of the system before the run.
+    pub fn preflight(mut self, preflight: bool) -> Self {
+        self.preflight = preflight;
+        self
+    }
+
+    /// Uses a persistent folder instead of a temporary folder.
+    pub fn workdir<P: Into<PathBuf>>(mut self, workdir: P) -> Self {
+        self.workdir = Some(workdir.into());
+        self
+    }
+
+    /// Continues the run in the working directory.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Keeps the temporary folder after the run.
+    pub fn keep_temp(mut self, keep: bool) -> Self {
+        self.keep_temp = keep;
+        self
+    }
+
+    /// Enables the cache of the stage outputs.
+    pub fn cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Sets the folder of the cache.
+    pub fn cache_dir<P: Into<PathBuf>>(mut self, folder: P) -> Self {
+        self.cache_dir = folder.into();
+        self
+    }
+
+    /// Sets the maximal size of the cache in megabytes.
+    pub fn cache_size(mut self, size: u64) -> Self {
+        self.cache_size = size;
+        self
+    }
+
+    /// Checks the options for invalid combinations and values.
+    fn validate(&self) -> Result<(), Error> {
+        if self.resolution == Resolution::Fixed(0) || self.max_resolution == 0 {
+            return Err(Error::invalid_input(
+                "Invalid resolution, has to be a positive number",
+            ));
+        }
+        if self.output_pages != 1 && self.output_pages != 2 {
+            return Err(Error::invalid_input(format!(
+                "Invalid number of output pages {}, has to be 1 or 2",
+                self.output_pages
+            )));
+        }
+        if self.jobs == 0 {
+            return Err(Error::invalid_input(
+                "Invalid number of jobs, has to be a positive number",
+            ));
+        }
+        if self.tesseract_threads == Some(0) {
+            return Err(Error::invalid_input(
+                "Invalid number of Tesseract threads, has to be a positive number",
+            ));
+        }
+        let n_stdin = self
+            .inputs
+            .iter()
+            .filter(|x| x.as_os_str() == STDIO)
+            .count();
+        if n_stdin > 1 {
+            return Err(Error::invalid_input(
+                "The input can only be read once from stdin",
+            ));
+        }
+        if n_stdin == 1 && self.step {
+            return Err(Error::invalid_input(
+                "The step mode waits for input on stdin and can't be used while the input is read from stdin",
+            ));
+        }
+        if self.hocr.is_some() && self.disable_tesseract {
+            return Err(Error::invalid_input(
+                "The hOCR output needs the OCR, it can't be combined with --disable-tesseract",
+            ));
+        }
+        if self.alto.is_some() && self.disable_tesseract {
+            return Err(Error::invalid_input(
+                "The ALTO output needs the OCR, it can't be combined with --disable-tesseract",
+            ));
+        }
+        if (self.sidecar.is_some() || self.sidecar_pages.is_some()) && self.disable_tesseract {
+            return Err(Error::invalid_input(
+                "The text sidecar needs the OCR, it can't be combined with --disable-tesseract",
+            ));
+        }
+        if self.jbig2 && self.disable_tesseract {
+            return Err(Error::invalid_input(
+                "The JBIG2 compression works on the output of Tesseract, it can't be combined with --disable-tesseract",
+            ));
+        }
+        if self.jbig2_lossy && !self.jbig2 {
+            return Err(Error::invalid_input(
+                "The lossy JBIG2 coding needs the JBIG2 compression, use --jbig2",
+            ));
+        }
+        if self.splice && self.pages.is_none() {
+            return Err(Error::invalid_input(
+                "Splicing needs a selection of pages, use --pages",
+            ));
+        }
+        if self.resume && self.workdir.is_none() {
+            return Err(Error::invalid_input(
+                "A run can only be resumed within a working directory, use --workdir",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Duration of one step of the pipeline.
+#[derive(Debug, Clone)]
+pub struct StageTiming {
+    /// Name of the step.
+    pub name: String,
+    /// Time spent in the step. As the pages are processed in parallel, this is the sum of the
+    /// processing time of all pages.
+    pub duration: Duration,
+}
+
+/// Outcome of a successful pipeline execution.
+#[derive(Debug, Clone)]
+pub struct PipelineResult {
+    /// Path to the resulting PDF file, `-` if it was written to stdout.
+    pub output: PathBuf,
+    /// Number of pages of the resulting PDF file.
+    pub pages: u64,
+    /// Time spent in each step, in execution order.
+    pub timings: Vec<StageTiming>,
+    /// Wall time of the whole execution.
+    pub elapsed: Duration,
+}
+
+/// State shared by all jobs of an execution.
+struct Context<'a> {
+    /// The run the jobs belong to.
+    run: &'a Run,
+    /// Distributes the jobs on the cores.
+    scheduler: Scheduler,
+    /// Records the completed steps.
+    manifest: &'a Manifest,
+    /// The cache of the stage outputs, if enabled.
+    cache: Option<Cache>,
+    /// Time spent in each stage.
+    busy: Mutex<Vec<Duration>>,
+    /// Number of jobs whose outputs were taken from the cache.
+    cached: AtomicUsize,
+}
+
+/// A page (or a part of it, when unpaper splits pages) on its way through the stages.
+struct Task {
+    /// Position of the file within the document, used to restore the page order at the end.
+    key: Vec<usize>,
+    /// Index of the stage which processes the task next.
+    stage: usize,
+    /// Path to the input file of the stage.
+    input: PathBuf,
+    /// Resolution of the page in DPI.
+    resolution: u64,
+    /// Whether the page is bilevel.
+    bilevel: bool,
+}
+
+/// How a page of an input document with embedded images is processed.
+#[derive(Debug, PartialEq, Eq)]
+enum ImageUse {
+    /// The image is extracted and used as it is.
+    Extract,
+    /// The page is rasterized with the given resolution.
+    Rasterize(u64),
+}
+
+/// Where a page of the input comes from.
+struct Source {
+    /// Position of the page within all inputs.
+    page: usize,
+    /// Input of the first stage, a page of the input document or a frame of an image.
+    input: PathBuf,
+    /// Resolution of the page in DPI.
+    resolution: u64,
+    /// Whether the page is a bilevel image.
+    bilevel: bool,
+}
+
+/// Executes the conversion of a document as described by the options. The work is done by a
+/// sequence of stages, by default these are: convert, unpaper, tiff and tesseract. The stages
+/// can be altered before the execution, the output of each stage has to match the input of the
+/// following one. The last stage has to produce PDF files which are then combined into the
+/// output document.
+pub struct Pipeline {
+    options: PipelineOptions,
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    /// Returns a new pipeline for the given options with the default stages.
+    pub fn new(options: PipelineOptions) -> Self {
+        let stages = Pipeline::default_stages(&options);
+        Self { options, stages }
+    }
+
+    /// Returns a new pipeline with a custom sequence of stages.
+    pub fn with_stages(options: PipelineOptions, stages: Vec<Box<dyn Stage>>) -> Self {
+        Self { options, stages }
+    }
+
+    /// Returns the stages pdfiron uses for the given options.
+    pub fn default_stages(options: &PipelineOptions) -> Vec<Box<dyn Stage>> {
+        let mut stages: Vec<Box<dyn Stage>> = vec![Box::new(convert::Rasterize::new(
+            options.rotate,
+            options.convert_options.clone(),
+        ))];
+        if !options.disable_unpaper {
+            stages.push(Box::new(unpaper::Unpaper::new(
+                options.layout,
+                options.output_pages,
+                options.unpaper_options.clone(),
+                options.no_blackfilter,
+                options.no_grayfilter,
+            )));
+        }
+        if options.disable_tesseract {
+            stages.push(Box::new(convert::ToPdf));
+        } else {
+            stages.push(Box::new(convert::Tiff));
+            let mut ocr = tesseract::Ocr::new(
+                options.lang.clone(),
+                options.tesseract_options.clone(),
+                options.tesseract_threads,
+            );
+            if options.hocr.is_some() || options.alto.is_some() {
+                ocr = ocr.config("hocr");
+            }
+            if options.sidecar.is_some() || options.sidecar_pages.is_some() {
+                ocr = ocr.config("txt");
+            }
+            if options.jbig2 {
+                ocr = ocr.jbig2(match options.jbig2_lossy {
+                    true => Coding::Symbol,
+                    false => Coding::Generic,
+                });
+            }
+            stages.push(Box::new(ocr));
+        }
+        stages
+    }
+
+    /// Returns the options of the pipeline.
+    pub fn options(&self) -> &PipelineOptions {
+        &self.options
+    }
+
+    /// Returns the names of the stages in execution order.
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|x| x.name()).collect()
+    }
+
+    /// Gives mutable access to the stages, use this to reorder the stages.
+    pub fn stages_mut(&mut self) -> &mut Vec<Box<dyn Stage>> {
+        &mut self.stages
+    }
+
+    /// Inserts a stage after the stage with the given name.
+    pub fn insert_after(&mut self, name: &str, stage: Box<dyn Stage>) -> Result<(), Error> {
+        let i = self.position(name)?;
+        self.stages.insert(i + 1, stage);
+        Ok(())
+    }
+
+    /// Inserts a stage before the stage with the given name.
+    pub fn insert_before(&mut self, name: &str, stage: Box<dyn Stage>) -> Result<(), Error> {
+        let i = self.position(name)?;
+        self.stages.insert(i, stage);
+        Ok(())
+    }
+
+    /// Replaces the stage with the given name.
+    pub fn replace(&mut self, name: &str, stage: Box<dyn Stage>) -> Result<(), Error> {
+        let i = self.position(name)?;
+        self.stages[i] = stage;
+        Ok(())
+    }
+
+    /// Removes the stage with the given name.
+    pub fn remove(&mut self, name: &str) -> Result<Box<dyn Stage>, Error> {
+        let i = self.position(name)?;
+        Ok(self.stages.remove(i))
+    }
+
+    /// Runs all stages of the conversion and returns the result. Each page flows through the
+    /// stages independently of the other pages, intermediate files are removed as soon as they
+    /// were consumed by the next stage. If the step mode is enabled, all pages have to complete
+    /// a stage before the next stage starts. This way the user can alter the files in between.
+    /// The folder of a failed run is kept for inspection, it can be resumed later.
+    pub fn execute(&self) -> Result<PipelineResult, Error> {
+        let start = Instant::now();
+        let opt = &self.options;
+        opt.validate()?;
+        self.validate_stages()?;
+        if opt.preflight {
+            doctor::preflight(opt)?;
+        }
+        let mut run = Run::new(opt)?;
+        let (manifest, entries) = Manifest::open(
+            &run,
+            self.stage_names().into_iter().map(String::from).collect(),
+            self.stages.iter().map(|x| x.sidecars()).collect(),
+            self.settings(),
+            opt.resume,
+        )?;
+        match self.execute_run(&run, &manifest, entries, start) {
+            Ok(x) => {
+                if run.is_kept() {
+                    info!("Working files are kept in {}", run.folder().display());
+                }
+                Ok(x)
+            }
+            Err(e) => {
+                let folder = run.keep();
+                info!(
+                    "Working files are kept in {}, use --workdir {} --resume to continue the run",
+                    folder.display(),
+                    folder.display()
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// Executes the stages within the given run, the completed steps of a resumed run are
+    /// skipped.
+    fn execute_run(
+        &self,
+        run: &Run,
+        manifest: &Manifest,
+        entries: Vec<Entry>,
+        start: Instant,
+    ) -> Result<PipelineResult, Error> {
+        let opt = &self.options;
+        let cache = match opt.cache {
+            true => match Cache::open(&opt.cache_dir, opt.cache_size) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    warn!("The cache is disabled, {}", e);
+                    None
+                }
+            },
+            false => None,
+        };
+        let ctx = Context {
+            run,
+            scheduler: Scheduler::new(opt.jobs),
+            manifest,
+            cache,
+            busy: Mutex::new(vec![Duration::default(); self.stages.len()]),
+            cached: AtomicUsize::new(0),
+        };
+
+        let sources = self.sources(&ctx)?;
+        let metadata = self.read_metadata(run, &sources);
+        let (sources, mut others) = self.select(sources)?;
+        if !opt.splice {
+            others.clear();
+        }
+        let (sources, text) = self.skip_text(&ctx, sources)?;
+        others.extend(text);
+        let sources = self.use_page_images(&ctx, sources)?;
+        let mut tasks = self.resume_tasks(&sources, entries);
+        tasks.extend(self.keep_pages(&ctx, others)?);
+        let mut tasks = match opt.step {
+            true => self.execute_stepwise(&ctx, tasks)?,
+            false => self.execute_streaming(&ctx, tasks)?,
+        };
+        tasks.sort_by(|a, b| a.key.cmp(&b.key));
+        let origins: Vec<usize> = tasks.iter().map(|x| x.key[0]).collect();
+        let resolutions: Vec<u64> = tasks.iter().map(|x| x.resolution).collect();
+        let files: Vec<PathBuf> = tasks.into_iter().map(|x| x.input).collect();
+
+        if let Some(cache) = &ctx.cache {
+            let n = ctx.cached.load(Ordering::SeqCst);
+            if n > 0 {
+                info!("Reused the outputs of {} steps from the cache", n);
+            }
+            if let Err(e) = cache.trim() {
+                warn!("Couldn't trim the cache, {}", e);
+            }
+        }
+        let mut timings: Vec<StageTiming> = self
+            .stages
+            .iter()
+            .zip(ctx.busy.into_inner().unwrap())
+            .map(|(stage, duration)| StageTiming {
+                name: String::from(stage.name()),
+                duration,
+            })
+            .collect();
+        let unite_start = Instant::now();
+        pdf::unite(run, &files)?;
+        timings.push(StageTiming {
+            name: String::from("pdfunite"),
+            duration: unite_start.elapsed(),
+        });
+
+        if let Some(x) = &opt.hocr {
+            let pages: Vec<Option<PathBuf>> = files.iter().map(|x| sidecar(x, "hocr")).collect();
+            hocr::merge(&pages, x)?;
+        }
+        if let Some(x) = &opt.alto {
+            let hocr: Vec<Option<PathBuf>> = files.iter().map(|x| sidecar(x, "hocr")).collect();
+            // Pages taken over untouched have no hOCR to take their size from.
+            let sizes = match hocr.iter().any(|x| x.is_none()) {
+                true => pdf::displayed_page_sizes(run, &run.output_path())?,
+                false => vec![],
+            };
+            let pages: Vec<alto::Page> = hocr
+                .into_iter()
+                .zip(resolutions)
+                .enumerate()
+                .map(|(n, (hocr, resolution))| alto::Page {
+                    size: match hocr {
+                        Some(_) => None,
+                        None => sizes.get(n).copied(),
+                    },
+                    hocr,
+                    resolution,
+                })
+                .collect();
+            alto::export(&pages, x)?;
+        }
+        if opt.sidecar.is_some() || opt.sidecar_pages.is_some() {
+            let pages: Vec<Option<PathBuf>> = files.iter().map(|x| sidecar(x, "txt")).collect();
+            if let Some(x) = &opt.sidecar {
+                text::join(&pages, x)?;
+            }
+            if let Some(x) = &opt.sidecar_pages {
+                text::split(&pages, x)?;
+            }
+        }
+
+        let mut output = run.output_path();
+        if !metadata.is_empty() {
+            metadata.write(&output, &origins)?;
+        }
+        if opt.pdfa {
+            // A document which isn't conform mustn't be mistaken for the requested PDF/A.
+            if let Err(e) = pdfa::convert(&output) {
+                if let Err(e) = fs::remove_file(&output) {
+                    warn!("Couldn't remove {}, {}", output.display(), e);
+                }
+                return Err(e);
+            }
+        }
+        // Each of the united files holds a single page.
+        let pages = files.len() as u64;
+        if run.to_stdout() {
+            run.write_to_stdout()?;
+            output = PathBuf::from(STDIO);
+        }
+        Ok(PipelineResult {
+            output,
+            pages,
+            timings,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// Returns the tasks of the pages. The completed steps of a resumed run are replayed, this
+    /// way each page continues with the output of its last completed stage. A page starts over
+    /// if one of its files went missing.
+    fn resume_tasks(&self, sources: &[Source], entries: Vec<Entry>) -> Vec<Task> {
+        let pages = sources.len();
+        let sources: BTreeMap<usize, &Source> = sources.iter().map(|x| (x.page, x)).collect();
+        let mut tasks: BTreeMap<Vec<usize>, Task> = sources
+            .values()
+            .map(|x| (vec![x.page], initial_task(x)))
+            .collect();
+        let n_entries = entries.len();
+        for entry in entries {
+            match tasks.get(&entry.key) {
+                Some(x) if x.stage == entry.stage => {}
+                _ => continue,
+            }
+            let parent = tasks.remove(&entry.key).unwrap();
+            for (n, output) in entry.outputs.into_iter().enumerate() {
+                let mut key = entry.key.clone();
+                key.push(n);
+                tasks.insert(
+                    key.clone(),
+                    Task {
+                        key,
+                        stage: entry.stage + 1,
+                        input: output,
+                        resolution: parent.resolution,
+                        bilevel: parent.bilevel,
+                    },
+                );
+            }
+        }
+
+        let missing: Vec<usize> = tasks
+            .values()
+            .filter(|x| x.stage > 0 && !x.input.exists())
+            .map(|x| x.key[0])
+            .collect();
+        for page in &missing {
+            warn!(
+                "Files of page {} are missing, the page is processed again",
+                page + 1
+            );
+            tasks.retain(|key, _| key[0] != *page);
+            tasks.insert(vec![*page], initial_task(sources[page]));
+        }
+        if n_entries > 0 {
+            let pending: BTreeSet<usize> = tasks
+                .values()
+                .filter(|x| x.stage < self.stages.len())
+                .map(|x| x.key[0])
+                .collect();
+            info!(
+                "Resuming run, {} steps were already completed, {} of {} pages are done",
+                n_entries,
+                pages - pending.len(),
+                pages
+            );
+        }
+        tasks.into_values().collect()
+    }
+
+    /// Returns the sources of all pages of the inputs in the order of the inputs. The pages of a
+    /// PDF document are counted with pdfinfo and rendered with the resolution of the options.
+    /// Images are taken as they are, the resolution stated in their metadata is preferred. Each
+    /// frame of a multi-page image is a page of its own.
+    fn sources(&self, ctx: &Context) -> Result<Vec<Source>, Error> {
+        let run = ctx.run;
+        let inputs: Vec<(usize, &InputFile)> = run.inputs().iter().enumerate().collect();
+        let sources = ctx.scheduler.map(inputs, 1, |(n, file)| match file.kind {
+            InputKind::Pdf => self.document_sources(run, n),
+            InputKind::Image => self.image_sources(run, &file.path),
+        })?;
+        Ok(sources
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(n, x)| Source { page: n, ..x })
+            .collect())
+    }
+
+    /// Reads the metadata of the input documents, the options take precedence. A document whose
+    /// metadata can't be read is skipped.
+    fn read_metadata(&self, run: &Run, sources: &[Source]) -> Metadata {
+        let opt = &self.options;
+        let mut rsl = Metadata::default();
+        rsl.info = Info {
+            title: opt.title.clone(),
+            author: opt.author.clone(),
+            subject: opt.subject.clone(),
+            keywords: opt.keywords.clone(),
+        };
+        for (n, file) in run.inputs().iter().enumerate() {
+            if file.kind != InputKind::Pdf {
+                continue;
+            }
+            let document = run.document(n);
+            let offset = match sources
+                .iter()
+                .find(|x| util::split_page(&x.input).is_some_and(|(x, _)| x == document))
+            {
+                Some(x) => x.page,
+                None => continue,
+            };
+            match Metadata::read(&document, run.password()) {
+                Ok(x) => rsl.append(x, offset),
+                Err(e) => warn!(
+                    "Couldn't read the metadata of {}, {}",
+                    file.path.display(),
+                    e
+                ),
+            }
+        }
+        rsl
+    }
+
+    /// Splits the sources into the pages selected for processing and the other pages.
+    fn select(&self, sources: Vec<Source>) -> Result<(Vec<Source>, Vec<Source>), Error> {
+        let ranges = match &self.options.pages {
+            Some(x) => x,
+            None => return Ok((sources, vec![])),
+        };
+        ranges.check(sources.len())?;
+        let (selected, others): (Vec<Source>, Vec<Source>) = sources
+            .into_iter()
+            .partition(|x| ranges.contains(x.page + 1));
+        debug!(
+            "processing {} of {} pages",
+            selected.len(),
+            selected.len() + others.len()
+        );
+        Ok((selected, others))
+    }
+
+    /// Splits the sources into the pages which need processing and the pages of input documents
+    /// which already contain text. All pages need processing if the OCR is forced. Pages whose
+    /// text is entirely invisible are processed again if requested.
+    fn skip_text(
+        &self,
+        ctx: &Context,
+        sources: Vec<Source>,
+    ) -> Result<(Vec<Source>, Vec<Source>), Error> {
+        let opt = &self.options;
+        if opt.force_ocr {
+            return Ok((sources, vec![]));
+        }
+        let run = ctx.run;
+        // The text of each document is extracted once.
+        let mut text = HashMap::new();
+        for source in &sources {
+            if let Some((document, _)) = util::split_page(&source.input) {
+                if !text.contains_key(&document) && is_pdf(&document) {
+                    let mut pages = pdf::text_pages(run, &document)?;
+                    if opt.redo_ocr && !pages.is_empty() {
+                        let invisible = pdf::invisible_text_pages(&document, run.password())?;
+                        if !invisible.is_empty() {
+                            info!(
+                                "Processing {} pages again whose text is an invisible OCR layer",
+                                invisible.len()
+                            );
+                        }
+                        pages.retain(|x| !invisible.contains(x));
+                    }
+                    text.insert(document, pages);
+                }
+            }
+        }
+        let (text, sources): (Vec<_>, Vec<_>) =
+            sources
+                .into_iter()
+                .partition(|source| match util::split_page(&source.input) {
+                    Some((document, page)) => {
+                        text.get(&document).is_some_and(|x| x.contains(&page))
+                    }
+                    None => false,
+                });
+        if !text.is_empty() {
+            info!(
+                "Taking over {} pages which already contain text, use --force-ocr to process them",
+                text.len()
+            );
+        }
+        Ok((sources, text))
+    }
+
+    /// Replaces the pages of input documents which consist of a single image covering the whole
+    /// page with the extracted image. This way the image is used with its native resolution and
+    /// isn't resampled. With the automatic resolution the other pages with images are rasterized
+    /// at the resolution of their largest image, see `image_use`.
+    fn use_page_images(&self, ctx: &Context, sources: Vec<Source>) -> Result<Vec<Source>, Error> {
+        let opt = &self.options;
+        if opt.no_extract_images && opt.resolution != Resolution::Auto {
+            return Ok(sources);
+        }
+        let run = ctx.run;
+        let mut images = HashMap::new();
+        for source in &sources {
+            if let Some((document, _)) = util::split_page(&source.input) {
+                if !images.contains_key(&document) && is_pdf(&document) {
+                    let rsl = pdf::page_images(run, &document)?;
+                    images.insert(document, rsl);
+                }
+            }
+        }
+        ctx.scheduler.map(sources, 1, |source| {
+            let (document, page) = match util::split_page(&source.input) {
+                Some(x) => x,
+                None => return Ok(source),
+            };
+            let image = match images.get(&document).and_then(|x| x.get(&page)) {
+                Some(x) => x,
+                None => return Ok(source),
+            };
+            let resolution = match self.image_use(image) {
+                ImageUse::Rasterize(x) => {
+                    return Ok(Source {
+                        resolution: x,
+                        ..source
+                    })
+                }
+                ImageUse::Extract => image.resolution,
+            };
+            let prefix = format!("embedded_{:05}", source.page);
+            let input = match pdf::extract_image(run, &document, page, &prefix) {
+                Ok(x) => x,
+                Err(e) => return Err(e.for_page(source.page)),
+            };
+            debug!(
+                "using embedded image of page {} with {} DPI",
+                source.page + 1,
+                resolution
+            );
+            Ok(Source {
+                input,
+                resolution,
+                bilevel: image.bilevel,
+                ..source
+            })
+        })
+    }
+
+    /// Decides how a page with embedded images is processed. A single image covering the whole
+    /// page is extracted with the automatic resolution (up to the maximal resolution) or if its
+    /// resolution equals the fixed one. Otherwise the page is rasterized, with the automatic
+    /// resolution at the resolution of the image if it covers the page. Smaller images (like a
+    /// logo on a page of text) don't lower the resolution below the default one. The maximal
+    /// resolution is never exceeded.
+    fn image_use(&self, image: &pdf::PageImage) -> ImageUse {
+        let opt = &self.options;
+        let extract = image.full_page
+            && !opt.no_extract_images
+            && match opt.resolution {
+                Resolution::Auto => image.resolution <= opt.max_resolution,
+                Resolution::Fixed(x) => image.resolution == x,
+            };
+        if extract {
+            return ImageUse::Extract;
+        }
+        ImageUse::Rasterize(match (opt.resolution, image.covers) {
+            (Resolution::Fixed(x), _) => x,
+            (Resolution::Auto, true) => image.resolution.min(opt.max_resolution),
+            (Resolution::Auto, false) => image
+                .resolution
+                .max(self.default_resolution())
+                .min(opt.max_resolution),
+        })
+    }
+
+    /// Takes the given pages untouched into the output. Pages of input documents are extracted
+    /// with pdfseparate (pdftocairo for encrypted documents), images are wrapped into a PDF file.
+    /// Returns the finished tasks of the pages.
+    fn keep_pages(&self, ctx: &Context, sources: Vec<Source>) -> Result<Vec<Task>, Error> {
+        let run = ctx.run;
+        // The encryption is checked once per document.
+        let mut encrypted = HashMap::new();
+        for source in &sources {
+            if let Some((document, _)) = util::split_page(&source.input) {
+                if !encrypted.contains_key(&document) && is_pdf(&document) {
+                    let rsl = pdf::is_encrypted(run, &document)?;
+                    encrypted.insert(document, rsl);
+                }
+            }
+        }
+        ctx.scheduler.map(sources, 1, |source| {
+            let output = run.build_path(format!("keep_{:05}", source.page), Some("pdf"));
+            let rsl = match util::split_page(&source.input) {
+                Some((document, page)) if is_pdf(&document) => {
+                    let encrypted = encrypted.get(&document).copied().unwrap_or_default();
+                    pdf::extract_page(run, &document, encrypted, page, &output)
+                }
+                _ => convert::wrap_image(
+                    run,
+                    &source.input,
+                    &output,
+                    source.resolution,
+                    source.bilevel,
+                ),
+            };
+            if let Err(e) = rsl {
+                return Err(e.for_page(source.page));
+            }
+            Ok(Task {
+                key: vec![source.page],
+                stage: self.stages.len(),
+                input: output,
+                resolution: source.resolution,
+                bilevel: source.bilevel,
+            })
+        })
+    }
+
+    /// Returns the sources of the pages of the input document at the given position. An encrypted
+    /// document which can't be opened is reported as such.
+    fn document_sources(&self, run: &Run, index: usize) -> Result<Vec<Source>, Error> {
+        let document = run.document(index);
+        let password = self.options.password.as_deref();
+        let pages = match util::count_pdf_pages(
+            document.clone(),
+            run.executable(Tool::Pdfinfo),
+            password,
+        ) {
+            Ok(x) => x,
+            Err(Error::ToolFailed { stderr, .. }) if stderr.contains("Incorrect password") => {
+                let original = run.inputs()[index].path.clone();
+                return Err(match password {
+                    Some(_) => Error::WrongPassword(original),
+                    None => Error::Encrypted(original),
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        Ok((0..pages)
+            .map(|x| Source {
+                page: 0,
+                input: PathBuf::from(format!("{}[{}]", document.display(), x)),
+                resolution: self.default_resolution(),
+                bilevel: false,
+            })
+            .collect())
+    }
+
+    /// Returns the resolution used for pages unless it's determined by their images.
+    fn default_resolution(&self) -> u64 {
+        match self.options.resolution {
+            Resolution::Fixed(x) => x,
+            Resolution::Auto => DEFAULT_RESOLUTION.min(self.options.max_resolution),
+        }
+    }
+
+    /// Returns the sources of the frames of an input image.
+    fn image_sources(&self, run: &Run, image: &Path) -> Result<Vec<Source>, Error> {
+        let resolution = self.default_resolution();
+        let frames = input::frames(image, run.executable(Tool::Convert))?;
+        if frames.len() > 1 {
+            debug!("{} contains {} pages", image.display(), frames.len());
+        }
+        Ok(frames
+            .into_iter()
+            .enumerate()
+            .map(|(n, frame)| Source {
+                page: 0,
+                input: match input::is_multi_page(image) {
+                    true => PathBuf::from(format!("{}[{}]", image.display(), n)),
+                    false => image.to_path_buf(),
+                },
+                resolution: match frame.resolution {
+                    Some(x) => x,
+                    None => {
+                        debug!(
+                            "{} doesn't state its resolution, using {} DPI",
+                            image.display(),
+                            resolution
+                        );
+                        resolution
+                    }
+                },
+                bilevel: frame.bilevel,
+            })
+            .collect())
+    }
+
+    /// Returns the settings which affect the outputs of the stages, a run can only be resumed
+    /// with the same settings.
+    fn settings(&self) -> Profile {
+        Profile {
+            tesseract_threads: None,
+            jobs: None,
+            no_preflight: None,
+            convert_binary: None,
+            unpaper_binary: None,
+            tesseract_binary: None,
+            pdfinfo_binary: None,
+            pdfunite_binary: None,
+            pdfseparate_binary: None,
+            pdftotext_binary: None,
+            pdfimages_binary: None,
+            jbig2_binary: None,
+            pdftocairo_binary: None,
+            cache: None,
+            cache_dir: None,
+            cache_size: None,
+            ..Profile::from_options(&self.options)
+        }
+    }
+
+    /// Executes the stages one after another, waits for the user between the stages. Tasks of
+    /// later stages (from a resumed run) wait for their stage. Returns the tasks of the final
+    /// files.
+    fn execute_stepwise(&self, ctx: &Context, mut tasks: Vec<Task>) -> Result<Vec<Task>, Error> {
+        for (i, stage) in self.stages.iter().enumerate() {
+            let (current, mut later): (Vec<Task>, Vec<Task>) =
+                tasks.into_iter().partition(|x| x.stage == i);
+            ctx.run.log_step(stage.description());
+            later.extend(
+                ctx.scheduler
+                    .map(current, self.demand(ctx, i), |task| {
+                        self.process_task(ctx, task, false)
+                    })?
+                    .into_iter()
+                    .flatten(),
+            );
+            tasks = later;
+            debug!("{} finished stage {}", tasks.len(), i);
+            ctx.run.wait();
+        }
+        Ok(tasks)
+    }
+
+    /// Lets each page flow through the stages on its own. Tasks of later stages are preferred,
+    /// this way pages are finished early and the intermediate files don't pile up. Returns the
+    /// tasks of the final files.
+    fn execute_streaming(&self, ctx: &Context, tasks: Vec<Task>) -> Result<Vec<Task>, Error> {
+        let (done, tasks): (Vec<Task>, Vec<Task>) = tasks
+            .into_iter()
+            .partition(|x| x.stage == self.stages.len());
+        ctx.run.log_step(format!(
+            "Processing {} pages with {}",
+            tasks.len(),
+            self.stage_names().join(", ")
+        ));
+        let done = Mutex::new(done);
+        ctx.scheduler.flow(
+            tasks,
+            |task| task.stage,
+            |task| self.demand(ctx, task.stage),
+            |task| {
+                let (finished, next): (Vec<Task>, Vec<Task>) = self
+                    .process_task(ctx, task, true)?
+                    .into_iter()
+                    .partition(|x| x.stage == self.stages.len());
+                done.lock().unwrap().extend(finished);
+                Ok(next)
+            },
+        )?;
+        Ok(done.into_inner().unwrap())
+    }
+
+    /// Returns what a job of the stage at the given position occupies in the scheduler.
+    fn demand(&self, ctx: &Context, stage: usize) -> Demand {
+        Demand {
+            slots: self.stages[stage].weight(ctx.scheduler.jobs()),
+            limit: self.stages[stage].limit().map(|x| (stage, x)),
+        }
+    }
+
+    /// Processes a task with its stage, records the completed step in the manifest and returns
+    /// the tasks for the next stage. If `cleanup` is set, the input file is removed afterwards.
+    fn process_task(&self, ctx: &Context, task: Task, cleanup: bool) -> Result<Vec<Task>, Error> {
+        let start = Instant::now();
+        let Task {
+            key,
+            stage: i,
+            input,
+            resolution,
+            bilevel,
+        } = task;
+        let stage = &self.stages[i];
+        let prefix = stage_prefix(i);
+        let job = Job {
+            stem: match i {
+                0 => format!("{}_{:05}", prefix, key[0]),
+                _ => format!("{}_{}", prefix, util::file_name(input.clone())),
+            },
+            input,
+            resolution,
+            bilevel,
+        };
+        let outputs = match self.process_job(ctx, stage.as_ref(), &job) {
+            Ok(x) => x,
+            Err(e) => return Err(e.for_page(key[0])),
+        };
+        ctx.manifest.record(i, &key, &outputs)?;
+
+        // The first stage reads from the input files which have to be kept.
+        if cleanup && i > 0 && !outputs.contains(&job.input) {
+            debug!("remove consumed file {}", job.input.display());
+            if let Err(e) = fs::remove_file(&job.input) {
+                warn!("Couldn't remove {}, {}", job.input.display(), e);
+            }
+        }
+        ctx.busy.lock().unwrap()[i] += start.elapsed();
+
+        Ok(outputs
+            .into_iter()
+            .enumerate()
+            .map(|(n, x)| {
+                let mut key = key.clone();
+                key.push(n);
+                Task {
+                    key,
+                    stage: i + 1,
+                    input: x,
+                    resolution,
+                    bilevel,
+                }
+            })
+            .collect())
+    }
+
+    /// Processes a job with the given stage. If the cache is enabled and the stage can be cached,
+    /// the outputs are taken from the cache if possible. The properties of the page are part of
+    /// the key. Failures of the cache aren't fatal.
+    fn process_job(
+        &self,
+        ctx: &Context,
+        stage: &dyn Stage,
+        job: &Job,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let cache = match (&ctx.cache, stage.parameters(ctx.run)) {
+            (Some(cache), Some(parameters)) => {
+                let parameters = format!("{} {} {}", job.resolution, job.bilevel, parameters);
+                match cache.key(stage.name(), &parameters, &job.input) {
+                    Ok(x) => Some((cache, x)),
+                    Err(e) => {
+                        warn!("Couldn't determine cache key, {}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        if let Some((cache, key)) = &cache {
+            match cache.fetch(ctx.run, key, &job.stem) {
+                Ok(Some(x)) => {
+                    debug!("took {} of {} from the cache", stage.name(), job.stem);
+                    ctx.cached.fetch_add(1, Ordering::SeqCst);
+                    return Ok(x);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Couldn't read from the cache, {}", e),
+            }
+        }
+        let outputs = stage.process(ctx.run, job)?;
+        if let Some((cache, key)) = &cache {
+            let mut sidecars = vec![];
+            for extension in stage.sidecars() {
+                sidecars.extend(outputs.iter().map(|x| x.with_extension(&extension)));
+            }
+            if let Err(e) = cache.store(key, &job.stem, &outputs, &sidecars) {
+                warn!("Couldn't write to the cache, {}", e);
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Returns the index of the stage with the given name.
+    fn position(&self, name: &str) -> Result<usize, Error> {
+        match self.stages.iter().position(|x| x.name() == name) {
+            Some(x) => Ok(x),
+            None => Err(Error::invalid_input(format!(
+                "There is no stage named {} in the pipeline",
+                name
+            ))),
+        }
+    }
+
+    /// Checks whether the stages can be chained together. The first stage has to take the pages
+    /// of the input document, the last one has to produce PDF files.
+    fn validate_stages(&self) -> Result<(), Error> {
+        let mut current = Artifact::Document;
+        for stage in &self.stages {
+            if stage.input() != current {
+                return Err(Error::invalid_input(format!(
+                    "Stage {} expects {} files but gets {} files",
+                    stage.name(),
+                    stage.input(),
+                    current
+                )));
+            }
+            current = stage.output();
+        }
+        match current {
+            Artifact::Pdf => Ok(()),
+            _ => Err(Error::invalid_input(format!(
+                "The last stage has to produce pdf files but produces {} files",
+                current
+            ))),
+        }
+    }
+}
+
+/// Returns the sidecar file with the given extension next to an output, None if there is none.
+fn sidecar(output: &Path, extension: &str) -> Option<PathBuf> {
+    Some(output.with_extension(extension)).filter(|x| x.exists())
+}
+
+/// Returns whether a path points to a PDF document (and not a multi-page image).
+fn is_pdf(path: &Path) -> bool {
+    path.extension().is_some_and(|x| x == "pdf")
+}
+
+/// Returns the task of a page for the first stage.
+fn initial_task(source: &Source) -> Task {
+    Task {
+        key: vec![source.page],
+        stage: 0,
+        input: source.input.clone(),
+        resolution: source.resolution,
+        bilevel: source.bilevel,
+    }
+}
+
+/// Returns the file name prefix of the output of the stage at the given position (a, b, c, ...).
+fn stage_prefix(i: usize) -> String {
+    match i < 26 {
+        true => ((b'a' + i as u8) as char).to_string(),
+        false => format!("z{}", i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(folder: &Path, page: usize) -> Source {
+        Source {
+            page,
+            input: folder.join(format!("page-{}.pdf", page)),
+            resolution: 300,
+            bilevel: false,
+        }
+    }
+
+    fn entry(folder: &Path, stage: usize, key: &[usize], outputs: &[&str]) -> Entry {
+        for output in outputs {
+            fs::write(folder.join(output), b"").unwrap();
+        }
+        Entry {
+            stage,
+            key: key.to_vec(),
+            outputs: outputs.iter().map(|x| folder.join(x)).collect(),
+        }
+    }
+
+    fn image(resolution: u64, covers: bool, full_page: bool) -> pdf::PageImage {
+        pdf::PageImage {
+            resolution,
+            bilevel: false,
+            covers,
+            full_page,
+        }
+    }
+
+    #[test]
+    fn automatic_resolution_of_page_images() {
+        let options = PipelineOptions::new("in.pdf").resolution(Resolution::Auto);
+        let pipeline = Pipeline::new(options.clone());
+        let phone_scan = image(150, true, true);
+        assert_eq!(pipeline.image_use(&phone_scan), ImageUse::Extract);
+        assert_eq!(
+            pipeline.image_use(&image(800, true, true)),
+            ImageUse::Rasterize(600)
+        );
+        // A scan with content drawn over it isn't upscaled.
+        assert_eq!(
+            pipeline.image_use(&image(150, true, false)),
+            ImageUse::Rasterize(150)
+        );
+        assert_eq!(
+            pipeline.image_use(&image(800, true, false)),
+            ImageUse::Rasterize(600)
+        );
+        // Small images don't lower the resolution.
+        assert_eq!(
+            pipeline.image_use(&image(72, false, false)),
+            ImageUse::Rasterize(300)
+        );
+        assert_eq!(
+            pipeline.image_use(&image(400, false, false)),
+            ImageUse::Rasterize(400)
+        );
+
+        let pipeline = Pipeline::new(options.clone().no_extract_images(true));
+        assert_eq!(pipeline.image_use(&phone_scan), ImageUse::Rasterize(150));
+
+        let pipeline = Pipeline::new(options.max_resolution(200));
+        assert_eq!(
+            pipeline.image_use(&image(72, false, false)),
+            ImageUse::Rasterize(200)
+        );
+        assert_eq!(
+            pipeline.image_use(&image(300, true, true)),
+            ImageUse::Rasterize(200)
+        );
+    }
+
+    #[test]
+    fn fixed_resolution_of_page_images() {
+        let pipeline = Pipeline::new(PipelineOptions::new("in.pdf").resolution(300));
+        assert_eq!(
+            pipeline.image_use(&image(300, true, true)),
+            ImageUse::Extract
+        );
+        assert_eq!(
+            pipeline.image_use(&image(150, true, true)),
+            ImageUse::Rasterize(300)
+        );
+        assert_eq!(
+            pipeline.image_use(&image(600, true, false)),
+            ImageUse::Rasterize(300)
+        );
+    }
+
+    #[test]
+    fn replay_of_journal() {
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        let pipeline = Pipeline::new(PipelineOptions::new("in.pdf"));
+        let last = pipeline.stages.len() - 1;
+        let sources: Vec<Source> = (0..4).map(|x| source(folder, x)).collect();
+        let mut entries = vec![
+            // Page 0 completed the first stage.
+            entry(folder, 0, &[0], &["a-0000.png"]),
+            // The output of page 1 was removed.
+            Entry {
+                stage: 0,
+                key: vec![1],
+                outputs: vec![folder.join("a-0001.png")],
+            },
+            // Page 2 was split into two files, the first one completed the second stage.
+            entry(folder, 0, &[2], &["a-0002-0.png", "a-0002-1.png"]),
+            entry(folder, 1, &[2, 0], &["b-0002-0.png"]),
+            // Doesn't match the stage of the file, the step is ignored.
+            entry(folder, 1, &[0], &["b-0000.png"]),
+            // Page 3 completed all stages.
+            entry(folder, 0, &[3], &["a-0003.png"]),
+        ];
+        let mut key = vec![3, 0];
+        for stage in 1..=last {
+            let name = format!("{}-0003.png", stage_prefix(stage));
+            entries.push(entry(folder, stage, &key, &[&name]));
+            key.push(0);
+        }
+
+        let mut tasks = pipeline.resume_tasks(&sources, entries);
+        tasks.sort_by(|a, b| a.key.cmp(&b.key));
+        let tasks: Vec<(Vec<usize>, usize, PathBuf)> = tasks
+            .into_iter()
+            .map(|x| (x.key, x.stage, x.input))
+            .collect();
+        let mut expected = vec![
+            (vec![0, 0], 1, folder.join("a-0000.png")),
+            (vec![1], 0, folder.join("page-1.pdf")),
+            (vec![2, 0, 0], 2, folder.join("b-0002-0.png")),
+            (vec![2, 1], 1, folder.join("a-0002-1.png")),
+        ];
+        expected.push((
+            key,
+            last + 1,
+            folder.join(format!("{}-0003.png", stage_prefix(last))),
+        ));
+        assert_eq!(tasks, expected);
+    }
+}
@@ -1,15 +1,18 @@
-use crate::error::ErrorMessage;
+use crate::binary::{Binaries, Executable, Tool};
+use crate::error::Error;
+use crate::input::{self, InputFile, InputKind, STDIO};
+use crate::pipeline::PipelineOptions;
 
 use std::env;
 use std::fs;
-use std::io::stdin;
-use std::path::PathBuf;
+use std::io::{self, stdin, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
-use shellexpand;
 use tempfile::{Builder, TempDir};
 
-/// Name of the start file in the temporary folder.
-pub const START_PDF: &str = "input.pdf";
+/// Name of the output file within the folder of the run, used when writing to stdout.
+const OUTPUT_PDF: &str = "output.pdf";
 
 /// Enumeration of the three possible image formats used within the process.
 #[derive(Debug, Clone, Copy)]
@@ -26,7 +29,7 @@ pub enum Format {
 
 impl Format {
     /// Returns the appropriate image format based on the user input.
-    fn from(use_gray: bool, use_rgb: bool) -> Self {
+    pub fn from(use_gray: bool, use_rgb: bool) -> Self {
         match (use_gray, use_rgb) {
             (true, false) => Format::Graymap,
             (false, true) => Format::Pixmap,
@@ -48,87 +51,165 @@ impl Format {
 /// This struct contains all the needed information and states to go trough the different
 /// conversion steps. The object manages the temporary folder.
 pub struct Run {
-    /// Absolute path to the input file.
+    /// Absolute path to the (first) input file or folder as given by the user.
     input: PathBuf,
-    /// Absolute path to the output file.
+    /// The input files, the content of folders is expanded.
+    inputs: Vec<InputFile>,
+    /// Absolute path to the output file, `-` stands for stdout.
     output: Option<PathBuf>,
-    /// Temporary folder where the conversion happens.
-    folder: TempDir,
+    /// Folder where the conversion happens.
+    folder: PathBuf,
+    /// Guard of the temporary folder which removes the folder when the run is dropped. None if
+    /// the folder is kept (a working directory was given or the user wants to keep the files).
+    temp: Option<TempDir>,
     /// Provides a comfortable way to pause between the steps if the user did enable the function.
     /// Contains a boolean whether the wait should be executed or not.
     do_step: bool,
     /// Image file format used internally.
     pub format: Format,
+    /// Names of the external binaries.
+    binaries: Binaries,
+    /// Password of encrypted input documents.
+    password: Option<String>,
 }
 
 impl Run {
-    /// Returns a new run based on an input file and an optional output file path. The given input
-    /// path will get shell-expanded and normalized as absolute path relative to the current
+    /// Returns a new run based on the input files and an optional output file path. The given
+    /// input paths will get shell-expanded and normalized as absolute path relative to the current
     /// working directory. The shell-expansion enables the usage of the tilde (`~`) as abbreviation
-    /// of the home folder and environment variables. The existence of the input file is tested.
-    /// The `do_step` states whether to pause between the steps.
-    ///
-    /// To modify the object further use the build pattern and finish the configuration with
-    /// init().
-    pub fn new<S: Into<String>>(
-        input: S,
-        output: Option<S>,
-        use_gray: bool,
-        use_rgb: bool,
-        do_step: bool,
-    ) -> Result<Self, ErrorMessage> {
-        let input = Run::expand_path(input.into())?;
-        Run::validate_input_file(&input)?;
+    /// of the home folder and environment variables. The existence of the input files is tested.
+    /// The step option states whether to pause between the steps. If a working directory is
+    /// given, it's used (and created if needed) instead of a temporary folder. An input of `-` is
+    /// read from stdin into the folder, the output then defaults to stdout.
+    pub fn new(options: &PipelineOptions) -> Result<Self, Error> {
+        let from_stdin = options.inputs.iter().any(|x| x.as_os_str() == STDIO);
+        let to_stdout = match &options.output {
+            Some(x) => x.as_os_str() == STDIO,
+            None => from_stdin,
+        };
 
-        let rsl = Self {
-            input: input.clone(),
-            output: match output {
-                Some(x) => Some(Run::expand_path(x.into())?),
-                None => None,
-            },
-            folder: match Builder::new().prefix("pdfiron-").tempdir() {
-                Ok(x) => x,
-                Err(e) => {
-                    return Err(ErrorMessage::new(format!(
-                        "Couldn't create temp folder, {}",
-                        e
-                    )))
+        let (folder, temp) = match &options.workdir {
+            Some(x) => {
+                let folder = Run::expand_path(x.to_string_lossy().into_owned())?;
+                if let Err(e) = fs::create_dir_all(&folder) {
+                    return Err(Error::io(
+                        format!("Couldn't create working directory {}", folder.display()),
+                        e,
+                    ));
                 }
+                (folder, None)
+            }
+            None => match Builder::new().prefix("pdfiron-").tempdir() {
+                Ok(x) if options.keep_temp => (x.keep(), None),
+                Ok(x) => (x.path().to_path_buf(), Some(x)),
+                Err(e) => return Err(Error::io("Couldn't create temp folder", e)),
+            },
+        };
+
+        let mut paths = vec![];
+        for path in &options.inputs {
+            paths.push(match path.as_os_str() == STDIO {
+                true => input::read_stdin(&folder)?,
+                false => Run::expand_path(path.to_string_lossy().into_owned())?,
+            });
+        }
+        let inputs = input::collect(&paths)?;
+
+        let rsl = Self {
+            input: paths[0].clone(),
+            inputs,
+            output: match (&options.output, to_stdout) {
+                (_, true) => Some(PathBuf::from(STDIO)),
+                (Some(x), false) => Some(Run::expand_path(x.to_string_lossy().into_owned())?),
+                (None, false) => None,
             },
-            do_step: do_step,
-            format: Format::from(use_gray, use_rgb),
+            folder,
+            temp,
+            do_step: options.step,
+            format: options.format,
+            binaries: options.binaries.clone(),
+            password: options.password.clone(),
         };
 
-        rsl.log_folder_path(rsl.folder.path().to_path_buf());
-
-        let in_dst = rsl.prepend_with_temp_folder(START_PDF);
-        debug!("copy {} to {}", input.display(), in_dst.display());
-        match fs::copy(&input, &in_dst) {
-            Ok(_) => Ok(rsl),
-            Err(e) => Err(ErrorMessage::new(format!(
-                "Couldn't copy input file to {}, {}",
-                in_dst.display(),
-                e
-            ))),
+        rsl.log_folder_path(rsl.folder.clone());
+
+        // Images are read directly from their location.
+        for (n, input) in rsl.inputs.iter().enumerate() {
+            if input.kind != InputKind::Pdf {
+                continue;
+            }
+            let in_dst = rsl.document(n);
+            debug!("copy {} to {}", input.path.display(), in_dst.display());
+            if let Err(e) = fs::copy(&input.path, &in_dst) {
+                return Err(Error::io(
+                    format!("Couldn't copy input file to {}", in_dst.display()),
+                    e,
+                ));
+            }
+        }
+        Ok(rsl)
+    }
+
+    /// Returns the executable of an external tool.
+    pub fn executable(&self, tool: Tool) -> &Executable {
+        self.binaries.get(tool)
+    }
+
+    /// Returns a new command calling the executable of an external tool.
+    pub fn command(&self, tool: Tool) -> Command {
+        self.binaries.get(tool).command()
+    }
+
+    /// Returns the password of encrypted input documents.
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    /// Returns the absolute path to the (first) input file or folder as given by the user.
+    pub fn input(&self) -> &Path {
+        &self.input
+    }
+
+    /// Returns the input files, the content of folders is expanded.
+    pub fn inputs(&self) -> &[InputFile] {
+        &self.inputs
+    }
+
+    /// Returns the path to the copy of the input document at the given position within the
+    /// inputs.
+    pub fn document(&self, index: usize) -> PathBuf {
+        self.prepend_with_temp_folder(format!("input_{:03}.pdf", index))
+    }
+
+    /// Returns the path to the folder where the conversion happens.
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+
+    /// Prevents the removal of the temporary folder at the end of the run and returns its path.
+    pub fn keep(&mut self) -> PathBuf {
+        match self.temp.take() {
+            Some(x) => x.keep(),
+            None => self.folder.clone(),
         }
     }
 
+    /// Returns whether the folder of the run persists after the run.
+    pub fn is_kept(&self) -> bool {
+        self.temp.is_none()
+    }
+
     /// Returns the path to the temporary folder with some path appended.
-    pub fn prepend_with_temp_folder<'a, S: Into<String>>(&self, path: S) -> PathBuf {
-        self.folder.path().join(path.into())
+    pub fn prepend_with_temp_folder<S: Into<String>>(&self, path: S) -> PathBuf {
+        self.folder.join(path.into())
     }
 
     /// Returns a Vector with all paths of the files in the temporary folder with a given prefix
     /// and a optional file ending.
-    pub fn query_files<'a>(&self, starts_with: &'a str) -> Result<Vec<PathBuf>, ErrorMessage> {
+    pub fn query_files(&self, starts_with: &str) -> Result<Vec<PathBuf>, Error> {
         let elements = match fs::read_dir(&self.folder) {
             Ok(x) => x,
-            Err(e) => {
-                return Err(ErrorMessage::new(format!(
-                    "Couldn't read content of temp folder, {}",
-                    e
-                )))
-            }
+            Err(e) => return Err(Error::io("Couldn't read content of temp folder", e)),
         };
 
         Ok(elements
@@ -142,11 +223,7 @@ impl Run {
 
     /// Joins (in this order) the temporary folder path with the given filename and the given
     /// extension. If None the extension will be determined.
-    pub fn build_path<'a, S: Into<String>>(
-        &self,
-        filename: S,
-        extension: Option<&'a str>,
-    ) -> PathBuf {
+    pub fn build_path<S: Into<String>>(&self, filename: S, extension: Option<&str>) -> PathBuf {
         let mut pth = self.prepend_with_temp_folder(filename);
         pth.set_extension(match extension {
             Some(x) => x,
@@ -180,22 +257,46 @@ impl Run {
     /// If the step mode was enabled the method will wait until user hits enter. This is used for
     /// the pause between steps mode. Allowing the user to tweak the files in the temporary folder
     pub fn wait(&self) {
-        match self.do_step {
-            true => {
-                println!("Hit enter to proceed with next step...");
-                let mut void = String::new();
-                match stdin().read_line(&mut void) {
-                    Ok(_) => {}
-                    Err(e) => error!("couldn't read line, {}", e),
-                }
+        if self.do_step {
+            eprintln!("Hit enter to proceed with next step...");
+            let mut void = String::new();
+            match stdin().read_line(&mut void) {
+                Ok(_) => {}
+                Err(e) => error!("couldn't read line, {}", e),
             }
-            false => {}
+        }
+    }
+
+    /// Returns whether the output is written to stdout.
+    pub fn to_stdout(&self) -> bool {
+        match &self.output {
+            Some(x) => x.as_os_str() == STDIO,
+            None => false,
+        }
+    }
+
+    /// Streams the output file to stdout.
+    pub fn write_to_stdout(&self) -> Result<(), Error> {
+        let mut file = match fs::File::open(self.output_path()) {
+            Ok(x) => x,
+            Err(e) => return Err(Error::io("Couldn't open output file", e)),
+        };
+        let stdout = io::stdout();
+        let mut lock = stdout.lock();
+        match io::copy(&mut file, &mut lock).and_then(|_| lock.flush()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::io("Couldn't write output to stdout", e)),
         }
     }
 
     /// Returns the output path for the PDF. If the user didn't specify a path, a default path will
-    /// be used in the same folder as the input file.
+    /// be used in the same folder as the input file. The name of an image or a folder of images
+    /// gets the extension of a PDF file. Output written to stdout is assembled in the folder of
+    /// the run first.
     pub fn output_path(&self) -> PathBuf {
+        if self.to_stdout() {
+            return self.prepend_with_temp_folder(OUTPUT_PDF);
+        }
         match &self.output {
             Some(x) => x.clone(),
             None => {
@@ -204,22 +305,23 @@ impl Run {
                 let name = self.input.file_name().unwrap().to_string_lossy();
                 let name_ele = name.split(".").collect::<Vec<&str>>();
                 let name_parts = name_ele.split_first().unwrap();
-                rsl.push(format!(
-                    "{}-ironed.{}",
-                    name_parts.0,
-                    name_parts.1.join(".")
-                ));
+                rsl.push(match self.inputs[0].kind {
+                    InputKind::Pdf => {
+                        format!("{}-ironed.{}", name_parts.0, name_parts.1.join("."))
+                    }
+                    InputKind::Image => format!("{}-ironed.pdf", name_parts.0),
+                });
                 rsl
             }
         }
     }
 
     /// Shell expands a path and normalize it to an absolute path.
-    fn expand_path(file: String) -> Result<PathBuf, ErrorMessage> {
+    fn expand_path(file: String) -> Result<PathBuf, Error> {
         let expanded = match shellexpand::full(&file) {
             Ok(x) => x,
             Err(e) => {
-                return Err(ErrorMessage::new(format!(
+                return Err(Error::invalid_input(format!(
                     "Couldn't shell-expand given input path {}, {}.",
                     file, e
                 )))
@@ -234,32 +336,11 @@ impl Run {
             true => Ok(input),
             false => match env::current_dir() {
                 Ok(x) => Ok(x.join(input)),
-                Err(e) => Err(ErrorMessage::new(format!(
-                    "Couldn't determine working directory to normalize input file, {}.",
+                Err(e) => Err(Error::io(
+                    "Couldn't determine working directory to normalize input file",
                     e,
-                ))),
-            },
-        }
-    }
-
-    /// Checks if a file exists and is a PDF file.
-    fn validate_input_file(file: &PathBuf) -> Result<(), ErrorMessage> {
-        let not_pdf_err = ErrorMessage::new(format!(
-            "Given input file {} isn't a PDF file",
-            file.display()
-        ));
-        match file.exists() {
-            true => match file.extension() {
-                Some(x) => match x.to_string_lossy().to_lowercase() == "pdf" {
-                    true => Ok(()),
-                    false => Err(not_pdf_err),
-                },
-                None => Err(not_pdf_err),
+                )),
             },
-            false => Err(ErrorMessage::new(format!(
-                "Given input file {} doesn't exist",
-                file.display(),
-            ))),
         }
     }
 }
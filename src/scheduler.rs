@@ -0,0 +1,519 @@
+/// The scheduler distributes the work of the stages on a pool of worker threads. The number of
+/// jobs running at the same time is limited by a global budget of slots (usually the number of
+/// cores). Each job occupies a given number of slots, this way tools using multiple cores by
+/// themselves (like Tesseract) don't overload the system. Jobs can also belong to a group with an
+/// upper limit of jobs running at the same time, like the processes of a tool.
+use crate::error::Error;
+
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// Executes jobs in parallel while respecting the global limit of slots.
+pub struct Scheduler {
+    /// Total number of slots.
+    capacity: usize,
+    /// State of the slots.
+    slots: Mutex<Slots>,
+    /// Signals the release of slots.
+    released: Condvar,
+}
+
+/// Bookkeeping of the slots. Jobs get their slots in the order they asked for them, this way a
+/// job occupying many slots isn't starved by a stream of small jobs.
+struct Slots {
+    /// Number of currently free slots.
+    available: usize,
+    /// Ticket handed out to the next job asking for slots.
+    next_ticket: u64,
+    /// Ticket of the job which is allowed to occupy slots next.
+    serving: u64,
+}
+
+/// What a job occupies while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Demand {
+    /// Number of slots.
+    pub slots: usize,
+    /// The group of the job and the maximal number of jobs of the group running at once.
+    pub limit: Option<(usize, usize)>,
+}
+
+impl From<usize> for Demand {
+    fn from(slots: usize) -> Self {
+        Self { slots, limit: None }
+    }
+}
+
+/// Queue of a streaming execution.
+struct Flow<T> {
+    /// Tasks waiting for execution, ordered by priority and insertion.
+    queue: BinaryHeap<Queued<T>>,
+    /// Number of tasks currently executed.
+    running: usize,
+    /// Number of tasks currently executed per limited group.
+    groups: HashMap<usize, usize>,
+    /// Number of tasks queued so far, used to keep the insertion order.
+    counter: u64,
+}
+
+/// A task in the queue of a streaming execution.
+struct Queued<T> {
+    priority: usize,
+    seq: Reverse<u64>,
+    task: T,
+}
+
+impl<T> PartialEq for Queued<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.priority, self.seq) == (other.priority, other.seq)
+    }
+}
+
+impl<T> Eq for Queued<T> {}
+
+impl<T> PartialOrd for Queued<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Queued<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.priority, self.seq).cmp(&(other.priority, other.seq))
+    }
+}
+
+impl<T> Flow<T> {
+    /// Adds a task to the queue.
+    fn push(&mut self, priority: usize, task: T) {
+        self.queue.push(Queued {
+            priority,
+            seq: Reverse(self.counter),
+            task,
+        });
+        self.counter += 1;
+    }
+
+    /// Takes the queued task with the highest priority whose group hasn't reached its limit.
+    fn pop<D: Fn(&T) -> Demand>(&mut self, demand: &D) -> Option<(T, Demand)> {
+        let mut skipped = vec![];
+        let mut rsl = None;
+        while let Some(x) = self.queue.pop() {
+            let d = demand(&x.task);
+            match d.limit {
+                Some((group, limit)) if self.groups.get(&group).copied().unwrap_or(0) >= limit => {
+                    skipped.push(x)
+                }
+                _ => {
+                    rsl = Some((x.task, d));
+                    break;
+                }
+            }
+        }
+        self.queue.extend(skipped);
+        rsl
+    }
+}
+
+/// Slots occupied by a running job. The slots are released when the permit is dropped, this also
+/// happens when the job panics.
+struct Permit<'a> {
+    scheduler: &'a Scheduler,
+    weight: usize,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut slots = self.scheduler.slots.lock().unwrap();
+        slots.available += self.weight;
+        self.scheduler.released.notify_all();
+    }
+}
+
+impl Scheduler {
+    /// Returns a new scheduler with the given number of slots. At least one slot is used.
+    pub fn new(jobs: usize) -> Self {
+        let capacity = jobs.max(1);
+        Self {
+            capacity,
+            slots: Mutex::new(Slots {
+                available: capacity,
+                next_ticket: 0,
+                serving: 0,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Returns the total number of slots.
+    pub fn jobs(&self) -> usize {
+        self.capacity
+    }
+
+    /// Applies the function to all items in parallel, every call occupies the given number of
+    /// slots (and respects the limit of its group). The results are returned in the order of the
+    /// items. When a job fails no further jobs are started and the first error is returned. A
+    /// panicking job is reported as error.
+    pub fn map<T, R, D, F>(&self, items: Vec<T>, demand: D, f: F) -> Result<Vec<R>, Error>
+    where
+        T: Send,
+        R: Send,
+        D: Into<Demand>,
+        F: Fn(T) -> Result<R, Error> + Sync,
+    {
+        let n_items = items.len();
+        let demand = demand.into();
+        let weight = self.clamp_weight(demand.slots);
+        let limit = demand.limit.map_or(usize::MAX, |(_, x)| x);
+        let n_workers = (self.capacity / weight).min(limit).max(1).min(n_items);
+        let queue = Mutex::new(items.into_iter().enumerate().rev().collect::<Vec<_>>());
+        let results: Mutex<Vec<Option<R>>> = Mutex::new((0..n_items).map(|_| None).collect());
+        let failed = AtomicBool::new(false);
+
+        let outcomes = thread::scope(|s| {
+            let handles: Vec<_> = (0..n_workers)
+                .map(|_| {
+                    s.spawn(|| -> Result<(), Error> {
+                        loop {
+                            if failed.load(Ordering::SeqCst) {
+                                return Ok(());
+                            }
+                            let (n, item) = match queue.lock().unwrap().pop() {
+                                Some(x) => x,
+                                None => return Ok(()),
+                            };
+                            let permit = self.acquire(weight);
+                            let rsl = catch_panic(|| f(item));
+                            drop(permit);
+                            match rsl {
+                                Ok(x) => results.lock().unwrap()[n] = Some(x),
+                                Err(e) => {
+                                    failed.store(true, Ordering::SeqCst);
+                                    return Err(e);
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|x| match x.join() {
+                    Ok(x) => x,
+                    Err(e) => Err(Error::internal(format!(
+                        "A worker thread panicked, {}",
+                        panic_message(&e)
+                    ))),
+                })
+                .collect::<Vec<_>>()
+        });
+        for outcome in outcomes {
+            outcome?;
+        }
+
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|x| match x {
+                Some(x) => Ok(x),
+                None => Err(Error::internal("A job of the scheduler wasn't executed")),
+            })
+            .collect()
+    }
+
+    /// Executes the tasks and all follow-up tasks returned by the function. This enables a task
+    /// to flow through multiple steps without waiting for all other tasks to finish a step.
+    /// Queued tasks with a higher priority are started first, tasks with the same priority in
+    /// the order they were queued, skipping tasks whose group reached its limit. The demand
+    /// function states the slots a task occupies and its group. When a task fails no further
+    /// tasks are started and the first error is returned.
+    pub fn flow<T, P, D, F>(&self, tasks: Vec<T>, priority: P, demand: D, f: F) -> Result<(), Error>
+    where
+        T: Send,
+        P: Fn(&T) -> usize + Sync,
+        D: Fn(&T) -> Demand + Sync,
+        F: Fn(T) -> Result<Vec<T>, Error> + Sync,
+    {
+        let mut flow = Flow {
+            queue: BinaryHeap::new(),
+            running: 0,
+            groups: HashMap::new(),
+            counter: 0,
+        };
+        for task in tasks {
+            flow.push(priority(&task), task);
+        }
+        let flow = Mutex::new(flow);
+        let changed = Condvar::new();
+        let failed = AtomicBool::new(false);
+
+        let outcomes = thread::scope(|s| {
+            let handles: Vec<_> = (0..self.capacity)
+                .map(|_| {
+                    s.spawn(|| -> Result<(), Error> {
+                        loop {
+                            let mut state = flow.lock().unwrap();
+                            let (task, d) = loop {
+                                if failed.load(Ordering::SeqCst) {
+                                    return Ok(());
+                                }
+                                match state.pop(&demand) {
+                                    Some(x) => break x,
+                                    None if state.running == 0 => return Ok(()),
+                                    None => state = changed.wait(state).unwrap(),
+                                }
+                            };
+                            state.running += 1;
+                            if let Some((group, _)) = d.limit {
+                                *state.groups.entry(group).or_insert(0) += 1;
+                            }
+                            drop(state);
+
+                            let permit = self.acquire(d.slots);
+                            let rsl = catch_panic(|| f(task));
+                            drop(permit);
+
+                            let mut state = flow.lock().unwrap();
+                            state.running -= 1;
+                            if let Some((group, _)) = d.limit {
+                                *state.groups.entry(group).or_insert(1) -= 1;
+                            }
+                            let rsl = match rsl {
+                                Ok(x) => {
+                                    for task in x {
+                                        state.push(priority(&task), task);
+                                    }
+                                    Ok(())
+                                }
+                                Err(e) => {
+                                    failed.store(true, Ordering::SeqCst);
+                                    Err(e)
+                                }
+                            };
+                            changed.notify_all();
+                            rsl?;
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|x| match x.join() {
+                    Ok(x) => x,
+                    Err(e) => Err(Error::internal(format!(
+                        "A worker thread panicked, {}",
+                        panic_message(&e)
+                    ))),
+                })
+                .collect::<Vec<_>>()
+        });
+        for outcome in outcomes {
+            outcome?;
+        }
+        Ok(())
+    }
+
+    /// Waits until the given number of slots is available and occupies them.
+    fn acquire(&self, weight: usize) -> Permit<'_> {
+        let weight = self.clamp_weight(weight);
+        let mut slots = self.slots.lock().unwrap();
+        let ticket = slots.next_ticket;
+        slots.next_ticket += 1;
+        while slots.serving != ticket || slots.available < weight {
+            slots = self.released.wait(slots).unwrap();
+        }
+        slots.available -= weight;
+        slots.serving += 1;
+        self.released.notify_all();
+        Permit {
+            scheduler: self,
+            weight,
+        }
+    }
+
+    /// A job can't occupy more slots than available in total and at least needs one slot.
+    fn clamp_weight(&self, weight: usize) -> usize {
+        weight.clamp(1, self.capacity)
+    }
+}
+
+/// Executes the function and reports a panic as error. This way the slots and the bookkeeping of
+/// the scheduler stay intact even if a job panics.
+fn catch_panic<R, F>(f: F) -> Result<R, Error>
+where
+    F: FnOnce() -> Result<R, Error>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(x) => x,
+        Err(e) => Err(Error::internal(format!(
+            "A job panicked, {}",
+            panic_message(&e)
+        ))),
+    }
+}
+
+/// Extracts the message of a panic payload.
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(x) => String::from(*x),
+        None => match payload.downcast_ref::<String>() {
+            Some(x) => x.clone(),
+            None => String::from("<unknown panic>"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    /// Counts the jobs running at the same time and keeps the maximum.
+    #[derive(Default)]
+    struct Gauge {
+        current: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    impl Gauge {
+        /// Runs a job which takes a while and records it.
+        fn run(&self, millis: u64) {
+            let n = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(n, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(millis));
+            self.current.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        fn max(&self) -> usize {
+            self.max.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn map_respects_the_slots() {
+        let scheduler = Scheduler::new(4);
+        let gauge = Gauge::default();
+        scheduler
+            .map((0..12).collect(), 2, |_: usize| {
+                gauge.run(10);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(gauge.max(), 2);
+
+        let gauge = Gauge::default();
+        let demand = Demand {
+            slots: 1,
+            limit: Some((0, 3)),
+        };
+        scheduler
+            .map((0..12).collect(), demand, |_: usize| {
+                gauge.run(10);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(gauge.max(), 3);
+    }
+
+    #[test]
+    fn map_keeps_the_order() {
+        let scheduler = Scheduler::new(4);
+        let items: Vec<u64> = (0..8).collect();
+        // The first items take the longest and finish last.
+        let rsl = scheduler
+            .map(items.clone(), 1, |x| {
+                thread::sleep(Duration::from_millis(5 * (8 - x)));
+                Ok(x * 10)
+            })
+            .unwrap();
+        assert_eq!(rsl, items.iter().map(|x| x * 10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn map_reports_panics() {
+        let scheduler = Scheduler::new(4);
+        let rsl = scheduler.map((0..8).collect(), 1, |x: usize| {
+            if x == 2 {
+                panic!("job {} broke", x);
+            }
+            Ok(x)
+        });
+        match rsl {
+            Err(Error::Internal(x)) => assert!(x.contains("job 2 broke"), "{}", x),
+            x => panic!("unexpected result {:?}", x.map(|_| ())),
+        }
+        // The slots are released, the scheduler is still usable.
+        assert_eq!(scheduler.map(vec![1, 2], 4, Ok).unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn flow_respects_slots_and_limits() {
+        let scheduler = Scheduler::new(4);
+        // Tasks are (step, id): step 0 is light, step 1 heavy and step 2 limited to two tasks.
+        let light = Gauge::default();
+        let heavy = Gauge::default();
+        let limited = Gauge::default();
+        let finished = AtomicUsize::new(0);
+        scheduler
+            .flow(
+                (0..6).map(|x| (0, x)).collect(),
+                |x: &(usize, usize)| x.0,
+                |x| match x.0 {
+                    0 => Demand::from(1),
+                    1 => Demand::from(3),
+                    _ => Demand {
+                        slots: 1,
+                        limit: Some((2, 2)),
+                    },
+                },
+                |(step, id)| {
+                    match step {
+                        0 => light.run(5),
+                        1 => heavy.run(5),
+                        _ => limited.run(5),
+                    }
+                    match step {
+                        2 => {
+                            finished.fetch_add(1, Ordering::SeqCst);
+                            Ok(vec![])
+                        }
+                        _ => Ok(vec![(step + 1, id)]),
+                    }
+                },
+            )
+            .unwrap();
+        assert_eq!(finished.load(Ordering::SeqCst), 6);
+        assert!(light.max() <= 4);
+        assert_eq!(heavy.max(), 1);
+        assert!(limited.max() <= 2);
+    }
+
+    #[test]
+    fn flow_reports_panics() {
+        let scheduler = Scheduler::new(3);
+        let rsl = scheduler.flow(
+            (0..6).collect(),
+            |_: &usize| 0,
+            |_| Demand::from(1),
+            |x| {
+                thread::sleep(Duration::from_millis(2));
+                match x {
+                    4 => panic!("task {} broke", x),
+                    _ => Ok(vec![]),
+                }
+            },
+        );
+        match rsl {
+            Err(Error::Internal(x)) => assert!(x.contains("task 4 broke"), "{}", x),
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+}
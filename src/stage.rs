@@ -0,0 +1,186 @@
+/// A stage is one step of the conversion pipeline (for example the enhancement with unpaper). Each
+/// stage declares which kind of files it consumes and produces, this way the pipeline can check
+/// whether a given sequence of stages is valid. Stages work on a single page at a time, the
+/// pipeline takes care of the parallel execution.
+use crate::binary::Executable;
+use crate::error::Error;
+use crate::run::Run;
+use crate::util;
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// The kind of files a stage consumes or produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artifact {
+    /// A page of the input, either a page of an input document addressed as `input_000.pdf[n]`,
+    /// an input image or a frame of a multi-page image addressed as `image.tiff[n]`.
+    Document,
+    /// An image in the internal format of the run (see [`crate::Format`]).
+    Image,
+    /// A TIFF image, used as the input for Tesseract.
+    Tiff,
+    /// A single page PDF file.
+    Pdf,
+}
+
+impl Artifact {
+    /// Returns the file extension of the artifact. None states the extension depends on the
+    /// internal image format of the run.
+    pub fn extension<'a>(self) -> Option<&'a str> {
+        match self {
+            Artifact::Document => Some("pdf"),
+            Artifact::Image => None,
+            Artifact::Tiff => Some("tiff"),
+            Artifact::Pdf => Some("pdf"),
+        }
+    }
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Artifact::Document => write!(f, "document"),
+            Artifact::Image => write!(f, "image"),
+            Artifact::Tiff => write!(f, "tiff"),
+            Artifact::Pdf => write!(f, "pdf"),
+        }
+    }
+}
+
+/// A single unit of work for a stage.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// Path to the input file.
+    pub input: PathBuf,
+    /// File name (without extension) the output should be saved as, the file is located in the
+    /// temporary folder of the run. Stages producing more than one file per input append a
+    /// suffix to the stem.
+    pub stem: String,
+    /// Resolution of the page in DPI.
+    pub resolution: u64,
+    /// Whether the page is a bilevel (black and white only) image, these pages are kept bilevel.
+    pub bilevel: bool,
+}
+
+impl Job {
+    /// Returns the path of the output file for the given artifact.
+    pub fn output(&self, run: &Run, artifact: Artifact) -> PathBuf {
+        run.build_path(&self.stem, artifact.extension())
+    }
+}
+
+/// One step of the conversion process. Implement this trait to add a custom step to the
+/// [`crate::Pipeline`].
+pub trait Stage: Send + Sync {
+    /// Short name of the stage, used to identify the stage within the pipeline.
+    fn name(&self) -> &str;
+
+    /// Description of the stage, shown to the user when the stage starts.
+    fn description(&self) -> String {
+        format!("Executing {}", self.name())
+    }
+
+    /// Kind of files consumed by the stage.
+    fn input(&self) -> Artifact;
+
+    /// Kind of files produced by the stage.
+    fn output(&self) -> Artifact;
+
+    /// Number of cores one job of the stage occupies. The scheduler uses this to limit the number
+    /// of parallel jobs, `jobs` is the total number of available slots.
+    fn weight(&self, _jobs: usize) -> usize {
+        1
+    }
+
+    /// Upper limit of jobs of the stage running at the same time, regardless of the free slots.
+    fn limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the effective parameters of the stage, these are part of the key of the cache.
+    /// The outputs of stages returning None aren't cached.
+    fn parameters(&self, _run: &Run) -> Option<String> {
+        None
+    }
+
+    /// Extensions of the additional files the stage writes next to each output (with the same
+    /// stem), like the hOCR of Tesseract. They are cached together with the outputs.
+    fn sidecars(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Processes one job and returns the paths of the produced files in page order.
+    fn process(&self, run: &Run, job: &Job) -> Result<Vec<PathBuf>, Error>;
+}
+
+/// Stage calling an arbitrary command for each file. The arguments can contain the placeholders
+/// `{input}` and `{output}` which are replaced with the paths of the current job and
+/// `{resolution}` which is replaced with the resolution of the page. This is handy
+/// to add additional tools (like a custom despeckle step) to the pipeline.
+pub struct CommandStage {
+    name: String,
+    program: Executable,
+    args: Vec<String>,
+    input: Artifact,
+    output: Artifact,
+}
+
+impl CommandStage {
+    /// Returns a new command stage with the given name, program and arguments. The stage consumes
+    /// and produces images by default, use [`CommandStage::artifacts`] to change this.
+    pub fn new<S: Into<String>>(name: S, program: S, args: Vec<String>) -> Self {
+        Self {
+            name: name.into(),
+            program: Executable::custom(program),
+            args,
+            input: Artifact::Image,
+            output: Artifact::Image,
+        }
+    }
+
+    /// Sets the kind of files the command consumes and produces.
+    pub fn artifacts(mut self, input: Artifact, output: Artifact) -> Self {
+        self.input = input;
+        self.output = output;
+        self
+    }
+}
+
+impl Stage for CommandStage {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn input(&self) -> Artifact {
+        self.input
+    }
+
+    fn output(&self) -> Artifact {
+        self.output
+    }
+
+    fn parameters(&self, _run: &Run) -> Option<String> {
+        Some(format!(
+            "{} {:?} {}",
+            self.program,
+            self.args,
+            self.output.extension().unwrap_or("image")
+        ))
+    }
+
+    fn process(&self, run: &Run, job: &Job) -> Result<Vec<PathBuf>, Error> {
+        let output = job.output(run, self.output);
+        let mut cmd = self.program.command();
+        for arg in &self.args {
+            cmd.arg(
+                arg.replace("{input}", &job.input.to_string_lossy())
+                    .replace("{output}", &output.to_string_lossy())
+                    .replace("{resolution}", &job.resolution.to_string()),
+            );
+        }
+        debug!("Going to execute {} on {}", self.name, job.input.display());
+        util::run_cmd(cmd, &self.program)?;
+        Ok(vec![output])
+    }
+}
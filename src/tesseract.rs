@@ -1,109 +1,148 @@
-use crate::error::ErrorMessage;
+use crate::binary::Tool;
+use crate::error::Error;
+use crate::jbig2::{self, Coding};
 use crate::run::Run;
+use crate::stage::{Artifact, Job, Stage};
 use crate::util;
 
 use std::path::PathBuf;
-use std::process::Command;
-use std::sync::{Arc, Mutex};
-use std::thread;
 
-use num_cpus;
-
-/// Name of the Tesseract binary.
-const TESSERACT_BINARY: &str = "tesseract";
+/// Number of cores used by one Tesseract process.
+const TESSERACT_WEIGHT: usize = 4;
 
 /// Takes the prepared tiff files and runs the OCR with Tesseract on each file.
-pub fn execute(
-    run: &Run,
-    disable_tesseract: bool,
-    lang: Option<String>,
+pub struct Ocr {
+    /// Language(s) used for the recognition.
+    lang: String,
+    /// Additional options for Tesseract.
     options: Option<String>,
-    threads: Option<String>,
-) -> Result<(), ErrorMessage> {
-    if disable_tesseract {
-        return Ok(());
+    /// Overwrites the number of parallel Tesseract processes.
+    threads: Option<usize>,
+    /// Additional output formats (Tesseract configs like hocr), written next to the PDF.
+    configs: Vec<String>,
+    /// Compresses the image of bilevel pages with JBIG2.
+    jbig2: Option<Coding>,
+}
+
+impl Ocr {
+    /// Returns a new OCR stage.
+    pub fn new<S: Into<String>>(lang: S, options: Option<String>, threads: Option<usize>) -> Self {
+        Self {
+            lang: lang.into(),
+            options,
+            threads,
+            configs: vec![],
+            jbig2: None,
+        }
     }
-    run.log_step("OCR");
-    let mut files: Vec<(PathBuf, PathBuf)> = vec![];
-    for input in run.query_files("c_")? {
-        files.push((
-            input.clone(),
-            run.build_path(format!("d_{}", util::file_name(input)), Some("pdf")),
-        ));
+
+    /// Adds an output format which is written next to the PDF of each page.
+    pub fn config<S: Into<String>>(mut self, config: S) -> Self {
+        self.configs.push(config.into());
+        self
     }
 
-    let files = Arc::new(Mutex::new(files));
-    let lang = Arc::new(lang);
-    let options = Arc::new(options);
-    let mut handles = vec![];
-
-    // Tesseract uses internally four cores per process. Thus starting more processes as (N_CORES/4).ceil()
-    // doesn't make any sense as one Tesseract process running on four cores is way faster than
-    // two processes running on two cores each.
-    let n_threads = match threads {
-        Some(x) => match x.parse::<usize>() {
-            Ok(x) => x,
-            Err(e) => {
-                return Err(ErrorMessage::new(format!(
-                    "Couldn't convert given thread number for Tesseract to number, {}",
-                    e
-                )))
-            }
-        },
-        None => (num_cpus::get() as f64 / 4.0).ceil() as usize,
-    };
-    for _ in 0..n_threads {
-        let files = Arc::clone(&files);
-        let lang = Arc::clone(&lang);
-        let options = Arc::clone(&options);
-        let handle = thread::spawn(move || tesseract_thread(files, lang, options));
-        handles.push(handle);
+    /// Replaces the image of bilevel pages with its JBIG2 encoding.
+    pub fn jbig2(mut self, coding: Coding) -> Self {
+        self.jbig2 = Some(coding);
+        self
     }
+}
 
-    for handle in handles {
-        handle.join().unwrap()?;
+impl Stage for Ocr {
+    fn name(&self) -> &str {
+        "tesseract"
     }
 
-    run.wait();
-    Ok(())
-}
+    fn description(&self) -> String {
+        String::from("OCR")
+    }
 
-/// Tesseract execution thread. Takes one image from the shared vector and process it. When
-/// Tesseract finishes the next image will be pulled from the vector of tuples. The first element in
-/// the tuple is the input path, the second points to the output file using the Tesseract number
-/// format. When the bus is empty, the thread terminates.
-fn tesseract_thread(
-    input: Arc<Mutex<Vec<(PathBuf, PathBuf)>>>,
-    lang: Arc<Option<String>>,
-    options: Arc<Option<String>>,
-) -> Result<(), ErrorMessage> {
-    loop {
-        let mut files = input.lock().unwrap();
-        let file = match files.pop() {
-            Some(x) => x,
-            None => return Ok(()),
-        };
-        drop(files);
-
-        let mut cmd = Command::new(TESSERACT_BINARY);
-        cmd.arg("-l").arg(match *lang {
-            Some(ref x) => x,
-            None => "eng",
-        });
-        cmd.args(match *options {
-            Some(ref x) => x.split(" ").collect::<Vec<&str>>(),
+    fn input(&self) -> Artifact {
+        Artifact::Tiff
+    }
+
+    fn output(&self) -> Artifact {
+        Artifact::Pdf
+    }
+
+    fn parameters(&self, _run: &Run) -> Option<String> {
+        let mut parameters = format!("{} {:?} {:?}", self.lang, self.options, self.configs);
+        if let Some(x) = self.jbig2 {
+            parameters.push_str(&format!(" jbig2 {:?}", x));
+        }
+        Some(parameters)
+    }
+
+    /// Tesseract uses internally four cores per process. Thus starting more processes as
+    /// (N_CORES/4).ceil() doesn't make any sense as one Tesseract process running on four cores
+    /// is way faster than two processes running on two cores each. If the user requested a
+    /// specific number of parallel Tesseract processes the cores are shared among them, their
+    /// number is enforced by the limit of the stage.
+    fn weight(&self, jobs: usize) -> usize {
+        match self.threads {
+            Some(x) => (jobs / x.max(1)).max(1),
+            None => TESSERACT_WEIGHT,
+        }
+    }
+
+    fn limit(&self) -> Option<usize> {
+        self.threads.map(|x| x.max(1))
+    }
+
+    fn sidecars(&self) -> Vec<String> {
+        self.configs.clone()
+    }
+
+    fn process(&self, run: &Run, job: &Job) -> Result<Vec<PathBuf>, Error> {
+        // Tesseract appends the extension by itself.
+        let output = run.prepend_with_temp_folder(&job.stem);
+        let mut cmd = run.command(Tool::Tesseract);
+        cmd.arg("-l").arg(&self.lang);
+        cmd.args(match self.options {
+            Some(ref x) => x.split(' ').collect::<Vec<&str>>(),
             None => vec![],
         });
-        cmd.arg(&file.0);
-        cmd.arg(&file.1);
+        cmd.arg(&job.input);
+        cmd.arg(&output);
         cmd.arg("pdf");
+        cmd.args(&self.configs);
 
-        debug!("Going to execute OCR on {}", &file.0.display());
-        util::run_cmd(cmd, TESSERACT_BINARY)?;
+        debug!("Going to execute OCR on {}", job.input.display());
+        util::run_cmd(cmd, run.executable(Tool::Tesseract))?;
+        let output = job.output(run, Artifact::Pdf);
+        if let Some(x) = self.jbig2 {
+            jbig2::compress(run, &job.input, &output, x)?;
+        }
         debug!(
             "OCR result of {} was written to {}",
-            &file.0.display(),
-            &file.1.display()
+            job.input.display(),
+            output.display()
         );
+        Ok(vec![output])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weight_without_threads() {
+        let ocr = Ocr::new("eng", None, None);
+        assert_eq!(ocr.weight(8), TESSERACT_WEIGHT);
+        assert_eq!(ocr.limit(), None);
+    }
+
+    #[test]
+    fn threads_set_the_number_of_processes() {
+        let ocr = Ocr::new("eng", None, Some(3));
+        assert_eq!(ocr.limit(), Some(3));
+        // Three processes fit into four slots.
+        assert!(4 / ocr.weight(4) >= 3);
+        assert_eq!(ocr.weight(4), 1);
+        assert_eq!(ocr.weight(8), 2);
+        assert_eq!(ocr.weight(2), 1);
+        assert_eq!(Ocr::new("eng", None, Some(0)).limit(), Some(1));
     }
 }
@@ -0,0 +1,139 @@
+/// Tesseract writes the recognized text of each page next to its PDF. These texts are combined
+/// into a single text file with a form feed between the pages, like the sidecar of pdfsandwich,
+/// or copied into a folder with a file per page.
+use crate::error::Error;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Page separator of the combined text file.
+const PAGE_SEPARATOR: char = '\x0c';
+
+/// Writes the text of all pages into one file, the pages are separated by a form feed. Pages
+/// without a text file (like pages taken over untouched) are added as empty pages.
+pub fn join(pages: &[Option<PathBuf>], output: &Path) -> Result<(), Error> {
+    let mut document = String::new();
+    for (n, page) in pages.iter().enumerate() {
+        if n > 0 {
+            document.push(PAGE_SEPARATOR);
+        }
+        if let Some(x) = page {
+            document.push_str(&read(x)?);
+        }
+    }
+    write(output, &document)
+}
+
+/// Writes the text of each page into a file of its own within the given folder.
+pub fn split(pages: &[Option<PathBuf>], folder: &Path) -> Result<(), Error> {
+    if let Err(e) = fs::create_dir_all(folder) {
+        return Err(Error::io(
+            format!("Couldn't create folder {}", folder.display()),
+            e,
+        ));
+    }
+    for (n, page) in pages.iter().enumerate() {
+        let content = match page {
+            Some(x) => read(x)?,
+            None => String::new(),
+        };
+        write(&folder.join(format!("page_{:04}.txt", n + 1)), &content)?;
+    }
+    Ok(())
+}
+
+/// Reads the text of a page without the page separator Tesseract appends.
+fn read(path: &Path) -> Result<String, Error> {
+    match fs::read_to_string(path) {
+        Ok(x) => Ok(String::from(x.strip_suffix(PAGE_SEPARATOR).unwrap_or(&x))),
+        Err(e) => Err(Error::io(
+            format!("Couldn't read text file {}", path.display()),
+            e,
+        )),
+    }
+}
+
+/// Writes the text of one or all pages to a file.
+fn write(path: &Path, content: &str) -> Result<(), Error> {
+    match fs::write(path, content) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::io(
+            format!("Couldn't write text to {}", path.display()),
+            e,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the text files of the pages, None stands for a page without text file.
+    fn pages(folder: &Path, texts: &[Option<&str>]) -> Vec<Option<PathBuf>> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(n, text)| {
+                text.map(|x| {
+                    let path = folder.join(format!("d-{:04}.txt", n));
+                    fs::write(&path, x).unwrap();
+                    path
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn joined_pages() {
+        let folder = tempfile::tempdir().unwrap();
+        let pages = pages(
+            folder.path(),
+            &[
+                Some("first\n\x0c"),
+                None,
+                Some(""),
+                Some("fourth\nline\n"),
+                Some("fifth\n\x0c"),
+            ],
+        );
+        let output = folder.path().join("text.txt");
+        join(&pages, &output).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "first\n\x0c\x0c\x0cfourth\nline\n\x0cfifth\n"
+        );
+
+        join(&[], &output).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "");
+    }
+
+    #[test]
+    fn split_pages() {
+        let folder = tempfile::tempdir().unwrap();
+        let pages = pages(
+            folder.path(),
+            &[Some("first\n\x0c"), None, Some("third\x0c\n")],
+        );
+        let output = folder.path().join("pages");
+        split(&pages, &output).unwrap();
+        let mut names: Vec<String> = fs::read_dir(&output)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["page_0001.txt", "page_0002.txt", "page_0003.txt"]);
+        let read = |name: &str| fs::read_to_string(output.join(name)).unwrap();
+        assert_eq!(read("page_0001.txt"), "first\n");
+        assert_eq!(read("page_0002.txt"), "");
+        // Only a trailing form feed is removed.
+        assert_eq!(read("page_0003.txt"), "third\x0c\n");
+    }
+
+    #[test]
+    fn missing_text_file() {
+        let folder = tempfile::tempdir().unwrap();
+        let pages = vec![Some(folder.path().join("missing.txt"))];
+        let output = folder.path().join("text.txt");
+        assert!(matches!(join(&pages, &output), Err(Error::Io { .. })));
+    }
+}
@@ -1,117 +1,104 @@
-use crate::error::ErrorMessage;
+use crate::binary::Tool;
+use crate::error::Error;
+use crate::pipeline::Layout;
 use crate::run::Run;
+use crate::stage::{Artifact, Job, Stage};
 use crate::util;
 
 use std::path::PathBuf;
-use std::process::Command;
-use std::sync::{Arc, Mutex};
-use std::thread;
 
-use num_cpus;
-
-/// Name of the unpaper binary.
-const UNPAPER_BINARY: &str = "unpaper";
-
-/// Executes the document enhancement application unpaper. To speed up the process, multiple
-/// threads (as many cores as the CPU offer) will be used at the same time. It's possible to use
-/// additional unpaper arguments by submitting them via the options parameter.
-pub fn execute(
-    run: &Run,
-    layout: Option<String>,
-    output_pages: Option<String>,
+/// Executes the document enhancement application unpaper. It's possible to use additional unpaper
+/// arguments by submitting them via the options parameter.
+pub struct Unpaper {
+    /// Layout of the scanned pages.
+    layout: Layout,
+    /// Number of output pages per input page.
+    output_pages: u8,
+    /// Additional options for unpaper.
     options: Option<String>,
+    /// Disables the blackfilter.
     no_blackfilter: bool,
+    /// Disables the grayfilter.
     no_grayfilter: bool,
-) -> Result<(), ErrorMessage> {
-    run.log_step("Enhance with unpaper");
+}
 
-    let mut files: Vec<(PathBuf, PathBuf)> = vec![];
-    for input in run.query_files("a_")? {
-        files.push((
-            input.clone(),
-            run.build_path(format!("b_{}_%05d", util::file_name(input)), None),
-        ));
+impl Unpaper {
+    /// Returns a new unpaper stage.
+    pub fn new(
+        layout: Layout,
+        output_pages: u8,
+        options: Option<String>,
+        no_blackfilter: bool,
+        no_grayfilter: bool,
+    ) -> Self {
+        Self {
+            layout,
+            output_pages,
+            options,
+            no_blackfilter,
+            no_grayfilter,
+        }
     }
+}
 
-    let files_arc = Arc::new(Mutex::new(files));
-    let layout = Arc::new(layout);
-    let output_pages = Arc::new(output_pages);
-    let options = Arc::new(options);
-    let mut handles = vec![];
+impl Stage for Unpaper {
+    fn name(&self) -> &str {
+        "unpaper"
+    }
 
-    for _ in 1..num_cpus::get() {
-        let files_arc = Arc::clone(&files_arc);
-        let layout = Arc::clone(&layout);
-        let output_pages = Arc::clone(&output_pages);
-        let options = Arc::clone(&options);
-        let handle = thread::spawn(move || {
-            unpaper_thread(files_arc, layout, output_pages, options, no_blackfilter, no_grayfilter)
-        });
-        handles.push(handle);
+    fn description(&self) -> String {
+        String::from("Enhance with unpaper")
     }
 
-    for handle in handles {
-        handle.join().unwrap()?;
+    fn input(&self) -> Artifact {
+        Artifact::Image
     }
 
-    run.wait();
-    Ok(())
-}
+    fn output(&self) -> Artifact {
+        Artifact::Image
+    }
 
-/// An unpaper execution thread. Takes one image from the shared vector and process it. When
-/// unpaper finishes the next image will be pulled from the vector of tuples. The first element in
-/// the tuple is the input path, the second points to the output file using the unpaper number
-/// format. When the bus is empty, the thread terminates.
-fn unpaper_thread(
-    input: Arc<Mutex<Vec<(PathBuf, PathBuf)>>>,
-    layout: Arc<Option<String>>,
-    output_pages: Arc<Option<String>>,
-    options: Arc<Option<String>>,
-    no_blackfilter: bool,
-    no_grayfilter: bool,
-) -> Result<(), ErrorMessage> {
-    loop {
-        let mut files = input.lock().unwrap();
-        let file = match files.pop() {
-            Some(x) => x,
-            None => return Ok(()),
-        };
-        drop(files);
+    fn parameters(&self, run: &Run) -> Option<String> {
+        Some(format!(
+            "{} {} {:?} {} {} {}",
+            self.layout,
+            self.output_pages,
+            self.options,
+            self.no_blackfilter,
+            self.no_grayfilter,
+            run.format.extension()
+        ))
+    }
 
-        let mut cmd = Command::new(UNPAPER_BINARY);
-        cmd.args(match *options {
-            Some(ref x) => x.split(" ").collect::<Vec<&str>>(),
+    /// Enhances one image. The output file uses the unpaper number format as unpaper can produce
+    /// two output pages for each input page.
+    fn process(&self, run: &Run, job: &Job) -> Result<Vec<PathBuf>, Error> {
+        let mut cmd = run.command(Tool::Unpaper);
+        cmd.args(match self.options {
+            Some(ref x) => x.split(' ').collect::<Vec<&str>>(),
             None => vec![],
         });
-        match *layout {
-            Some(ref x) => {
-                cmd.arg("--layout").arg(x);
-            }
-            None => {}
-        };
-        match *output_pages {
-            Some(ref x) => {
-                cmd.arg("--output-pages").arg(x);
-            }
-            None => {}
-        };
-        match no_blackfilter {
-            true => cmd.arg("--no-blackfilter"),
-            false => &cmd,
-        };
-        match no_grayfilter {
-            true => cmd.arg("--no-grayfilter"),
-            false => &cmd,
-        };
-        cmd.arg(&file.0);
-        cmd.arg(&file.1);
+        cmd.arg("--layout").arg(self.layout.as_str());
+        cmd.arg("--output-pages").arg(self.output_pages.to_string());
+        if self.no_blackfilter {
+            cmd.arg("--no-blackfilter");
+        }
+        if self.no_grayfilter {
+            cmd.arg("--no-grayfilter");
+        }
+        let output = run.build_path(format!("{}_%05d", job.stem), None);
+        cmd.arg(&job.input);
+        cmd.arg(&output);
 
-        debug!("Going to enhance {} with unpaper", &file.0.display());
-        util::run_cmd(cmd, UNPAPER_BINARY)?;
+        debug!("Going to enhance {} with unpaper", job.input.display());
+        util::run_cmd(cmd, run.executable(Tool::Unpaper))?;
+        let mut outputs = run.query_files(&format!("{}_", job.stem))?;
+        outputs.sort();
         debug!(
             "{} was enhanced and saved as {}",
-            &file.0.display(),
-            &file.1.display()
+            job.input.display(),
+            output.display()
         );
+        Ok(outputs)
     }
 }
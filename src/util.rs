@@ -1,118 +1,120 @@
-use crate::error::ErrorMessage;
+use crate::binary::Executable;
+use crate::error::Error;
 
 use std::io::ErrorKind;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
 
 use regex::Regex;
 
-/// Name of the pdfinfo binary.
-const PDFINFO_BINARY: &str = "pdfinfo";
-
 /// Runs a Command and handles the outcome of it.
-pub fn run_cmd<'a>(mut cmd: Command, cmd_name: &'a str) -> Result<(), ErrorMessage> {
+pub fn run_cmd(cmd: Command, exe: &Executable) -> Result<(), Error> {
+    execute(cmd, exe)?;
+    Ok(())
+}
+
+/// Runs a Command and returns its output on stdout.
+pub fn cmd_output(cmd: Command, exe: &Executable) -> Result<String, Error> {
+    let out = execute(cmd, exe)?;
+    match String::from_utf8(out.stdout) {
+        Ok(x) => Ok(x),
+        Err(_) => Err(Error::parse(format!(
+            "Couldn't convert output of {} to string",
+            exe
+        ))),
+    }
+}
+
+/// Runs a Command and returns its raw output on stdout, used for binary data.
+pub fn cmd_bytes(cmd: Command, exe: &Executable) -> Result<Vec<u8>, Error> {
+    Ok(execute(cmd, exe)?.stdout)
+}
+
+/// Runs a Command and returns its output on stdout and stderr regardless of the exit status. Some
+/// tools report their version on stderr or exit with an error code when doing so. Only a command
+/// which fails without any output is reported as error.
+pub fn probe(mut cmd: Command, exe: &Executable) -> Result<String, Error> {
     match cmd.output() {
-        Ok(x) => match x.status.success() {
-            true => /*debug!(
-                "{}",
-                match String::from_utf8(x.stdout) {
-                    Ok(x) => x,
-                    Err(_) => String::from("<Error converting stdout to string>"),
-                }
-            )*/{},
-            false => {
-                return Err(ErrorMessage::new(format!(
-                    "Execution of {} failed {} {}",
-                    cmd_name,
-                    match String::from_utf8(x.stdout) {
-                        Ok(x) => x,
-                        Err(_) => String::from("<Error converting stdout to string>"),
-                    },
-                    match String::from_utf8(x.stderr) {
-                        Ok(x) => x,
-                        Err(_) => String::from("<Error converting stderr to string>"),
-                    }
-                )))
-            }
-        },
-        Err(e) => {
-            if let ErrorKind::NotFound = e.kind() {
-                return Err(ErrorMessage::new(format!(
-                    "couldn't find the convert binary ({}) on your system",
-                    cmd_name,
-                )));
-            } else {
-                return Err(ErrorMessage::new(format!(
-                    "Failed to call {}, {}",
-                    cmd_name, e
-                )));
+        Ok(x) => {
+            let text = format!(
+                "{}{}",
+                String::from_utf8_lossy(&x.stdout),
+                String::from_utf8_lossy(&x.stderr)
+            );
+            match x.status.success() || !text.trim().is_empty() {
+                true => Ok(text),
+                false => Err(Error::ToolFailed {
+                    tool: String::from(exe.used()),
+                    code: x.status.code(),
+                    stderr: text,
+                }),
             }
         }
+        Err(e) => match e.kind() {
+            ErrorKind::NotFound => Err(exe.not_found().into()),
+            _ => Err(Error::io(format!("Failed to call {}", exe), e)),
+        },
+    }
+}
+
+/// Executes the command, a unsuccessful execution is reported as error.
+fn execute(mut cmd: Command, exe: &Executable) -> Result<Output, Error> {
+    match cmd.output() {
+        Ok(x) => match x.status.success() {
+            true => Ok(x),
+            false => Err(Error::ToolFailed {
+                tool: String::from(exe.used()),
+                code: x.status.code(),
+                stderr: String::from_utf8_lossy(&x.stderr).into_owned(),
+            }),
+        },
+        Err(e) => match e.kind() {
+            ErrorKind::NotFound => Err(exe.not_found().into()),
+            _ => Err(Error::io(format!("Failed to call {}", exe), e)),
+        },
     }
-    Ok(())
 }
 
 /// Returns the filename (without any extension) of a path.
 pub fn file_name(path: PathBuf) -> String {
     let name = path.file_name().unwrap().to_string_lossy();
-    let name_ele = name.split(".").collect::<Vec<&str>>();
+    let name_ele = name.split('.').collect::<Vec<&str>>();
     name_ele.split_first().unwrap().0.to_string()
 }
 
-/// Determines the number of pages a given PDF file contains. Uses pdfinfo.
-pub fn count_pdf_pages(file: PathBuf) -> Result<u64, ErrorMessage> {
-    let mut cmd = Command::new(PDFINFO_BINARY);
+/// Splits the address of a page like `document.pdf[3]` into the path of the document and the
+/// index of the page. Returns None if the path doesn't address a page.
+pub fn split_page(input: &Path) -> Option<(PathBuf, usize)> {
+    let name = input.to_string_lossy();
+    let (document, page) = name.strip_suffix(']')?.rsplit_once('[')?;
+    Some((PathBuf::from(document), page.parse().ok()?))
+}
+
+/// Determines the number of pages a given PDF file contains. Uses pdfinfo. The password is used to
+/// open encrypted files, it's tried as owner and as user password.
+pub fn count_pdf_pages(
+    file: PathBuf,
+    pdfinfo: &Executable,
+    password: Option<&str>,
+) -> Result<u64, Error> {
+    let mut cmd = pdfinfo.command();
+    if let Some(x) = password {
+        cmd.arg("-opw").arg(x).arg("-upw").arg(x);
+    }
     cmd.arg(file);
-    let out = match cmd.output() {
-        Ok(x) => match x.status.success() {
-            true => match String::from_utf8(x.stdout) {
-                Ok(x) => x,
-                Err(_) => return Err(ErrorMessage::new("Couldn't convert stdout to string")),
-            },
-            false => {
-                return Err(ErrorMessage::new(format!(
-                    "Execution of {} failed {}",
-                    PDFINFO_BINARY,
-                    match String::from_utf8(x.stderr) {
-                        Ok(x) => x,
-                        Err(_) => String::from("<Error converting stderr to string>"),
-                    }
-                )))
-            }
-        },
-        Err(e) => {
-            if let ErrorKind::NotFound = e.kind() {
-                return Err(ErrorMessage::new(format!(
-                    "couldn't find the convert binary ({}) on your system",
-                    PDFINFO_BINARY,
-                )));
-            } else {
-                return Err(ErrorMessage::new(format!(
-                    "Failed to call {}, {}",
-                    PDFINFO_BINARY, e
-                )));
-            }
-        }
-    };
+    let out = cmd_output(cmd, pdfinfo)?;
 
-    let re = match Regex::new(r#"Pages:\s*([0-9]*)"#) {
-        Ok(x) => x,
-        Err(e) => {
-            return Err(ErrorMessage::new(format!(
-                "Couldn't compile regular expression, {}",
-                e
-            )))
-        }
-    };
+    let re = Regex::new(r#"Pages:\s*([0-9]+)"#).unwrap();
     match re.captures(&out) {
-        Some(x) => match x.get(1) {
-            Some(x) => Ok(x.as_str().parse::<u64>().unwrap()),
-            None => Err(ErrorMessage::new(format!(
-                "Couldn't find number of pages in pdfinfo output"
+        Some(x) => match x[1].parse::<u64>() {
+            Ok(x) => Ok(x),
+            Err(e) => Err(Error::parse(format!(
+                "Couldn't parse number of pages in pdfinfo output, {}",
+                e
             ))),
         },
-        None => Err(ErrorMessage::new(format!(
-            "Couldn't find number of pages in pdfinfo output"
-        ))),
+        None => Err(Error::parse(
+            "Couldn't find number of pages in pdfinfo output",
+        )),
     }
 }